@@ -10,7 +10,7 @@ use crate::{
     common::{Clock, Kind, NesRegion, Regional, Reset},
     cpu::Irq,
 };
-// use once_cell::sync::Lazy;
+use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
 
 pub mod dmc;
@@ -341,25 +341,28 @@ impl ApuRegisters for Apu {
     }
 }
 
-// impl Audio for Apu {
-//     #[must_use]
-//     fn output(&self) -> f32 {
-//         let pulse1 = self.pulse1.output();
-//         let pulse2 = self.pulse2.output();
-//         let triangle = self.triangle.output();
-//         let noise = self.noise.output();
-//         let dmc = self.dmc.output();
-//         let mut pulse_idx = (pulse1 + pulse2) as usize;
-//         if pulse_idx > PULSE_TABLE.len() {
-//             pulse_idx %= PULSE_TABLE.len();
-//         }
-//         let mut tnd_idx = (3.0f32.mul_add(triangle, 2.0 * noise) + dmc) as usize;
-//         if tnd_idx > TND_TABLE.len() {
-//             tnd_idx %= TND_TABLE.len();
-//         }
-//         PULSE_TABLE[pulse_idx] + TND_TABLE[tnd_idx]
-//     }
-// }
+impl Apu {
+    /// Mixed output of all five channels, using the nonlinear lookup table approximation.
+    ///
+    /// <https://www.nesdev.org/wiki/APU_Mixer>
+    #[must_use]
+    pub fn output(&self) -> f32 {
+        let pulse1 = self.pulse1.output();
+        let pulse2 = self.pulse2.output();
+        let triangle = self.triangle.output();
+        let noise = self.noise.output();
+        let dmc = self.dmc.output();
+        let mut pulse_idx = (pulse1 + pulse2) as usize;
+        if pulse_idx >= PULSE_TABLE.len() {
+            pulse_idx %= PULSE_TABLE.len();
+        }
+        let mut tnd_idx = (3.0f32.mul_add(triangle, 2.0 * noise) + dmc) as usize;
+        if tnd_idx >= TND_TABLE.len() {
+            tnd_idx %= TND_TABLE.len();
+        }
+        PULSE_TABLE[pulse_idx] + TND_TABLE[tnd_idx]
+    }
+}
 
 impl Clock for Apu {
     fn clock(&mut self) -> usize {
@@ -423,20 +426,20 @@ impl std::fmt::Debug for Apu {
     }
 }
 
-// pub(crate) static PULSE_TABLE: Lazy<[f32; 31]> = Lazy::new(|| {
-//     let mut pulse_table = [0.0; 31];
-//     for (i, val) in pulse_table.iter_mut().enumerate().skip(1) {
-//         *val = 95.52 / (8_128.0 / (i as f32) + 100.0);
-//     }
-//     pulse_table
-// });
-// static TND_TABLE: Lazy<[f32; 203]> = Lazy::new(|| {
-//     let mut tnd_table = [0.0; 203];
-//     for (i, val) in tnd_table.iter_mut().enumerate().skip(1) {
-//         *val = 163.67 / (24_329.0 / (i as f32) + 100.0);
-//     }
-//     tnd_table
-// });
+pub(crate) static PULSE_TABLE: Lazy<[f32; 31]> = Lazy::new(|| {
+    let mut pulse_table = [0.0; 31];
+    for (i, val) in pulse_table.iter_mut().enumerate().skip(1) {
+        *val = 95.52 / (8_128.0 / (i as f32) + 100.0);
+    }
+    pulse_table
+});
+static TND_TABLE: Lazy<[f32; 203]> = Lazy::new(|| {
+    let mut tnd_table = [0.0; 203];
+    for (i, val) in tnd_table.iter_mut().enumerate().skip(1) {
+        *val = 163.67 / (24_329.0 / (i as f32) + 100.0);
+    }
+    tnd_table
+});
 
 #[cfg(test)]
 impl Apu {
@@ -5,7 +5,7 @@ use crate::{
     common::{Clock, Kind, NesRegion, Regional, Reset},
     cpu::{Cpu, Irq},
     genie::GenieCode,
-    input::{FourPlayer, Input, InputRegisters, Joypad, Slot, Zapper},
+    input::{FourPlayer, Input, InputRegisters, Joypad, Slot, VsSystem, Zapper},
     mapper::{Mapped, MappedRead, MappedWrite, Mapper, MemMap},
     mem::{Access, Mem, RamState},
     ppu::{Ppu, PpuRegisters},
@@ -136,6 +136,18 @@ impl CpuBus {
         self.input.joypad_mut(slot)
     }
 
+    #[inline]
+    #[must_use]
+    pub const fn vs_system(&self) -> Option<&VsSystem> {
+        self.input.vs_system()
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn vs_system_mut(&mut self) -> Option<&mut VsSystem> {
+        self.input.vs_system_mut()
+    }
+
     #[inline]
     pub fn connect_zapper(&mut self, enabled: bool) {
         self.input.connect_zapper(enabled);
@@ -154,10 +166,13 @@ impl CpuBus {
     #[inline]
     pub fn load_cart(&mut self, cart: Cart) {
         // Start with ~20ms of audio capacity
+        self.audio_samples.clear();
         self.audio_samples
-            .resize((Cpu::region_clock_rate(cart.region()) * 0.02) as usize, 0.0);
+            .reserve((Cpu::region_clock_rate(cart.region()) * 0.02) as usize);
         self.battery_backed = cart.battery_backed();
         self.set_region(cart.region());
+        self.ppu.set_variant(cart.ppu_variant());
+        self.input.set_vs_system(cart.is_vs_system());
         self.load_prg_rom(cart.prg_rom);
         self.load_prg_ram(cart.prg_ram);
         self.ppu.load_chr_rom(cart.chr_rom);
@@ -225,10 +240,10 @@ impl CpuBus {
             .map_or(val, |genie_code| genie_code.read(val))
     }
 
-    // #[inline]
-    // fn mix_audio(&mut self, sample1: f32, sample2: f32) {
-    //     self.audio_samples.push(sample1 + sample2);
-    // }
+    #[inline]
+    fn mix_audio(&mut self, sample1: f32, sample2: f32) {
+        self.audio_samples.push(sample1 + sample2);
+    }
 
     #[inline]
     #[must_use]
@@ -342,13 +357,16 @@ impl Clock for CpuBus {
         self.mapper_mut().clock();
         self.input.clock();
 
-        // let apu_output = self.apu.output();
-        // let mapper_output = match self.mapper() {
-        //     Mapper::Exrom(ref exrom) => exrom.output(),
-        //     Mapper::Vrc6(ref vrc6) => vrc6.output(),
-        //     _ => 0.0,
-        // };
-        // self.mix_audio(apu_output, mapper_output);
+        let apu_output = self.apu.output();
+        let mapper_output = match self.mapper() {
+            Mapper::Exrom(ref exrom) => exrom.output(),
+            Mapper::Vrc6(ref vrc6) => vrc6.output(),
+            Mapper::Vrc7(ref vrc7) => vrc7.output(),
+            Mapper::Fds(ref fds) => fds.output(),
+            Mapper::Fme7(ref fme7) => fme7.output(),
+            _ => 0.0,
+        };
+        self.mix_audio(apu_output, mapper_output);
 
         1
     }
@@ -415,6 +433,11 @@ impl Mem for CpuBus {
     fn write(&mut self, addr: u16, val: u8, _access: Access) {
         match addr {
             0x0000..=0x07FF => self.wram[addr as usize] = val,
+            0x4020 if self.input.vs_system().is_some() => {
+                if let Some(vs) = self.input.vs_system_mut() {
+                    vs.write_coin_counter(val);
+                }
+            }
             0x4020..=0xFFFF => {
                 let prg_ram_enabled = !self.prg_ram.is_empty() && !self.prg_ram_protect;
                 match self.mapper_mut().map_write(addr, val) {
@@ -424,6 +447,9 @@ impl Mem for CpuBus {
                 }
                 self.ppu.update_mirroring();
             }
+            // The 2C05 swaps PPUCTRL and PPUMASK
+            0x2000 if self.ppu.variant().swaps_ctrl_mask() => self.ppu.write_mask(val),
+            0x2001 if self.ppu.variant().swaps_ctrl_mask() => self.ppu.write_ctrl(val),
             0x2000 => self.ppu.write_ctrl(val),
             0x2001 => self.ppu.write_mask(val),
             0x2003 => self.ppu.write_oamaddr(val),
@@ -516,7 +542,10 @@ impl std::fmt::Debug for CpuBus {
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::cart::Cart;
+    use crate::{
+        cart::{fds::DiskImage, Cart},
+        mapper::{self, Fds},
+    };
 
     #[test]
     fn load_cart_values() {
@@ -634,6 +663,90 @@ mod test {
         assert_eq!(bus.apu.cycle(), 1, "apu clock");
     }
 
+    #[test]
+    fn mix_vrc7_audio() {
+        let mut bus = CpuBus::default();
+        bus.load_cart(mapper::tests::cart(85, 2, 0x8000, 0x2000));
+
+        for _ in 0..0x1000 {
+            bus.clock();
+        }
+        assert!(
+            bus.audio_samples().iter().all(|&sample| sample == 0.0),
+            "silent before key-on"
+        );
+
+        // Flute at ~440Hz on channel 2, full volume
+        bus.clear_audio_samples();
+        for (select, data) in [(0x12, 0x20), (0x32, 0x40), (0x22, 0x19)] {
+            bus.write(0x9010, select, Access::Write);
+            bus.write(0x9030, data, Access::Write);
+        }
+        for _ in 0..0x10000 {
+            bus.clock();
+        }
+        assert_eq!(bus.audio_samples().len(), 0x10000, "one sample per cycle");
+        assert!(
+            bus.audio_samples().iter().any(|sample| sample.abs() > 0.01),
+            "audible after key-on"
+        );
+    }
+
+    #[test]
+    fn mix_sunsoft_5b_audio() {
+        let mut bus = CpuBus::default();
+        bus.load_cart(mapper::tests::cart(69, 0, 0x8000, 0x2000));
+
+        // Channel B tone at full volume
+        for (select, data) in [(0x02, 0x10), (0x07, 0x3D), (0x09, 0x0F)] {
+            bus.write(0xC000, select, Access::Write);
+            bus.write(0xE000, data, Access::Write);
+        }
+        for _ in 0..0x1000 {
+            bus.clock();
+        }
+        let (min, max) = bus
+            .audio_samples()
+            .iter()
+            .fold((f32::MAX, f32::MIN), |(min, max), &sample| {
+                (min.min(sample), max.max(sample))
+            });
+        assert!(max - min > 0.3, "square wave audible: {min}..{max}");
+    }
+
+    #[test]
+    fn mix_fds_audio() {
+        let mut bus = CpuBus::default();
+        let mut cart = Cart::empty();
+        let mut side = b"\x01*NINTENDO-HVC*".to_vec();
+        side.resize(56, 0x00);
+        let image = DiskImage::load(&side).expect("valid image");
+        cart.mapper = Fds::load(&mut cart, &image);
+        bus.load_cart(cart);
+
+        // Square wave at full volume
+        bus.write(0x4023, 0x02, Access::Write);
+        bus.write(0x4089, 0x80, Access::Write);
+        for addr in 0x4060..=0x407F {
+            bus.write(addr, 0x3F, Access::Write);
+        }
+        for (addr, val) in [
+            (0x4089, 0x00),
+            (0x4080, 0xA0),
+            (0x4082, 0x00),
+            (0x4083, 0x08),
+        ] {
+            bus.write(addr, val, Access::Write);
+        }
+        for _ in 0..0x1000 {
+            bus.clock();
+        }
+        assert!(
+            bus.audio_samples().iter().any(|sample| sample.abs() > 0.5),
+            "wave audible"
+        );
+    }
+
     #[test]
     fn read_write_ram() {
         let mut bus = CpuBus::default();
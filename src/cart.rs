@@ -1,37 +1,47 @@
 use crate::{
     common::{NesRegion, Regional},
     mapper::{
-        m024_m026_vrc6::Vrc6Revision, Axrom, Bf909x, Cnrom, Exrom, Gxrom, Mapper, Mmc1Revision,
-        Nrom, Pxrom, Sxrom, Txrom, Uxrom, Vrc6,
+        m024_m026_vrc6::Vrc6Revision, Axrom, Bf909x, Cnrom, Exrom, Fds, Fme7, Fxrom, Gxrom,
+        MappedRead, Mapper, MemMap, Mmc1Revision, Nrom, Pxrom, Sxrom, Txrom, Uxrom, Vrc2_4, Vrc6,
+        Vrc7, Vs,
     },
     mem::RamState,
-    ppu::Mirroring,
+    ppu::{variant::PpuVariant, Mirroring},
     NesResult,
 };
-use anyhow::{anyhow, bail, Context};
-#[cfg(not(target_arch = "wasm32"))]
+use anyhow::{bail, Context};
+use archive::Archive;
+use fds::DiskImage;
+use game_db::{DbOverride, GameDb, GameInfo};
+use hash::RomHashes;
+use playchoice::PlayChoice;
 use std::{
-    collections::hash_map::DefaultHasher,
-    hash::{Hash, Hasher},
-};
-use std::{
-    fs::File,
+    fs::{self, File},
     io::{BufReader, Read},
     path::Path,
 };
+use unif::{Unif, UnifMirroring};
+
+pub mod archive;
+pub mod fds;
+pub mod game_db;
+pub mod hash;
+pub mod patch;
+pub mod playchoice;
+pub mod rom_db;
+pub mod unif;
 
 const PRG_ROM_BANK_SIZE: usize = 0x4000;
 const CHR_ROM_BANK_SIZE: usize = 0x2000;
-
-#[cfg(not(target_arch = "wasm32"))]
-const GAME_DB: &[u8] = include_bytes!("../config/game_database.txt");
+const TRAINER_SIZE: usize = 512;
+const TRAINER_PRG_RAM_SIZE: usize = 0x2000;
 
 /// An NES cartridge.
 #[derive(Default, Clone)]
 #[must_use]
 pub struct Cart {
     name: String,
-    header: NesHeader,
+    pub(crate) header: NesHeader,
     region: NesRegion,
     ram_state: RamState,
     pub(crate) mapper: Mapper,
@@ -40,6 +50,9 @@ pub struct Cart {
     pub(crate) ex_ram: Vec<u8>,  // Internal Extra RAM
     pub(crate) prg_rom: Vec<u8>, // Program ROM
     pub(crate) prg_ram: Vec<u8>, // Program RAM
+    playchoice: Option<PlayChoice>,
+    misc_rom: Vec<u8>,
+    db_overrides: Vec<DbOverride>,
 }
 
 impl Cart {
@@ -55,12 +68,16 @@ impl Cart {
             ex_ram: vec![],
             prg_rom: vec![0x00; PRG_ROM_BANK_SIZE],
             prg_ram: vec![],
+            playchoice: None,
+            misc_rom: vec![],
+            db_overrides: vec![],
         };
         empty.mapper = Nrom::load(&mut empty);
         empty
     }
 
-    /// Load `Cart` from a ROM path.
+    /// Load `Cart` from a ROM path. Gzip, zip and 7z archives are decompressed, loading the first
+    /// ROM entry.
     ///
     /// # Errors
     ///
@@ -68,70 +85,109 @@ impl Cart {
     /// the header, then an error is returned.
     pub fn from_path<P: AsRef<Path>>(path: P, ram_state: RamState) -> NesResult<Self> {
         let path = path.as_ref();
-        let mut rom = BufReader::new(
-            File::open(path).with_context(|| format!("failed to open rom {path:?}"))?,
-        );
-        Self::from_rom(&path.to_string_lossy(), &mut rom, ram_state)
+        let mut data = fs::read(path).with_context(|| format!("failed to open rom {path:?}"))?;
+        if Archive::is_archive(&data) {
+            let (entry, rom) = Archive::new(data)?.read_rom()?;
+            log::info!("loading {entry:?} from archive {path:?}");
+            data = rom;
+        }
+        Self::from_rom(path.to_string_lossy(), &mut data.as_slice(), ram_state)
+    }
+
+    /// Load `Cart` from `iNES`, `NES 2.0` or UNIF ROM data.
+    ///
+    /// # Errors
+    ///
+    /// If the NES header is invalid, or the ROM data does not match the header, then an error is
+    /// returned.
+    pub fn from_rom<S, F>(name: S, rom_data: &mut F, ram_state: RamState) -> NesResult<Self>
+    where
+        S: ToString,
+        F: Read,
+    {
+        Self::from_rom_with_db(name, rom_data, ram_state, None)
     }
 
-    /// Load `Cart` from ROM data.
+    /// Load `Cart` from `iNES`, `NES 2.0` or UNIF ROM data, correcting `iNES` headers with
+    /// records from `game_db` before falling back to the builtin game database. Corrected fields
+    /// are returned by `db_overrides`.
     ///
     /// # Errors
     ///
     /// If the NES header is invalid, or the ROM data does not match the header, then an error is
     /// returned.
-    pub fn from_rom<S, F>(name: S, mut rom_data: &mut F, ram_state: RamState) -> NesResult<Self>
+    pub fn from_rom_with_db<S, F>(
+        name: S,
+        rom_data: &mut F,
+        ram_state: RamState,
+        game_db: Option<&GameDb>,
+    ) -> NesResult<Self>
     where
         S: ToString,
         F: Read,
     {
         let name = name.to_string();
-        let header = NesHeader::load(&mut rom_data)?;
-
-        let mut prg_rom = vec![0x00; (header.prg_rom_banks as usize) * PRG_ROM_BANK_SIZE];
-        rom_data.read_exact(&mut prg_rom).with_context(|| {
-            let bytes_rem = rom_data
-                .read_to_end(&mut prg_rom)
-                .map_or_else(|_| "unknown".to_string(), |rem| rem.to_string());
-            format!(
-                "invalid rom header '{}'. prg-rom banks: {}. bytes remaining: {}",
-                name, header.prg_rom_banks, bytes_rem
+        let mut magic = [0x00; 4];
+        rom_data
+            .read_exact(&mut magic)
+            .with_context(|| format!("failed to read rom header '{name}'"))?;
+        let mut rom_data = magic.as_slice().chain(rom_data);
+        if Unif::is_unif(&magic) {
+            let unif = Unif::load(&mut rom_data)?;
+            return Self::from_unif(name, unif, ram_state);
+        }
+        let mut header = NesHeader::load(&mut rom_data)?;
+
+        let trainer = if header.has_trainer() {
+            Some(
+                Self::read_rom(&mut rom_data, TRAINER_SIZE)
+                    .with_context(|| format!("invalid rom header '{name}'. trainer"))?,
             )
-        })?;
+        } else {
+            None
+        };
+
+        let prg_rom = Self::read_rom(&mut rom_data, header.prg_rom_size())
+            .with_context(|| format!("invalid rom header '{name}'. prg-rom"))?;
+
+        let chr_rom = Self::read_rom(&mut rom_data, header.chr_rom_size())
+            .with_context(|| format!("invalid rom header '{name}'. chr-rom"))?;
+
+        let game = Self::lookup_game(game_db, &prg_rom, &chr_rom);
+        let db_overrides = match game {
+            Some(game) if header.version == 1 => header.apply_game_info(game),
+            _ => vec![],
+        };
+        for db_override in &db_overrides {
+            log::info!("game database override: {db_override:?}");
+        }
 
-        let prg_ram_size = Self::calculate_ram_size(header.prg_ram_shift).context("prg_ram")?;
-        let mut prg_ram = vec![0x00; prg_ram_size];
+        let mut prg_ram = vec![0x00; header.prg_ram_size() + header.prg_nvram_size()];
         RamState::fill(&mut prg_ram, ram_state);
 
-        let mut chr_rom = vec![0x00; (header.chr_rom_banks as usize) * CHR_ROM_BANK_SIZE];
-        if header.chr_rom_banks > 0 {
-            rom_data.read_exact(&mut chr_rom).with_context(|| {
-                let bytes_rem = rom_data
-                    .read_to_end(&mut chr_rom)
-                    .map_or_else(|_| "unknown".to_string(), |rem| rem.to_string());
-                format!(
-                    "invalid rom header \"{}\". chr-rom banks: {}. bytes remaining: {}",
-                    name, header.chr_rom_banks, bytes_rem,
-                )
-            })?;
+        let playchoice = if header.is_playchoice() {
+            PlayChoice::load(&mut rom_data)?
+        } else {
+            None
+        };
+
+        let mut misc_rom = vec![];
+        if header.misc_roms > 0 {
+            rom_data
+                .read_to_end(&mut misc_rom)
+                .with_context(|| format!("failed to read misc rom '{name}'"))?;
         }
 
         let mut chr_ram = vec![];
         if chr_rom.is_empty() {
-            let chr_ram_size = Self::calculate_ram_size(header.chr_ram_shift).context("chr_ram")?;
-            chr_ram.resize(chr_ram_size, 0x00);
+            chr_ram.resize(header.chr_ram_size() + header.chr_nvram_size(), 0x00);
             RamState::fill(&mut chr_ram, ram_state);
         }
 
-        #[cfg(not(target_arch = "wasm32"))]
-        let region = {
-            let mut hasher = DefaultHasher::new();
-            prg_rom.hash(&mut hasher);
-            let hash = hasher.finish();
-            Self::lookup_region(hash)
-        };
-        #[cfg(target_arch = "wasm32")]
-        let region = NesRegion::default();
+        let region = header
+            .region()
+            .or_else(|| game.and_then(|game| game.region))
+            .unwrap_or_default();
 
         let mut cart = Self {
             name,
@@ -144,24 +200,132 @@ impl Cart {
             ex_ram: vec![],
             prg_rom,
             prg_ram,
+            playchoice,
+            misc_rom,
+            db_overrides,
+        };
+        if trainer.is_some() && cart.prg_ram.len() < TRAINER_PRG_RAM_SIZE {
+            // Mappers only map PRG-RAM they're given, so allocate the bank the trainer loads into
+            cart.add_prg_ram(TRAINER_PRG_RAM_SIZE);
+        }
+        cart.load_mapper()?;
+        if let Some(trainer) = trainer {
+            cart.load_trainer(&trainer)?;
+        }
+        if cart.header.is_vs_dual_system() {
+            log::warn!("Vs. DualSystem is not supported, only the main console will be emulated");
+        }
+
+        log::info!("Loaded `{}`", cart);
+        log::debug!("{:?}", cart);
+        Ok(cart)
+    }
+
+    /// Load `Cart` from a UNIF image, translating the board name to a supported mapper.
+    ///
+    /// # Errors
+    ///
+    /// If the board is not implemented, then an error is returned.
+    pub fn from_unif<S: ToString>(name: S, unif: Unif, ram_state: RamState) -> NesResult<Self> {
+        let name = name.to_string();
+        let Some((mapper_num, submapper_num)) = unif.mapper_num() else {
+            bail!("unimplemented unif board: {}", unif.board());
         };
-        cart.mapper = match cart.header.mapper_num {
-            0 => Nrom::load(&mut cart),
-            1 => Sxrom::load(&mut cart, Mmc1Revision::BC),
-            2 => Uxrom::load(&mut cart),
-            3 => Cnrom::load(&mut cart),
-            4 => Txrom::load(&mut cart),
-            5 => Exrom::load(&mut cart),
-            7 => Axrom::load(&mut cart),
-            9 => Pxrom::load(&mut cart),
-            24 => Vrc6::load(&mut cart, Vrc6Revision::A),
-            26 => Vrc6::load(&mut cart, Vrc6Revision::B),
-            66 => Gxrom::load(&mut cart),
-            71 => Bf909x::load(&mut cart),
-            155 => Sxrom::load(&mut cart, Mmc1Revision::A),
-            _ => bail!("unimplemented mapper: {}", cart.header.mapper_num),
+        if let Some(unif_name) = unif.name() {
+            log::info!("UNIF name: {unif_name}, board: {}", unif.board());
+        }
+
+        let mut flags = 0x00;
+        match unif.mirroring() {
+            Some(UnifMirroring::Vertical) => flags |= 0x01,
+            Some(UnifMirroring::FourScreen) => flags |= 0x08,
+            _ => (),
+        }
+        if unif.battery_backed() {
+            flags |= 0x02;
+        }
+        let header = NesHeader {
+            version: 1,
+            mapper_num,
+            submapper_num,
+            flags,
+            prg_rom_banks: unif.prg_rom().len().div_ceil(PRG_ROM_BANK_SIZE) as u16,
+            chr_rom_banks: unif.chr_rom().len().div_ceil(CHR_ROM_BANK_SIZE) as u16,
+            ..NesHeader::default()
         };
 
+        let region = unif
+            .region()
+            .or_else(|| {
+                Self::lookup_game(None, unif.prg_rom(), unif.chr_rom()).and_then(|game| game.region)
+            })
+            .unwrap_or_default();
+
+        let mut cart = Self {
+            name,
+            header,
+            region,
+            ram_state,
+            mapper: Mapper::none(),
+            chr_rom: unif.chr_rom().to_vec(),
+            chr_ram: vec![],
+            ex_ram: vec![],
+            prg_rom: unif.prg_rom().to_vec(),
+            prg_ram: vec![],
+            playchoice: None,
+            misc_rom: vec![],
+            db_overrides: vec![],
+        };
+        cart.load_mapper()?;
+
+        log::info!("Loaded `{}`", cart);
+        log::debug!("{:?}", cart);
+        Ok(cart)
+    }
+
+    /// Load `Cart` from a Famicom Disk System image, with or without an `fwNES` header, using the
+    /// 8K `disksys.rom` BIOS.
+    ///
+    /// # Errors
+    ///
+    /// If the disk image or BIOS is invalid, then an error is returned.
+    pub fn from_fds<S: ToString>(
+        name: S,
+        disk: &[u8],
+        bios: &[u8],
+        ram_state: RamState,
+    ) -> NesResult<Self> {
+        const BIOS_SIZE: usize = 8 * 1024;
+
+        let image = DiskImage::load(disk)?;
+        if bios.len() != BIOS_SIZE {
+            bail!(
+                "invalid fds bios. expected {} bytes, found {}",
+                BIOS_SIZE,
+                bios.len()
+            );
+        }
+        let mut cart = Self {
+            name: name.to_string(),
+            header: NesHeader {
+                mapper_num: 20,
+                ..NesHeader::default()
+            },
+            // The FDS was only released in Japan
+            region: NesRegion::Ntsc,
+            ram_state,
+            mapper: Mapper::none(),
+            chr_rom: vec![],
+            chr_ram: vec![],
+            ex_ram: vec![],
+            prg_rom: bios.to_vec(),
+            prg_ram: vec![],
+            playchoice: None,
+            misc_rom: vec![],
+            db_overrides: vec![],
+        };
+        cart.mapper = Fds::load(&mut cart, &image);
+
         log::info!("Loaded `{}`", cart);
         log::debug!("{:?}", cart);
         Ok(cart)
@@ -197,6 +361,17 @@ impl Cart {
         &self.prg_ram
     }
 
+    /// Returns CRC32, MD5 and SHA-1 checksums of PRG-ROM.
+    pub fn prg_hashes(&self) -> RomHashes {
+        RomHashes::new(&self.prg_rom)
+    }
+
+    /// Returns CRC32, MD5 and SHA-1 checksums of PRG-ROM followed by CHR-ROM, matching headerless
+    /// No-Intro and NesCartDB entries.
+    pub fn rom_hashes(&self) -> RomHashes {
+        RomHashes::from_chunks(&[&self.prg_rom, &self.chr_rom])
+    }
+
     #[inline]
     #[must_use]
     pub fn has_chr(&self) -> bool {
@@ -236,16 +411,14 @@ impl Cart {
 
     /// Returns hardware configured `Mirroring`.
     #[inline]
-    pub fn mirroring(&self) -> Mirroring {
-        if self.header.flags & 0x08 == 0x08 {
-            Mirroring::FourScreen
-        } else {
-            match self.header.flags & 0x01 {
-                0 => Mirroring::Horizontal,
-                1 => Mirroring::Vertical,
-                _ => unreachable!("impossible mirroring"),
-            }
-        }
+    pub const fn mirroring(&self) -> Mirroring {
+        self.header.mirroring()
+    }
+
+    /// Returns the header fields corrected by the game database when loaded.
+    #[inline]
+    pub fn db_overrides(&self) -> &[DbOverride] {
+        &self.db_overrides
     }
 
     /// Returns the Mapper number for this Cart.
@@ -269,6 +442,59 @@ impl Cart {
         self.header.mapper_board()
     }
 
+    /// Returns whether this Cart is for the Vs. System arcade hardware.
+    #[inline]
+    #[must_use]
+    pub const fn is_vs_system(&self) -> bool {
+        self.header.is_vs_system()
+    }
+
+    /// Returns the PlayChoice-10 INST-ROM and PROM data, if present.
+    #[inline]
+    #[must_use]
+    pub const fn playchoice(&self) -> Option<&PlayChoice> {
+        self.playchoice.as_ref()
+    }
+
+    /// Returns NES 2.0 miscellaneous ROM data following CHR-ROM, used by some boards for
+    /// additional chips.
+    #[inline]
+    #[must_use]
+    pub fn misc_rom(&self) -> &[u8] {
+        &self.misc_rom
+    }
+
+    /// Returns the `PpuVariant` this Cart was designed for.
+    #[inline]
+    pub const fn ppu_variant(&self) -> PpuVariant {
+        self.header.ppu_variant()
+    }
+
+    pub(crate) fn load_mapper(&mut self) -> NesResult<()> {
+        self.mapper = match self.header.mapper_num {
+            0 => Nrom::load(self),
+            1 => Sxrom::load(self, Mmc1Revision::BC),
+            2 => Uxrom::load(self),
+            3 => Cnrom::load(self),
+            4 => Txrom::load(self),
+            5 => Exrom::load(self),
+            7 => Axrom::load(self),
+            9 => Pxrom::load(self),
+            10 => Fxrom::load(self),
+            21 | 22 | 23 | 25 => Vrc2_4::load(self),
+            24 => Vrc6::load(self, Vrc6Revision::A),
+            26 => Vrc6::load(self, Vrc6Revision::B),
+            66 => Gxrom::load(self),
+            69 => Fme7::load(self),
+            71 => Bf909x::load(self),
+            85 => Vrc7::load(self),
+            99 => Vs::load(self),
+            155 => Sxrom::load(self, Mmc1Revision::A),
+            _ => bail!("unimplemented mapper: {}", self.header.mapper_num),
+        };
+        Ok(())
+    }
+
     /// Allows mappers to add PRG-RAM.
     pub(crate) fn add_prg_ram(&mut self, capacity: usize) {
         self.prg_ram.resize(capacity, 0x00);
@@ -287,36 +513,40 @@ impl Cart {
         RamState::fill(&mut self.ex_ram, self.ram_state);
     }
 
-    fn calculate_ram_size(value: u8) -> NesResult<usize> {
-        if value > 0 {
-            64usize
-                .checked_shl(value.into())
-                .ok_or_else(|| anyhow!("invalid header ram size: ${:02X}", value))
-        } else {
-            Ok(0)
-        }
-    }
-
-    #[cfg(not(target_arch = "wasm32"))]
-    fn lookup_region(lookup_hash: u64) -> NesRegion {
-        use std::io::BufRead;
-
-        let db = BufReader::new(GAME_DB);
-        let lines: Vec<String> = db.lines().map_while(Result::ok).collect();
-        if let Ok(line) = lines.binary_search_by(|line| {
-            let hash = line
-                .split(',')
-                .next()
-                .map(|hash| hash.parse::<u64>().unwrap_or_default())
-                .unwrap_or_default();
-            hash.cmp(&lookup_hash)
-        }) {
-            let mut fields = lines[line].split(',').skip(1);
-            if let Some(region) = fields.next() {
-                return NesRegion::try_from(region).unwrap_or_default();
+    // Trainers are loaded into PRG-RAM at $7000-$71FF, where the cartridge would map it.
+    fn load_trainer(&mut self, trainer: &[u8]) -> NesResult<()> {
+        const TRAINER_ADDR: u16 = 0x7000;
+        match self.mapper.map_peek(TRAINER_ADDR) {
+            MappedRead::PrgRam(offset) if offset + TRAINER_SIZE <= self.prg_ram.len() => {
+                self.prg_ram[offset..offset + TRAINER_SIZE].copy_from_slice(trainer);
+                Ok(())
             }
+            _ => bail!(
+                "trainer requires prg-ram mapped at $7000 on power-up, which `{}` does not provide",
+                self.mapper_board()
+            ),
         }
-        NesRegion::default()
+    }
+
+    fn read_rom<F: Read>(rom_data: &mut F, size: usize) -> NesResult<Vec<u8>> {
+        let mut rom = vec![];
+        rom_data.take(size as u64).read_to_end(&mut rom)?;
+        if rom.len() != size {
+            bail!("expected {} bytes, found {}", size, rom.len());
+        }
+        Ok(rom)
+    }
+
+    // Game database record for the ROM, preferring `game_db` over the builtin database.
+    fn lookup_game<'a>(
+        game_db: Option<&'a GameDb>,
+        prg_rom: &[u8],
+        chr_rom: &[u8],
+    ) -> Option<&'a GameInfo> {
+        let hashes = RomHashes::from_chunks(&[prg_rom, chr_rom]);
+        game_db
+            .and_then(|db| db.lookup(&hashes))
+            .or_else(|| GameDb::builtin().lookup(&hashes))
     }
 }
 
@@ -364,6 +594,8 @@ impl std::fmt::Debug for Cart {
             .field("ex_ram_len", &self.ex_ram.len())
             .field("prg_rom_len", &self.prg_rom.len())
             .field("prg_ram_len", &self.prg_ram.len())
+            .field("playchoice", &self.playchoice)
+            .field("db_overrides", &self.db_overrides)
             .finish()
     }
 }
@@ -376,19 +608,24 @@ impl std::fmt::Debug for Cart {
 #[derive(Default, Copy, Clone, PartialEq, Eq)]
 #[must_use]
 pub struct NesHeader {
-    pub version: u8,        // 1 for iNES or 2 for NES 2.0
-    pub mapper_num: u16,    // The primary mapper number
-    pub submapper_num: u8,  // NES 2.0 https://wiki.nesdev.com/w/index.php/NES_2.0_submappers
-    pub flags: u8,          // Mirroring, Battery, Trainer, VS Unisystem, Playchoice-10, NES 2.0
-    pub prg_rom_banks: u16, // Number of 16KB PRG-ROM banks (Program ROM)
-    pub chr_rom_banks: u16, // Number of 8KB CHR-ROM banks (Character ROM)
-    pub prg_ram_shift: u8,  // NES 2.0 PRG-RAM
-    pub chr_ram_shift: u8,  // NES 2.0 CHR-RAM
-    pub tv_mode: u8,        // NES 2.0 NTSC/PAL indicator
-    pub vs_data: u8,        // NES 2.0 VS System data
+    pub version: u8,          // 1 for iNES or 2 for NES 2.0
+    pub mapper_num: u16,      // The primary mapper number
+    pub submapper_num: u8,    // NES 2.0 https://wiki.nesdev.com/w/index.php/NES_2.0_submappers
+    pub flags: u8,            // Mirroring, Battery, Trainer, VS Unisystem, Playchoice-10, NES 2.0
+    pub prg_rom_banks: u16,   // Number of 16KB PRG-ROM banks, or NES 2.0 exponent-multiplier
+    pub chr_rom_banks: u16,   // Number of 8KB CHR-ROM banks, or NES 2.0 exponent-multiplier
+    pub prg_ram_shift: u8,    // NES 2.0 PRG-RAM (D0..D3) and PRG-NVRAM (D4..D7)
+    pub chr_ram_shift: u8,    // NES 2.0 CHR-RAM (D0..D3) and CHR-NVRAM (D4..D7)
+    pub tv_mode: u8,          // NES 2.0 CPU/PPU timing
+    pub vs_data: u8,          // NES 2.0 Vs. System PPU/hardware or extended console type
+    pub misc_roms: u8,        // NES 2.0 number of miscellaneous ROMs
+    pub expansion_device: u8, // NES 2.0 default expansion device
 }
 
 impl NesHeader {
+    pub const SIZE: usize = 16;
+    const MAGIC: &'static [u8] = b"NES\x1a";
+
     /// Load `NesHeader` from a ROM path.
     ///
     /// # Errors
@@ -403,22 +640,22 @@ impl NesHeader {
         Self::load(&mut rom)
     }
 
-    /// Load `NesHeader` from ROM data.
+    /// Load `NesHeader` from ROM data. Junk data left in old dumps is repaired, with each fix
+    /// logged as a warning.
     ///
     /// # Errors
     ///
     /// If the NES header is invalid, then an error is returned.
     pub fn load<F: Read>(rom_data: &mut F) -> NesResult<Self> {
-        let mut header = [0u8; 16];
+        let mut header = [0u8; Self::SIZE];
         rom_data.read_exact(&mut header)?;
 
         // Header checks
-        if header[0..4] != *b"NES\x1a" {
+        if header[0..4] != *Self::MAGIC {
             bail!("nes header signature not found");
-        } else if (header[7] & 0x0C) == 0x04 {
-            bail!("header is corrupted by `DiskDude!`. repair and try again");
-        } else if (header[7] & 0x0C) == 0x0C {
-            bail!("unrecognized header format. repair and try again");
+        }
+        for fix in Self::repair(&mut header) {
+            log::warn!("repaired rom header: {fix}");
         }
 
         let mut prg_rom_banks = u16::from(header[4]);
@@ -435,6 +672,8 @@ impl NesHeader {
         let mut chr_ram_shift = 0;
         let mut tv_mode = 0;
         let mut vs_data = 0;
+        let mut misc_roms = 0;
+        let mut expansion_device = 0;
         // If D2..D3 of flag 7 == 2
         if header[7] & 0x0C == 0x08 {
             version = 2;
@@ -448,34 +687,18 @@ impl NesHeader {
             chr_rom_banks |= u16::from(header[9] & 0xF0) << 4;
             prg_ram_shift = header[10];
             chr_ram_shift = header[11];
-            tv_mode = header[12];
+            tv_mode = header[12] & 0x03;
             vs_data = header[13];
+            misc_roms = header[14] & 0x03;
+            expansion_device = header[15] & 0x3F;
 
             if prg_ram_shift & 0x0F == 0x0F || prg_ram_shift & 0xF0 == 0xF0 {
                 bail!("invalid prg-ram size in header");
             } else if chr_ram_shift & 0x0F == 0x0F || chr_ram_shift & 0xF0 == 0xF0 {
                 bail!("invalid chr-ram size in header");
-            } else if chr_ram_shift & 0xF0 == 0xF0 {
-                bail!("battery-backed chr-ram is currently not supported");
-            } else if header[14] > 0 || header[15] > 0 {
-                bail!("unrecognized data found at header offsets 14-15");
-            }
-        } else {
-            for (i, header) in header.iter().enumerate().take(16).skip(8) {
-                if *header > 0 {
-                    bail!(
-                        "unrecogonized data found at header offset {}. repair and try again",
-                        i,
-                    );
-                }
             }
         }
 
-        // Trainer
-        if flags & 0x04 == 0x04 {
-            bail!("trained roms are currently not supported.");
-        }
-
         Ok(Self {
             version,
             mapper_num,
@@ -487,9 +710,259 @@ impl NesHeader {
             chr_ram_shift,
             tv_mode,
             vs_data,
+            misc_roms,
+            expansion_device,
         })
     }
 
+    /// Repair junk data in bytes 7-15 left by old dumping tools, such as `DiskDude!`, returning a
+    /// description of each fix. NES 2.0 headers are left untouched. Clearing byte 7 drops the
+    /// upper nibble of the mapper number, which was junk as well.
+    pub fn repair(header: &mut [u8; Self::SIZE]) -> Vec<String> {
+        let mut fixes = vec![];
+        if header[7..] == *b"DiskDude!" {
+            header[7..].fill(0x00);
+            fixes.push("removed `DiskDude!` from bytes 7-15".to_string());
+        } else if header[7] & 0x0C == 0x08 {
+            // NES 2.0
+        } else if header[7] & 0x0C != 0x00 || header[12..].iter().any(|byte| *byte > 0) {
+            fixes.push(format!(
+                "cleared junk data {:02X?} from bytes 7-15",
+                &header[7..]
+            ));
+            header[7..].fill(0x00);
+        }
+        fixes
+    }
+
+    /// Serialize as header bytes, for writing a repaired header back to a ROM file.
+    #[must_use]
+    pub fn into_bytes(self) -> [u8; Self::SIZE] {
+        let mut header = [0x00; Self::SIZE];
+        header[..4].copy_from_slice(Self::MAGIC);
+        header[4] = (self.prg_rom_banks & 0xFF) as u8;
+        header[5] = (self.chr_rom_banks & 0xFF) as u8;
+        header[6] = ((self.mapper_num & 0x0F) as u8) << 4 | (self.flags & 0x0F);
+        header[7] = (self.mapper_num & 0xF0) as u8 | ((self.flags >> 4) & 0x03);
+        if self.version == 2 {
+            header[7] |= 0x08;
+            header[8] = (self.submapper_num << 4) | ((self.mapper_num >> 8) & 0x0F) as u8;
+            header[9] =
+                ((self.chr_rom_banks >> 4) & 0xF0) as u8 | ((self.prg_rom_banks >> 8) & 0x0F) as u8;
+            header[10] = self.prg_ram_shift;
+            header[11] = self.chr_ram_shift;
+            header[12] = self.tv_mode;
+            header[13] = self.vs_data;
+            header[14] = self.misc_roms;
+            header[15] = self.expansion_device;
+        }
+        header
+    }
+
+    /// Returns the hardwired nametable `Mirroring`.
+    #[inline]
+    pub const fn mirroring(&self) -> Mirroring {
+        if self.flags & 0x08 == 0x08 {
+            Mirroring::FourScreen
+        } else if self.flags & 0x01 == 0x01 {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
+        }
+    }
+
+    /// Correct fields that disagree with a game database record, returning each override.
+    /// Single-screen mirroring is skipped, as that is controlled by the mapper.
+    pub fn apply_game_info(&mut self, game: &GameInfo) -> Vec<DbOverride> {
+        let mut overrides = vec![];
+        if self.mapper_num != game.mapper_num {
+            overrides.push(DbOverride::Mapper(self.mapper_num, game.mapper_num));
+            self.mapper_num = game.mapper_num;
+        }
+        if self.submapper_num != game.submapper_num {
+            overrides.push(DbOverride::Submapper(
+                self.submapper_num,
+                game.submapper_num,
+            ));
+            self.submapper_num = game.submapper_num;
+        }
+        let mirroring = self.mirroring();
+        let flags = match game.mirroring {
+            Mirroring::Horizontal => Some(0x00),
+            Mirroring::Vertical => Some(0x01),
+            Mirroring::FourScreen => Some(0x08),
+            Mirroring::SingleScreenA | Mirroring::SingleScreenB => None,
+        };
+        if let Some(flags) = flags.filter(|_| mirroring != game.mirroring) {
+            overrides.push(DbOverride::Mirroring(mirroring, game.mirroring));
+            self.flags = (self.flags & !0x09) | flags;
+        }
+        let battery = self.flags & 0x02 == 0x02;
+        if battery != game.battery {
+            overrides.push(DbOverride::Battery(battery, game.battery));
+            self.flags ^= 0x02;
+        }
+        // RAM sizes are left to the header: the database values were recorded from this
+        // emulator's own mapper allocations, so they can't correct anything yet.
+        overrides
+    }
+
+    /// Returns whether a 512-byte trainer precedes PRG-ROM.
+    #[inline]
+    #[must_use]
+    pub const fn has_trainer(&self) -> bool {
+        self.flags & 0x04 == 0x04
+    }
+
+    /// Returns the PRG-ROM size in bytes.
+    #[inline]
+    #[must_use]
+    pub const fn prg_rom_size(&self) -> usize {
+        Self::rom_size(self.prg_rom_banks, PRG_ROM_BANK_SIZE)
+    }
+
+    /// Returns the CHR-ROM size in bytes.
+    #[inline]
+    #[must_use]
+    pub const fn chr_rom_size(&self) -> usize {
+        Self::rom_size(self.chr_rom_banks, CHR_ROM_BANK_SIZE)
+    }
+
+    /// Returns the volatile PRG-RAM size in bytes.
+    #[inline]
+    #[must_use]
+    pub const fn prg_ram_size(&self) -> usize {
+        Self::ram_size(self.prg_ram_shift & 0x0F)
+    }
+
+    /// Returns the non-volatile, battery-backed PRG-NVRAM size in bytes.
+    #[inline]
+    #[must_use]
+    pub const fn prg_nvram_size(&self) -> usize {
+        Self::ram_size(self.prg_ram_shift >> 4)
+    }
+
+    /// Returns the volatile CHR-RAM size in bytes.
+    #[inline]
+    #[must_use]
+    pub const fn chr_ram_size(&self) -> usize {
+        Self::ram_size(self.chr_ram_shift & 0x0F)
+    }
+
+    /// Returns the non-volatile, battery-backed CHR-NVRAM size in bytes.
+    #[inline]
+    #[must_use]
+    pub const fn chr_nvram_size(&self) -> usize {
+        Self::ram_size(self.chr_ram_shift >> 4)
+    }
+
+    /// Returns the `NesRegion` for the NES 2.0 CPU/PPU timing, or `None` for `iNES` headers and
+    /// multi-region ROMs.
+    #[inline]
+    #[must_use]
+    pub const fn region(&self) -> Option<NesRegion> {
+        if self.version != 2 {
+            return None;
+        }
+        match self.tv_mode & 0x03 {
+            0 => Some(NesRegion::Ntsc),
+            1 => Some(NesRegion::Pal),
+            3 => Some(NesRegion::Dendy),
+            _ => None,
+        }
+    }
+
+    /// Returns the console type the ROM was made for.
+    #[inline]
+    pub const fn console_type(&self) -> ConsoleType {
+        match (self.flags >> 4) & 0x03 {
+            0 => ConsoleType::Nes,
+            1 => ConsoleType::VsSystem,
+            2 => ConsoleType::PlayChoice,
+            _ if self.version == 2 => ConsoleType::from_extended(self.vs_data & 0x0F),
+            // Both Vs. and PlayChoice-10 bits are set, which only NES 2.0 allows
+            _ => ConsoleType::VsSystem,
+        }
+    }
+
+    /// Returns the NES 2.0 Vs. System hardware type, or `None` if this isn't a NES 2.0 Vs. System
+    /// ROM.
+    #[inline]
+    #[must_use]
+    pub const fn vs_hardware(&self) -> Option<VsHardware> {
+        if self.version == 2 && self.is_vs_system() {
+            Some(VsHardware::from_type(self.vs_data >> 4))
+        } else {
+            None
+        }
+    }
+
+    // NES 2.0 ROM sizes with a most significant nibble of $F use exponent-multiplier notation:
+    // 2^E * (MM * 2 + 1) where the least significant byte is EEEEEEMM.
+    const fn rom_size(banks: u16, bank_size: usize) -> usize {
+        if banks & 0x0F00 == 0x0F00 {
+            let exponent = (banks & 0xFC) >> 2;
+            let multiplier = (banks & 0x03) as usize * 2 + 1;
+            match 1usize.checked_shl(exponent as u32) {
+                Some(size) => size.saturating_mul(multiplier),
+                None => usize::MAX,
+            }
+        } else {
+            banks as usize * bank_size
+        }
+    }
+
+    const fn ram_size(shift: u8) -> usize {
+        if shift > 0 {
+            64 << shift
+        } else {
+            0
+        }
+    }
+
+    /// Returns whether this is a Vs. System ROM.
+    #[inline]
+    #[must_use]
+    pub const fn is_vs_system(&self) -> bool {
+        self.flags & 0x30 == 0x10
+    }
+
+    /// Returns whether the NES 2.0 Vs. hardware type is a DualSystem, which links two consoles
+    /// with shared RAM.
+    #[inline]
+    #[must_use]
+    pub const fn is_vs_dual_system(&self) -> bool {
+        matches!(
+            self.vs_hardware(),
+            Some(VsHardware::DualSystem | VsHardware::DualSystemBungelingBay)
+        )
+    }
+
+    /// Returns whether this is a PlayChoice-10 ROM.
+    #[inline]
+    #[must_use]
+    pub const fn is_playchoice(&self) -> bool {
+        if self.version == 2 {
+            self.flags & 0x30 == 0x20
+        } else {
+            self.flags & 0x20 == 0x20
+        }
+    }
+
+    /// Returns the `PpuVariant` specified by the NES 2.0 Vs. PPU type. PlayChoice-10 boards
+    /// always use the 2C03. Other `iNES` headers don't specify a PPU, so they use the composite
+    /// PPU.
+    #[inline]
+    pub const fn ppu_variant(&self) -> PpuVariant {
+        if self.version == 2 && self.is_vs_system() {
+            PpuVariant::from_vs_ppu_type(self.vs_data & 0x0F)
+        } else if self.is_playchoice() {
+            PpuVariant::Rp2c03b
+        } else {
+            PpuVariant::Rp2c02
+        }
+    }
+
     #[must_use]
     pub const fn mapper_board(&self) -> &'static str {
         match self.mapper_num {
@@ -501,16 +974,99 @@ impl NesHeader {
             5 => "Mapper 005 - ExROM/MMC5",
             7 => "Mapper 007 - AxROM",
             9 => "Mapper 009 - PxROM",
+            10 => "Mapper 010 - FxROM/MMC4",
+            20 => "Mapper 020 - FDS",
+            21 => "Mapper 021 - VRC4a/VRC4c",
+            22 => "Mapper 022 - VRC2a",
+            23 => "Mapper 023 - VRC2b/VRC4e/VRC4f",
             24 => "Mapper 024 - Vrc6a",
+            25 => "Mapper 025 - VRC2c/VRC4b/VRC4d",
             26 => "Mapper 026 - Vrc6b",
             66 => "Mapper 066 - GxROM/MxROM",
+            69 => "Mapper 069 - Sunsoft FME-7/5B",
             71 => "Mapper 071 - Camerica/Codemasters/BF909x",
+            85 => "Mapper 085 - VRC7",
+            99 => "Mapper 099 - Vs. System",
             155 => "Mapper 155 - SxROM/MMC1A",
             _ => "Unimplemented Mapper",
         }
     }
 }
 
+/// Console type from `iNES` header byte 7 and the NES 2.0 extended console type.
+///
+/// <https://www.nesdev.org/wiki/NES_2.0#Extended_Console_Type>
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[must_use]
+pub enum ConsoleType {
+    Nes,
+    VsSystem,
+    PlayChoice,
+    DecimalFamiclone,
+    EpsmModule,
+    Vt01,
+    Vt02,
+    Vt03,
+    Vt09,
+    Vt32,
+    Vt369,
+    Um6578,
+    FamicomNetworkSystem,
+    Reserved(u8),
+}
+
+impl ConsoleType {
+    const fn from_extended(console_type: u8) -> Self {
+        match console_type {
+            0x00 => Self::Nes,
+            0x01 => Self::VsSystem,
+            0x02 => Self::PlayChoice,
+            0x03 => Self::DecimalFamiclone,
+            0x04 => Self::EpsmModule,
+            0x05 => Self::Vt01,
+            0x06 => Self::Vt02,
+            0x07 => Self::Vt03,
+            0x08 => Self::Vt09,
+            0x09 => Self::Vt32,
+            0x0A => Self::Vt369,
+            0x0B => Self::Um6578,
+            0x0C => Self::FamicomNetworkSystem,
+            _ => Self::Reserved(console_type),
+        }
+    }
+}
+
+/// NES 2.0 Vs. System hardware type, which determines protection and DualSystem wiring.
+///
+/// <https://www.nesdev.org/wiki/NES_2.0#Vs._System_Type>
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[must_use]
+pub enum VsHardware {
+    Unisystem,
+    UnisystemRbiBaseball,
+    UnisystemTkoBoxing,
+    UnisystemSuperXevious,
+    UnisystemIceClimber,
+    DualSystem,
+    DualSystemBungelingBay,
+    Reserved(u8),
+}
+
+impl VsHardware {
+    const fn from_type(hardware_type: u8) -> Self {
+        match hardware_type {
+            0x00 => Self::Unisystem,
+            0x01 => Self::UnisystemRbiBaseball,
+            0x02 => Self::UnisystemTkoBoxing,
+            0x03 => Self::UnisystemSuperXevious,
+            0x04 => Self::UnisystemIceClimber,
+            0x05 => Self::DualSystem,
+            0x06 => Self::DualSystemBungelingBay,
+            _ => Self::Reserved(hardware_type),
+        }
+    }
+}
+
 impl std::fmt::Debug for NesHeader {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
         f.debug_struct("NesHeader")
@@ -524,6 +1080,8 @@ impl std::fmt::Debug for NesHeader {
             .field("chr_ram_shift", &self.chr_ram_shift)
             .field("tv_mode", &self.tv_mode)
             .field("vs_data", &self.vs_data)
+            .field("misc_roms", &self.misc_roms)
+            .field("expansion_device", &self.expansion_device)
             .finish()
     }
 }
@@ -538,6 +1096,7 @@ mod tests {
             fn $test() {
                 let header = NesHeader::load(&mut $data.as_slice()).expect("valid header");
                 assert_eq!(header, $header);
+                assert_eq!(header.into_bytes(), $data);
             }
         )*};
     }
@@ -574,5 +1133,290 @@ mod tests {
                 ..NesHeader::default()
             },
         ),
+        (
+            mapper099_vs_system,
+            [0x4E, 0x45, 0x53, 0x1A,
+             0x02, 0x01, 0x30, 0x69,
+             0x00, 0x00, 0x00, 0x00,
+             0x00, 0x09, 0x00, 0x00],
+            NesHeader {
+                version: 2,
+                mapper_num: 99,
+                flags: 0b1001_0000,
+                prg_rom_banks: 2,
+                chr_rom_banks: 1,
+                vs_data: 0x09,
+                ..NesHeader::default()
+            },
+        ),
+        (
+            mapper004_nes2_extended,
+            [0x4E, 0x45, 0x53, 0x1A,
+             0x44, 0x10, 0x42, 0x08,
+             0x10, 0x0F, 0x70, 0x07,
+             0x01, 0x00, 0x01, 0x01],
+            NesHeader {
+                version: 2,
+                mapper_num: 4,
+                submapper_num: 1,
+                flags: 0b1000_0010,
+                prg_rom_banks: 0x0F44,
+                chr_rom_banks: 0x10,
+                prg_ram_shift: 0x70,
+                chr_ram_shift: 0x07,
+                tv_mode: 0x01,
+                misc_roms: 1,
+                expansion_device: 0x01,
+                ..NesHeader::default()
+            },
+        ),
     );
+
+    #[test]
+    fn repair_header() {
+        let mut data = *b"NES\x1a\x02\x01\x41DiskDude!";
+        let header = NesHeader::load(&mut data.as_slice()).expect("repaired header");
+        assert_eq!(header.mapper_num, 4);
+        assert_eq!(header.version, 1);
+        assert_eq!(NesHeader::repair(&mut data).len(), 1);
+        assert_eq!(data, header.into_bytes());
+        assert!(NesHeader::repair(&mut data).is_empty());
+
+        let mut data = *b"NES\x1a\x02\x01\x10\x00\x00\x00\x00\x00Ni03";
+        let header = NesHeader::load(&mut data.as_slice()).expect("repaired header");
+        assert_eq!(header.mapper_num, 1);
+        assert_eq!(NesHeader::repair(&mut data).len(), 1);
+    }
+
+    #[test]
+    fn trainer() {
+        #[rustfmt::skip]
+        let mut rom = vec![
+            0x4E, 0x45, 0x53, 0x1A,
+            0x01, 0x01, 0x46, 0x00,
+            0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00,
+        ];
+        rom.resize(16 + TRAINER_SIZE, 0xEA);
+        rom.resize(rom.len() + PRG_ROM_BANK_SIZE + CHR_ROM_BANK_SIZE, 0x00);
+        let cart =
+            Cart::from_rom("trainer", &mut rom.as_slice(), RamState::default()).expect("valid rom");
+        assert_eq!(cart.prg_rom().len(), PRG_ROM_BANK_SIZE);
+        assert_eq!(cart.prg_ram()[0x0FFF], 0x00);
+        assert!(cart.prg_ram()[0x1000..0x1200]
+            .iter()
+            .all(|byte| *byte == 0xEA));
+        assert_eq!(cart.prg_ram()[0x1200], 0x00);
+    }
+
+    #[test]
+    fn trainer_without_prg_ram() {
+        #[rustfmt::skip]
+        let mut rom = vec![
+            0x4E, 0x45, 0x53, 0x1A,
+            0x01, 0x01, 0x24, 0x00,
+            0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00,
+        ];
+        rom.resize(
+            16 + TRAINER_SIZE + PRG_ROM_BANK_SIZE + CHR_ROM_BANK_SIZE,
+            0x00,
+        );
+        let err = Cart::from_rom("trainer", &mut rom.as_slice(), RamState::default())
+            .expect_err("uxrom has no prg-ram");
+        assert!(err.to_string().contains("$7000"), "{err}");
+    }
+
+    #[test]
+    fn game_db_overrides() {
+        #[rustfmt::skip]
+        let mut rom = vec![
+            0x4E, 0x45, 0x53, 0x1A,
+            0x01, 0x01, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00,
+        ];
+        rom.resize(16 + PRG_ROM_BANK_SIZE, 0xEA);
+        rom.resize(rom.len() + CHR_ROM_BANK_SIZE, 0x00);
+        let crc32 = RomHashes::new(&rom[16..]).crc32_hex();
+        let record = format!("crc32:{crc32},PAL,CNROM,,,3,1,1,0,0,true,Vertical,0,\"Test\"");
+        let game_db = GameDb::load(record.as_bytes()).expect("valid db");
+
+        let cart = Cart::from_rom_with_db("db", &mut rom.as_slice(), RamState::default(), None)
+            .expect("valid rom");
+        assert_eq!(cart.mapper_num(), 0);
+        assert!(cart.db_overrides().is_empty());
+
+        let cart = Cart::from_rom_with_db(
+            "db",
+            &mut rom.as_slice(),
+            RamState::default(),
+            Some(&game_db),
+        )
+        .expect("valid rom");
+        assert_eq!(cart.mapper_num(), 3);
+        assert_eq!(
+            cart.prg_hashes(),
+            RomHashes::new(&rom[16..16 + PRG_ROM_BANK_SIZE])
+        );
+        assert_eq!(cart.rom_hashes(), RomHashes::new(&rom[16..]));
+        assert_eq!(cart.mirroring(), Mirroring::Vertical);
+        assert!(cart.battery_backed());
+        assert_eq!(cart.region(), NesRegion::Pal);
+        assert_eq!(
+            cart.db_overrides(),
+            [
+                DbOverride::Mapper(0, 3),
+                DbOverride::Mirroring(Mirroring::Horizontal, Mirroring::Vertical),
+                DbOverride::Battery(false, true),
+            ]
+        );
+
+        // NES 2.0 headers are trusted
+        rom[7] = 0x08;
+        let cart = Cart::from_rom_with_db(
+            "db",
+            &mut rom.as_slice(),
+            RamState::default(),
+            Some(&game_db),
+        )
+        .expect("valid rom");
+        assert_eq!(cart.mapper_num(), 0);
+        assert!(cart.db_overrides().is_empty());
+        assert_eq!(cart.region(), NesRegion::Ntsc);
+    }
+
+    #[test]
+    fn header_ram_kept_over_game_info() {
+        let game = GameInfo::parse("crc32:00000000,NTSC,,,,1,8,0,1,2,false,SingleScreenA,0,title")
+            .expect("valid record");
+        let mut header = NesHeader {
+            version: 1,
+            mapper_num: 1,
+            ..NesHeader::default()
+        };
+        assert!(header.apply_game_info(&game).is_empty());
+        assert_eq!(header.prg_ram_size(), 0);
+        assert_eq!(header.chr_ram_size(), 0);
+        assert_eq!(header.mirroring(), Mirroring::Horizontal);
+    }
+
+    #[test]
+    fn nes2_sizes_and_timing() {
+        let header = NesHeader {
+            version: 2,
+            prg_rom_banks: 0x0F44,
+            chr_rom_banks: 0x10,
+            prg_ram_shift: 0x70,
+            chr_ram_shift: 0x07,
+            tv_mode: 0x01,
+            ..NesHeader::default()
+        };
+        assert_eq!(header.prg_rom_size(), 128 * 1024);
+        assert_eq!(header.chr_rom_size(), 128 * 1024);
+        assert_eq!(header.prg_ram_size(), 0);
+        assert_eq!(header.prg_nvram_size(), 8 * 1024);
+        assert_eq!(header.chr_ram_size(), 8 * 1024);
+        assert_eq!(header.chr_nvram_size(), 0);
+        assert_eq!(header.region(), Some(NesRegion::Pal));
+        assert_eq!(header.console_type(), ConsoleType::Nes);
+
+        // 2^3 * (1 * 2 + 1) = 24 bytes
+        let header = NesHeader {
+            prg_rom_banks: 0x0F0D,
+            tv_mode: 0x02,
+            ..header
+        };
+        assert_eq!(header.prg_rom_size(), 24);
+        assert_eq!(header.region(), None);
+
+        let header = NesHeader {
+            version: 2,
+            flags: 0b0011_0000,
+            vs_data: 0x0C,
+            ..NesHeader::default()
+        };
+        assert_eq!(header.console_type(), ConsoleType::FamicomNetworkSystem);
+        assert_eq!(header.vs_hardware(), None);
+
+        let header = NesHeader {
+            version: 2,
+            flags: 0b0001_0000,
+            vs_data: 0x50,
+            ..NesHeader::default()
+        };
+        assert_eq!(header.console_type(), ConsoleType::VsSystem);
+        assert_eq!(header.vs_hardware(), Some(VsHardware::DualSystem));
+        assert!(header.is_vs_dual_system());
+    }
+
+    #[test]
+    fn vs_ppu_variant() {
+        let header = NesHeader {
+            version: 2,
+            flags: 0b1001_0000,
+            vs_data: 0x03,
+            ..NesHeader::default()
+        };
+        assert_eq!(header.ppu_variant(), PpuVariant::Rp2c04_0002);
+        let header = NesHeader {
+            version: 1,
+            flags: 0b0001_0000,
+            ..NesHeader::default()
+        };
+        assert_eq!(header.ppu_variant(), PpuVariant::Rp2c02);
+    }
+
+    #[test]
+    fn playchoice_inst_rom() {
+        #[rustfmt::skip]
+        let mut rom = vec![
+            0x4E, 0x45, 0x53, 0x1A,
+            0x01, 0x01, 0x00, 0x02,
+            0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00,
+        ];
+        rom.resize(16 + PRG_ROM_BANK_SIZE + CHR_ROM_BANK_SIZE, 0x00);
+        rom.resize(
+            rom.len() + PlayChoice::INST_ROM_SIZE + PlayChoice::PROM_SIZE,
+            0xFF,
+        );
+        let cart =
+            Cart::from_rom("pc10", &mut rom.as_slice(), RamState::default()).expect("valid rom");
+        assert_eq!(cart.ppu_variant(), PpuVariant::Rp2c03b);
+        let playchoice = cart.playchoice().expect("playchoice data");
+        assert_eq!(playchoice.inst_rom().len(), PlayChoice::INST_ROM_SIZE);
+        assert_eq!(playchoice.prom().len(), PlayChoice::PROM_SIZE);
+    }
+
+    #[test]
+    fn unif_rom() {
+        fn unif(board: &[u8]) -> Vec<u8> {
+            let mut rom = b"UNIF".to_vec();
+            rom.resize(32, 0x00);
+            for (id, data) in [
+                (b"MAPR", board.to_vec()),
+                (b"PRG0", vec![0x00; PRG_ROM_BANK_SIZE]),
+                (b"CHR0", vec![0x00; CHR_ROM_BANK_SIZE]),
+                (b"MIRR", vec![0x01]),
+            ] {
+                rom.extend(id);
+                rom.extend((data.len() as u32).to_le_bytes());
+                rom.extend(data);
+            }
+            rom
+        }
+
+        let rom = unif(b"NES-NROM-128\0");
+        let cart =
+            Cart::from_rom("unif", &mut rom.as_slice(), RamState::default()).expect("valid rom");
+        assert_eq!(cart.mapper_num(), 0);
+        assert_eq!(cart.mirroring(), Mirroring::Vertical);
+        assert_eq!(cart.prg_rom().len(), PRG_ROM_BANK_SIZE);
+
+        let rom = unif(b"BMC-70in1\0");
+        let err = Cart::from_rom("unif", &mut rom.as_slice(), RamState::default())
+            .expect_err("unimplemented board");
+        assert!(err.to_string().contains("BMC-70in1"));
+    }
 }
@@ -0,0 +1,273 @@
+//! Compressed ROM archives.
+//!
+//! ROM sets are commonly distributed as gzip, zip or 7z archives. Archives are detected by their
+//! magic bytes, and the first entry with a known ROM extension is loaded unless an entry is
+//! picked by name.
+
+use crate::NesResult;
+use anyhow::{anyhow, bail, Context};
+use flate2::read::GzDecoder;
+use sevenz_rust::{Password, SevenZReader};
+use std::{
+    fs,
+    io::{Cursor, Read},
+    path::Path,
+};
+use zip::ZipArchive;
+
+const GZIP_MAGIC: &[u8] = &[0x1F, 0x8B];
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+const SEVEN_ZIP_MAGIC: &[u8] = &[0x37, 0x7A, 0xBC, 0xAF, 0x27, 0x1C];
+
+/// File extensions of entries that can be loaded as a ROM.
+pub const ROM_EXTENSIONS: [&str; 5] = ["nes", "fds", "nsf", "unf", "unif"];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[must_use]
+pub enum ArchiveKind {
+    Gzip,
+    Zip,
+    SevenZip,
+}
+
+impl ArchiveKind {
+    /// Detect the archive format from the magic bytes at the start of `data`.
+    #[must_use]
+    pub fn detect(data: &[u8]) -> Option<Self> {
+        if data.starts_with(GZIP_MAGIC) {
+            Some(Self::Gzip)
+        } else if data.starts_with(ZIP_MAGIC) {
+            Some(Self::Zip)
+        } else if data.starts_with(SEVEN_ZIP_MAGIC) {
+            Some(Self::SevenZip)
+        } else {
+            None
+        }
+    }
+}
+
+/// A gzip, zip or 7z archive held in memory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[must_use]
+pub struct Archive {
+    kind: ArchiveKind,
+    data: Vec<u8>,
+}
+
+impl Archive {
+    /// Returns whether `data` is a supported archive.
+    #[must_use]
+    pub fn is_archive(data: &[u8]) -> bool {
+        ArchiveKind::detect(data).is_some()
+    }
+
+    /// Create an `Archive` from archive data.
+    ///
+    /// # Errors
+    ///
+    /// If `data` is not a supported archive, then an error is returned.
+    pub fn new(data: Vec<u8>) -> NesResult<Self> {
+        let kind = ArchiveKind::detect(&data).ok_or_else(|| anyhow!("unsupported archive"))?;
+        Ok(Self { kind, data })
+    }
+
+    /// Load an `Archive` from a file path.
+    ///
+    /// # Errors
+    ///
+    /// If the file cannot be read or is not a supported archive, then an error is returned.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> NesResult<Self> {
+        let path = path.as_ref();
+        let data = fs::read(path).with_context(|| format!("failed to read archive {path:?}"))?;
+        Self::new(data)
+    }
+
+    #[inline]
+    pub const fn kind(&self) -> ArchiveKind {
+        self.kind
+    }
+
+    /// Names of the file entries in the archive, in archive order. Gzip archives hold a single
+    /// entry, named after the original file if it was stored, or empty otherwise.
+    ///
+    /// # Errors
+    ///
+    /// If the archive is corrupted, then an error is returned.
+    pub fn entries(&self) -> NesResult<Vec<String>> {
+        match self.kind {
+            ArchiveKind::Gzip => {
+                let decoder = GzDecoder::new(self.data.as_slice());
+                let name = decoder
+                    .header()
+                    .and_then(|header| header.filename())
+                    .map(|name| String::from_utf8_lossy(name).into_owned())
+                    .unwrap_or_default();
+                Ok(vec![name])
+            }
+            ArchiveKind::Zip => {
+                let mut zip = self.zip()?;
+                let mut entries = Vec::with_capacity(zip.len());
+                for i in 0..zip.len() {
+                    let file = zip.by_index(i).context("failed to read zip entry")?;
+                    if file.is_file() {
+                        entries.push(file.name().to_string());
+                    }
+                }
+                Ok(entries)
+            }
+            ArchiveKind::SevenZip => Ok(self
+                .seven_zip()?
+                .archive()
+                .files
+                .iter()
+                .filter(|entry| entry.has_stream() && !entry.is_directory())
+                .map(|entry| entry.name().to_string())
+                .collect()),
+        }
+    }
+
+    /// Name of the first entry with a ROM file extension, or the only entry of a gzip archive.
+    ///
+    /// # Errors
+    ///
+    /// If the archive is corrupted, then an error is returned.
+    pub fn rom_entry(&self) -> NesResult<Option<String>> {
+        let entries = self.entries()?;
+        if self.kind == ArchiveKind::Gzip {
+            return Ok(entries.into_iter().next());
+        }
+        Ok(entries.into_iter().find(|entry| {
+            Path::new(entry)
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| {
+                    ROM_EXTENSIONS
+                        .iter()
+                        .any(|rom_ext| ext.eq_ignore_ascii_case(rom_ext))
+                })
+        }))
+    }
+
+    /// Decompress the entry named `entry`. The name is ignored for gzip archives.
+    ///
+    /// # Errors
+    ///
+    /// If the entry is not found or the archive is corrupted, then an error is returned.
+    pub fn read_entry(&self, entry: &str) -> NesResult<Vec<u8>> {
+        let mut data = vec![];
+        match self.kind {
+            ArchiveKind::Gzip => {
+                GzDecoder::new(self.data.as_slice())
+                    .read_to_end(&mut data)
+                    .context("failed to decompress gzip archive")?;
+            }
+            ArchiveKind::Zip => {
+                self.zip()?
+                    .by_name(entry)
+                    .with_context(|| format!("failed to find zip entry {entry:?}"))?
+                    .read_to_end(&mut data)
+                    .with_context(|| format!("failed to decompress zip entry {entry:?}"))?;
+            }
+            ArchiveKind::SevenZip => {
+                let mut found = false;
+                self.seven_zip()?
+                    .for_each_entries(|file, reader| {
+                        if file.name() == entry {
+                            found = true;
+                            reader.read_to_end(&mut data)?;
+                            return Ok(false);
+                        }
+                        Ok(true)
+                    })
+                    .with_context(|| format!("failed to decompress 7z entry {entry:?}"))?;
+                if !found {
+                    bail!("failed to find 7z entry {entry:?}");
+                }
+            }
+        }
+        Ok(data)
+    }
+
+    /// Decompress the first ROM entry, returning its name and data.
+    ///
+    /// # Errors
+    ///
+    /// If there are no ROM entries or the archive is corrupted, then an error is returned.
+    pub fn read_rom(&self) -> NesResult<(String, Vec<u8>)> {
+        let entry = self.rom_entry()?.ok_or_else(|| {
+            anyhow!(
+                "no rom found in archive. expected one of: {}",
+                ROM_EXTENSIONS.join(", ")
+            )
+        })?;
+        let data = self.read_entry(&entry)?;
+        Ok((entry, data))
+    }
+
+    fn zip(&self) -> NesResult<ZipArchive<Cursor<&[u8]>>> {
+        ZipArchive::new(Cursor::new(self.data.as_slice())).context("invalid zip archive")
+    }
+
+    fn seven_zip(&self) -> NesResult<SevenZReader<Cursor<&[u8]>>> {
+        SevenZReader::new(
+            Cursor::new(self.data.as_slice()),
+            self.data.len() as u64,
+            Password::empty(),
+        )
+        .context("invalid 7z archive")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{write::GzEncoder, Compression, GzBuilder};
+    use std::io::Write;
+    use zip::{write::FileOptions, ZipWriter};
+
+    #[test]
+    fn gzip_archive() {
+        let mut encoder = GzBuilder::new()
+            .filename("game.nes")
+            .write(vec![], Compression::default());
+        encoder.write_all(b"NES\x1a").expect("compressed");
+        let archive = Archive::new(encoder.finish().expect("compressed")).expect("archive");
+        assert_eq!(archive.kind(), ArchiveKind::Gzip);
+        assert_eq!(archive.entries().expect("entries"), ["game.nes"]);
+        assert_eq!(
+            archive.read_rom().expect("rom"),
+            ("game.nes".to_string(), b"NES\x1a".to_vec())
+        );
+
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(b"NES\x1a").expect("compressed");
+        let archive = Archive::new(encoder.finish().expect("compressed")).expect("archive");
+        assert_eq!(archive.read_rom().expect("rom").1, b"NES\x1a");
+    }
+
+    #[test]
+    fn zip_archive() {
+        let mut zip = ZipWriter::new(Cursor::new(vec![]));
+        for (name, data) in [("readme.txt", b"text"), ("game.NES", b"NES\x1a")] {
+            zip.start_file(name, FileOptions::default())
+                .expect("zip entry");
+            zip.write_all(data).expect("zip entry");
+        }
+        let data = zip.finish().expect("zip archive").into_inner();
+        assert!(Archive::is_archive(&data));
+
+        let archive = Archive::new(data).expect("archive");
+        assert_eq!(archive.kind(), ArchiveKind::Zip);
+        assert_eq!(
+            archive.entries().expect("entries"),
+            ["readme.txt", "game.NES"]
+        );
+        assert_eq!(
+            archive.rom_entry().expect("entries").as_deref(),
+            Some("game.NES")
+        );
+        assert_eq!(archive.read_entry("readme.txt").expect("entry"), b"text");
+        assert!(archive.read_entry("missing.nes").is_err());
+        assert!(Archive::new(b"NES\x1a".to_vec()).is_err());
+    }
+}
@@ -0,0 +1,255 @@
+//! Famicom Disk System disk images.
+//!
+//! Disk images are either raw `.fds` files, which are a sequence of 65,500 byte disk sides, or
+//! the same data preceded by a 16 byte `fwNES` header. Images only store the data of each block,
+//! so the gaps and CRCs found on a real disk are added back when a side is loaded into the drive,
+//! and removed again when saving.
+//!
+//! <https://www.nesdev.org/wiki/FDS_file_format>
+//! <https://www.nesdev.org/wiki/FDS_disk_format>
+
+use crate::{cart::patch::Ips, NesResult};
+use anyhow::{bail, Context};
+use std::io::{Read, Write};
+
+const HEADER_MAGIC: &[u8] = b"FDS\x1a";
+const HEADER_SIZE: usize = 16;
+const DISK_MAGIC: &[u8] = b"\x01*NINTENDO-HVC*";
+
+// Gaps are measured in bits on disk.
+const LEAD_IN_GAP: usize = 28_300 / 8;
+const BLOCK_GAP: usize = 976 / 8;
+const BLOCK_START: u8 = 0x80;
+// Real CRCs are not stored in images. The BIOS doesn't verify reads, so any value works.
+const BLOCK_CRC: [u8; 2] = [0x4D, 0x62];
+
+/// A Famicom Disk System disk image.
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+#[must_use]
+pub struct DiskImage {
+    sides: Vec<Vec<u8>>,
+}
+
+impl DiskImage {
+    /// Size of a single disk side, in bytes.
+    pub const SIDE_SIZE: usize = 65_500;
+
+    /// Returns whether `data` looks like a disk image, with or without an `fwNES` header.
+    #[must_use]
+    pub fn is_disk_image(data: &[u8]) -> bool {
+        data.starts_with(HEADER_MAGIC) || data.starts_with(DISK_MAGIC)
+    }
+
+    /// Load a disk image, skipping the `fwNES` header if present.
+    ///
+    /// # Errors
+    ///
+    /// If the image is empty or a disk side is missing the disk info block, then an error is
+    /// returned.
+    pub fn load(data: &[u8]) -> NesResult<Self> {
+        let data = if data.starts_with(HEADER_MAGIC) {
+            data.get(HEADER_SIZE..).unwrap_or_default()
+        } else {
+            data
+        };
+        if data.is_empty() {
+            bail!("invalid fds image: no disk sides found");
+        }
+        let sides = data
+            .chunks(Self::SIDE_SIZE)
+            .enumerate()
+            .map(|(i, side)| {
+                if !side.starts_with(DISK_MAGIC) {
+                    bail!("invalid fds image: missing disk info block on side {}", i);
+                }
+                let mut side = side.to_vec();
+                side.resize(Self::SIDE_SIZE, 0x00);
+                Ok(side)
+            })
+            .collect::<NesResult<Vec<_>>>()?;
+        Ok(Self { sides })
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn sides(&self) -> &[Vec<u8>] {
+        &self.sides
+    }
+
+    /// Headerless image data.
+    #[must_use]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.sides.concat()
+    }
+
+    /// Disk side data as read by the drive, with gaps and block CRCs.
+    #[must_use]
+    pub fn add_gaps(side: &[u8]) -> Vec<u8> {
+        let mut disk = vec![0x00; LEAD_IN_GAP];
+        let mut pos = 0;
+        let mut file_size = 0;
+        while let Some(len) = Self::block_len(side, pos, file_size) {
+            if side[pos] == 0x03 {
+                file_size = Self::file_size(side, pos);
+            }
+            disk.push(BLOCK_START);
+            disk.extend_from_slice(&side[pos..pos + len]);
+            disk.extend_from_slice(&BLOCK_CRC);
+            disk.resize(disk.len() + BLOCK_GAP, 0x00);
+            pos += len;
+        }
+        disk.resize(disk.len().max(Self::SIDE_SIZE + LEAD_IN_GAP), 0x00);
+        disk
+    }
+
+    /// Disk side image data from data read by the drive, dropping gaps and block CRCs.
+    #[must_use]
+    pub fn remove_gaps(disk: &[u8]) -> Vec<u8> {
+        let mut side = Vec::with_capacity(Self::SIDE_SIZE);
+        let mut pos = 0;
+        let mut file_size = 0;
+        while pos < disk.len() {
+            if disk[pos] != BLOCK_START {
+                pos += 1;
+                continue;
+            }
+            pos += 1;
+            let Some(len) = Self::block_len(disk, pos, file_size) else {
+                break;
+            };
+            if disk[pos] == 0x03 {
+                file_size = Self::file_size(disk, pos);
+            }
+            side.extend_from_slice(&disk[pos..pos + len]);
+            pos += len + BLOCK_CRC.len();
+        }
+        side.resize(Self::SIDE_SIZE, 0x00);
+        side
+    }
+
+    // Length of the block starting at `pos`, if valid and complete.
+    fn block_len(data: &[u8], pos: usize, file_size: usize) -> Option<usize> {
+        let len = match data.get(pos)? {
+            0x01 => 56, // Disk info
+            0x02 => 2,  // File amount
+            0x03 => 16, // File header
+            0x04 => 1 + file_size,
+            _ => return None,
+        };
+        (pos + len <= data.len()).then_some(len)
+    }
+
+    // File size stored in a file header block.
+    fn file_size(data: &[u8], pos: usize) -> usize {
+        usize::from(data[pos + 13]) | (usize::from(data[pos + 14]) << 8)
+    }
+}
+
+/// Changes written to a disk image, stored as an IPS patch so the original image is never
+/// modified.
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+#[must_use]
+pub struct DiskDiff {
+    ips: Ips,
+}
+
+impl DiskDiff {
+    /// Diff two headerless images of the same size.
+    pub fn new(original: &[u8], modified: &[u8]) -> Self {
+        Self {
+            ips: Ips::new(original, modified),
+        }
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.ips.is_empty()
+    }
+
+    /// Apply changes to a headerless image. Changes past the end of the image are ignored.
+    pub fn apply(&self, image: &mut [u8]) {
+        let mut patched = image.to_vec();
+        self.ips.apply(&mut patched);
+        patched.resize(image.len(), 0x00);
+        image.copy_from_slice(&patched);
+    }
+
+    /// Write changes as an IPS patch.
+    ///
+    /// # Errors
+    ///
+    /// If writing fails, then an error is returned.
+    pub fn save<W: Write>(&self, mut writer: W) -> NesResult<()> {
+        writer
+            .write_all(&self.ips.to_bytes())
+            .context("failed to write disk diff")
+    }
+
+    /// Read changes from an IPS patch.
+    ///
+    /// # Errors
+    ///
+    /// If reading fails or the patch is invalid, then an error is returned.
+    pub fn load<R: Read>(mut reader: R) -> NesResult<Self> {
+        let mut patch = vec![];
+        reader
+            .read_to_end(&mut patch)
+            .context("failed to read disk diff")?;
+        let ips = Ips::load(&patch).context("invalid disk diff")?;
+        Ok(Self { ips })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_side() -> Vec<u8> {
+        let mut side = DISK_MAGIC.to_vec();
+        side.resize(56, 0x00);
+        side.extend([0x02, 0x01]);
+        let mut header = vec![0x03; 16];
+        header[13] = 0x04;
+        header[14] = 0x00;
+        side.extend(header);
+        side.extend([0x04, 0xDE, 0xAD, 0xBE, 0xEF]);
+        side.resize(DiskImage::SIDE_SIZE, 0x00);
+        side
+    }
+
+    #[test]
+    fn load_and_gaps() {
+        let mut data = HEADER_MAGIC.to_vec();
+        data.resize(HEADER_SIZE, 0x00);
+        data.extend(test_side());
+        data.extend(test_side());
+        assert!(DiskImage::is_disk_image(&data));
+        let image = DiskImage::load(&data).expect("valid image");
+        assert_eq!(image.sides().len(), 2);
+
+        let disk = DiskImage::add_gaps(&image.sides()[0]);
+        assert_eq!(disk[LEAD_IN_GAP], BLOCK_START);
+        assert_eq!(&disk[LEAD_IN_GAP + 1..][..DISK_MAGIC.len()], DISK_MAGIC);
+        assert_eq!(DiskImage::remove_gaps(&disk), image.sides()[0]);
+
+        assert!(DiskImage::load(&[0x00; 16]).is_err());
+    }
+
+    #[test]
+    fn ips_diff() {
+        let original = test_side();
+        let mut modified = original.clone();
+        modified[100..104].copy_from_slice(&[1, 2, 3, 4]);
+        modified[2000] = 0xFF;
+        let diff = DiskDiff::new(&original, &modified);
+        assert!(!diff.is_empty());
+
+        let mut patch = vec![];
+        diff.save(&mut patch).expect("saved diff");
+        let diff = DiskDiff::load(patch.as_slice()).expect("loaded diff");
+        let mut patched = original;
+        diff.apply(&mut patched);
+        assert_eq!(patched, modified);
+    }
+}
@@ -0,0 +1,299 @@
+//! Game database of known ROMs, used to correct bad `iNES` headers.
+//!
+//! Each line is a comma-separated record keyed by the checksum of headerless PRG-ROM followed by
+//! CHR-ROM, written as `crc32:XXXXXXXX` or `sha1:XXXX...`, see `config/game_database.txt` for the
+//! field list. Lines starting with `#` are comments.
+
+use crate::{
+    cart::hash::{from_hex, RomHashes},
+    common::NesRegion,
+    ppu::Mirroring,
+    NesResult,
+};
+use anyhow::{anyhow, Context};
+use once_cell::sync::Lazy;
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+const GAME_DB: &[u8] = include_bytes!("../../config/game_database.txt");
+
+static BUILTIN: Lazy<GameDb> = Lazy::new(|| {
+    #[cfg(not(target_arch = "wasm32"))]
+    return GameDb::load(GAME_DB).unwrap_or_else(|err| {
+        log::error!("failed to load game database: {err:?}");
+        GameDb::default()
+    });
+    #[cfg(target_arch = "wasm32")]
+    GameDb::default()
+});
+
+/// Key identifying a ROM in the game database.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[must_use]
+pub enum GameKey {
+    /// CRC32 of PRG-ROM followed by CHR-ROM.
+    Crc32(u32),
+    /// SHA-1 of PRG-ROM followed by CHR-ROM.
+    Sha1([u8; 20]),
+}
+
+impl GameKey {
+    /// Parse a record key, either `crc32:XXXXXXXX` or `sha1:XXXX...`.
+    ///
+    /// # Errors
+    ///
+    /// If the key isn't valid, then an error is returned.
+    pub fn parse(key: &str) -> NesResult<Self> {
+        if let Some(crc32) = key.strip_prefix("crc32:") {
+            Ok(Self::Crc32(u32::from_be_bytes(from_hex(crc32)?)))
+        } else if let Some(sha1) = key.strip_prefix("sha1:") {
+            Ok(Self::Sha1(from_hex(sha1)?))
+        } else {
+            Err(anyhow!("invalid key: {key:?}"))
+        }
+    }
+}
+
+/// A game database record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[must_use]
+pub struct GameInfo {
+    pub key: GameKey,
+    pub region: Option<NesRegion>,
+    pub board: String,
+    pub pcb: String,
+    pub chip: String,
+    pub mapper_num: u16,
+    pub prg_rom_banks: u16, // Number of 16KB PRG-ROM banks
+    pub chr_rom_banks: u16, // Number of 8KB CHR-ROM banks
+    pub chr_ram_banks: u16, // Number of 8KB CHR-RAM banks
+    pub prg_ram_banks: u16, // Number of 16KB PRG-RAM banks
+    pub battery: bool,
+    pub mirroring: Mirroring,
+    pub submapper_num: u8,
+    pub title: String,
+}
+
+impl GameInfo {
+    pub const PRG_RAM_BANK_SIZE: usize = 16 * 1024;
+    pub const CHR_RAM_BANK_SIZE: usize = 8 * 1024;
+
+    const FIELDS: usize = 14;
+
+    /// Parse a database record.
+    ///
+    /// # Errors
+    ///
+    /// If the record is missing fields or a field is invalid, then an error is returned.
+    pub fn parse(record: &str) -> NesResult<Self> {
+        // Titles may contain commas, so they take the remainder of the record
+        let fields = record.splitn(Self::FIELDS, ',').collect::<Vec<_>>();
+        let [key, region, board, pcb, chip, mapper_num, prg_rom_banks, chr_rom_banks, chr_ram_banks, prg_ram_banks, battery, mirroring, submapper_num, title] =
+            fields[..]
+        else {
+            return Err(anyhow!(
+                "expected {} fields, found {}",
+                Self::FIELDS,
+                fields.len()
+            ));
+        };
+        Ok(Self {
+            key: GameKey::parse(key)?,
+            region: NesRegion::try_from(region).ok(),
+            board: board.to_string(),
+            pcb: pcb.to_string(),
+            chip: chip.to_string(),
+            mapper_num: mapper_num.parse().context("invalid mapper")?,
+            prg_rom_banks: prg_rom_banks.parse().context("invalid prg-rom size")?,
+            chr_rom_banks: chr_rom_banks.parse().context("invalid chr-rom size")?,
+            chr_ram_banks: chr_ram_banks.parse().context("invalid chr-ram size")?,
+            prg_ram_banks: prg_ram_banks.parse().context("invalid prg-ram size")?,
+            battery: battery.parse().context("invalid battery")?,
+            mirroring: match mirroring {
+                "Horizontal" => Mirroring::Horizontal,
+                "Vertical" => Mirroring::Vertical,
+                "SingleScreenA" => Mirroring::SingleScreenA,
+                "SingleScreenB" => Mirroring::SingleScreenB,
+                "FourScreen" => Mirroring::FourScreen,
+                _ => return Err(anyhow!("invalid mirroring: {mirroring:?}")),
+            },
+            submapper_num: submapper_num.parse().context("invalid submapper")?,
+            title: title.trim_matches('"').to_string(),
+        })
+    }
+
+    /// PRG-RAM size in bytes, or `None` if less than one bank or unknown.
+    #[must_use]
+    pub const fn prg_ram_size(&self) -> Option<usize> {
+        match self.prg_ram_banks {
+            0 => None,
+            banks => Some(banks as usize * Self::PRG_RAM_BANK_SIZE),
+        }
+    }
+
+    /// CHR-RAM size in bytes, or `None` if the board has no CHR-RAM.
+    #[must_use]
+    pub const fn chr_ram_size(&self) -> Option<usize> {
+        match self.chr_ram_banks {
+            0 => None,
+            banks => Some(banks as usize * Self::CHR_RAM_BANK_SIZE),
+        }
+    }
+}
+
+/// A header field corrected by the game database, with the header and database values.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[must_use]
+pub enum DbOverride {
+    Mapper(u16, u16),
+    Submapper(u8, u8),
+    Mirroring(Mirroring, Mirroring),
+    Battery(bool, bool),
+}
+
+/// A game database keyed by ROM checksum.
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+#[must_use]
+pub struct GameDb {
+    games: HashMap<GameKey, GameInfo>,
+}
+
+impl GameDb {
+    /// The game database bundled with `TetaNES`.
+    pub fn builtin() -> &'static Self {
+        &BUILTIN
+    }
+
+    /// Load a game database, such as a user-supplied override database.
+    ///
+    /// # Errors
+    ///
+    /// If reading fails or a record is invalid, then an error is returned.
+    pub fn load<R: BufRead>(reader: R) -> NesResult<Self> {
+        let mut games = HashMap::new();
+        for (i, line) in reader.lines().enumerate() {
+            let line = line.context("failed to read game database")?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let game = GameInfo::parse(line)
+                .with_context(|| format!("invalid game database record on line {}", i + 1))?;
+            games.insert(game.key, game);
+        }
+        Ok(Self { games })
+    }
+
+    /// Load a game database from a file path.
+    ///
+    /// # Errors
+    ///
+    /// If the file cannot be read or a record is invalid, then an error is returned.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> NesResult<Self> {
+        let path = path.as_ref();
+        let file =
+            File::open(path).with_context(|| format!("failed to open game database {path:?}"))?;
+        Self::load(BufReader::new(file))
+    }
+
+    #[must_use]
+    pub fn get(&self, key: GameKey) -> Option<&GameInfo> {
+        self.games.get(&key)
+    }
+
+    /// Find the record for a ROM by the SHA-1 or CRC32 of PRG-ROM followed by CHR-ROM.
+    #[must_use]
+    pub fn lookup(&self, hashes: &RomHashes) -> Option<&GameInfo> {
+        self.get(GameKey::Sha1(hashes.sha1))
+            .or_else(|| self.get(GameKey::Crc32(hashes.crc32)))
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.games.len()
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.games.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_record() {
+        let game = GameInfo::parse(
+            r#"crc32:0000002A,PAL,SLROM,,,1,8,16,0,2,true,SingleScreenA,0,"Game, The (Europe).nes""#,
+        )
+        .expect("valid record");
+        assert_eq!(game.key, GameKey::Crc32(42));
+        assert_eq!(game.region, Some(NesRegion::Pal));
+        assert_eq!(game.board, "SLROM");
+        assert_eq!(game.mapper_num, 1);
+        assert_eq!(game.prg_ram_size(), Some(32 * 1024));
+        assert_eq!(game.chr_ram_size(), None);
+        assert!(game.battery);
+        assert_eq!(game.mirroring, Mirroring::SingleScreenA);
+        assert_eq!(game.title, "Game, The (Europe).nes");
+
+        assert!(GameInfo::parse("crc32:0000002A,NTSC,,,,1").is_err());
+        assert!(GameInfo::parse("42,NTSC,,,,1,8,16,0,2,true,Vertical,0,title").is_err());
+        assert!(GameInfo::parse("crc32:XYZ,NTSC,,,,1,8,16,0,2,true,Vertical,0,title").is_err());
+        assert!(
+            GameInfo::parse("crc32:0000002A,NTSC,,,,1,8,16,0,2,true,Diagonal,0,title").is_err()
+        );
+    }
+
+    #[test]
+    fn load_db() {
+        let db = GameDb::load(
+            "# comment\n\ncrc32:00000007,NTSC,,,,4,8,0,1,0,false,Vertical,0,\"a\"\n".as_bytes(),
+        )
+        .expect("valid db");
+        assert_eq!(db.len(), 1);
+        assert_eq!(
+            db.get(GameKey::Crc32(7)).map(|game| game.mapper_num),
+            Some(4)
+        );
+        assert!(GameDb::load("crc32:00000007,NTSC".as_bytes()).is_err());
+
+        assert!(GameDb::load(GAME_DB).is_ok(), "valid builtin db");
+    }
+
+    #[test]
+    fn lookup_by_checksum() {
+        let prg_rom = [0xEA; 0x4000];
+        let chr_rom = [0x00; 0x2000];
+        let hashes = RomHashes::from_chunks(&[&prg_rom, &chr_rom]);
+        let other = RomHashes::new(&prg_rom);
+        let crc32 = format!(
+            "crc32:{},NTSC,,,,2,1,1,0,0,false,Vertical,0,\"crc32\"\n",
+            hashes.crc32_hex().to_lowercase()
+        );
+        let db = GameDb::load(crc32.as_bytes()).expect("valid db");
+        assert_eq!(db.lookup(&hashes).map(|game| game.mapper_num), Some(2));
+        assert!(db.lookup(&other).is_none());
+
+        // SHA-1 is preferred over CRC32
+        let db = GameDb::load(
+            format!(
+                "{crc32}sha1:{},NTSC,,,,3,1,1,0,0,false,Vertical,0,\"sha1\"\n",
+                hashes.sha1_hex()
+            )
+            .as_bytes(),
+        )
+        .expect("valid db");
+        assert_eq!(db.lookup(&hashes).map(|game| game.mapper_num), Some(3));
+        assert!(db.lookup(&other).is_none());
+    }
+}
@@ -0,0 +1,125 @@
+//! Standard ROM checksums used by public ROM databases.
+//!
+//! No-Intro and NesCartDB identify dumps by the CRC32, MD5 and SHA-1 of headerless ROM data,
+//! which stay stable across builds unlike the hash used by the builtin game database.
+
+use crate::NesResult;
+use anyhow::{anyhow, bail};
+use flate2::Crc;
+use md5::{Digest, Md5};
+use sha1::Sha1;
+
+/// CRC32, MD5 and SHA-1 checksums of ROM data.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[must_use]
+pub struct RomHashes {
+    pub crc32: u32,
+    pub md5: [u8; 16],
+    pub sha1: [u8; 20],
+}
+
+impl RomHashes {
+    /// Compute checksums of `data`.
+    pub fn new(data: &[u8]) -> Self {
+        Self::from_chunks(&[data])
+    }
+
+    /// Compute checksums of `chunks` as if they were one contiguous buffer, such as PRG-ROM
+    /// followed by CHR-ROM.
+    pub fn from_chunks(chunks: &[&[u8]]) -> Self {
+        let mut crc = Crc::new();
+        let mut md5 = Md5::new();
+        let mut sha1 = Sha1::new();
+        for chunk in chunks {
+            crc.update(chunk);
+            md5.update(chunk);
+            sha1.update(chunk);
+        }
+        Self {
+            crc32: crc.sum(),
+            md5: md5.finalize().into(),
+            sha1: sha1.finalize().into(),
+        }
+    }
+
+    /// CRC32 as 8 uppercase hex digits, the format used by ROM databases.
+    #[must_use]
+    pub fn crc32_hex(&self) -> String {
+        format!("{:08X}", self.crc32)
+    }
+
+    #[must_use]
+    pub fn md5_hex(&self) -> String {
+        to_hex(&self.md5)
+    }
+
+    #[must_use]
+    pub fn sha1_hex(&self) -> String {
+        to_hex(&self.sha1)
+    }
+}
+
+impl std::fmt::Display for RomHashes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "CRC32: {}, MD5: {}, SHA-1: {}",
+            self.crc32_hex(),
+            self.md5_hex(),
+            self.sha1_hex()
+        )
+    }
+}
+
+/// Format bytes as uppercase hex digits.
+#[must_use]
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02X}")).collect()
+}
+
+/// Parse a hex digest of exactly `N` bytes, ignoring case.
+///
+/// # Errors
+///
+/// If `hex` has the wrong length or isn't valid hex, then an error is returned.
+pub fn from_hex<const N: usize>(hex: &str) -> NesResult<[u8; N]> {
+    if hex.len() != N * 2 || !hex.bytes().all(|digit| digit.is_ascii_hexdigit()) {
+        bail!("invalid hex digest {hex:?}. expected {} digits", N * 2);
+    }
+    let mut bytes = [0x00; N];
+    for (byte, digits) in bytes.iter_mut().zip(hex.as_bytes().chunks_exact(2)) {
+        let digits = std::str::from_utf8(digits)?;
+        *byte =
+            u8::from_str_radix(digits, 16).map_err(|_| anyhow!("invalid hex digest {hex:?}"))?;
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rom_hashes() {
+        let hashes = RomHashes::new(b"The quick brown fox jumps over the lazy dog");
+        assert_eq!(hashes.crc32_hex(), "414FA339");
+        assert_eq!(hashes.md5_hex(), "9E107D9D372BB6826BD81D3542A419D6");
+        assert_eq!(
+            hashes.sha1_hex(),
+            "2FD4E1C67A2D28FCED849EE1BB76E7391B93EB12"
+        );
+        assert_eq!(
+            RomHashes::from_chunks(&[
+                b"The quick brown fox ".as_slice(),
+                b"jumps over the lazy dog"
+            ]),
+            hashes
+        );
+        assert_eq!(
+            from_hex::<16>(&hashes.md5_hex().to_lowercase()).ok(),
+            Some(hashes.md5)
+        );
+        assert!(from_hex::<16>("9E107D").is_err());
+        assert!(from_hex::<2>("zz00").is_err());
+    }
+}
@@ -0,0 +1,577 @@
+//! ROM soft-patching.
+//!
+//! Translations and ROM hacks are distributed as IPS, UPS or BPS patches which are applied to the
+//! original ROM data at load time, leaving the ROM file untouched.
+//!
+//! <https://zerosoft.zophar.net/ips.php>
+//! <https://www.romhacking.net/documents/392/> (UPS)
+//! <https://www.romhacking.net/documents/746/> (BPS)
+
+use crate::NesResult;
+use anyhow::{bail, Context};
+use flate2::Crc;
+use std::{fs, path::Path};
+
+const IPS_MAGIC: &[u8] = b"PATCH";
+const IPS_EOF: &[u8] = b"EOF";
+const UPS_MAGIC: &[u8] = b"UPS1";
+const BPS_MAGIC: &[u8] = b"BPS1";
+// Source, target and patch CRC32s
+const FOOTER_SIZE: usize = 12;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[must_use]
+pub enum PatchKind {
+    Ips,
+    Ups,
+    Bps,
+}
+
+impl PatchKind {
+    /// Detect the patch format from the magic bytes at the start of `data`.
+    #[must_use]
+    pub fn detect(data: &[u8]) -> Option<Self> {
+        if data.starts_with(IPS_MAGIC) {
+            Some(Self::Ips)
+        } else if data.starts_with(UPS_MAGIC) {
+            Some(Self::Ups)
+        } else if data.starts_with(BPS_MAGIC) {
+            Some(Self::Bps)
+        } else {
+            None
+        }
+    }
+}
+
+/// An IPS, UPS or BPS patch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[must_use]
+pub struct Patch {
+    kind: PatchKind,
+    data: Vec<u8>,
+}
+
+impl Patch {
+    /// Create a `Patch` from patch data.
+    ///
+    /// # Errors
+    ///
+    /// If `data` is not a supported patch format, then an error is returned.
+    pub fn new(data: Vec<u8>) -> NesResult<Self> {
+        let Some(kind) = PatchKind::detect(&data) else {
+            bail!("unsupported patch format. expected ips, ups or bps");
+        };
+        Ok(Self { kind, data })
+    }
+
+    /// Load a `Patch` from a file path.
+    ///
+    /// # Errors
+    ///
+    /// If the file cannot be read or is not a supported patch format, then an error is returned.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> NesResult<Self> {
+        let path = path.as_ref();
+        let data = fs::read(path).with_context(|| format!("failed to read patch {path:?}"))?;
+        Self::new(data)
+    }
+
+    #[inline]
+    pub const fn kind(&self) -> PatchKind {
+        self.kind
+    }
+
+    /// Raw patch data, suitable for writing to a patch file.
+    #[inline]
+    #[must_use]
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Apply the patch to `rom`, returning the patched ROM.
+    ///
+    /// # Errors
+    ///
+    /// If the patch is invalid, or a UPS or BPS checksum does not match, then an error is
+    /// returned.
+    pub fn apply(&self, rom: &[u8]) -> NesResult<Vec<u8>> {
+        match self.kind {
+            PatchKind::Ips => {
+                let mut rom = rom.to_vec();
+                Ips::load(&self.data)?.apply(&mut rom);
+                Ok(rom)
+            }
+            PatchKind::Ups => self.apply_ups(rom),
+            PatchKind::Bps => self.apply_bps(rom),
+        }
+    }
+
+    /// Create an IPS patch that turns `original` into `modified`.
+    ///
+    /// # Errors
+    ///
+    /// If `modified` is larger than the 16MB IPS can address, then an error is returned.
+    pub fn create_ips(original: &[u8], modified: &[u8]) -> NesResult<Self> {
+        if modified.len() > Ips::MAX_SIZE {
+            bail!(
+                "rom is too large for an ips patch: {} bytes, max {} bytes",
+                modified.len(),
+                Ips::MAX_SIZE
+            );
+        }
+        Self::new(Ips::new(original, modified).to_bytes())
+    }
+
+    /// Create a BPS patch that turns `original` into `modified`. Runs that are unchanged at the
+    /// same offset are read from the original, everything else is stored in the patch.
+    pub fn create_bps(original: &[u8], modified: &[u8]) -> Self {
+        let mut data = BPS_MAGIC.to_vec();
+        encode_number(original.len() as u64, &mut data);
+        encode_number(modified.len() as u64, &mut data);
+        encode_number(0, &mut data); // No metadata
+
+        let unchanged = |i: usize| original.get(i) == modified.get(i);
+        let mut pos = 0;
+        while pos < modified.len() {
+            let source_read = unchanged(pos);
+            let len = modified[pos..]
+                .iter()
+                .enumerate()
+                .take_while(|(i, _)| unchanged(pos + i) == source_read)
+                .count();
+            if source_read {
+                encode_number(((len as u64 - 1) << 2) | BpsAction::SOURCE_READ, &mut data);
+            } else {
+                encode_number(((len as u64 - 1) << 2) | BpsAction::TARGET_READ, &mut data);
+                data.extend_from_slice(&modified[pos..pos + len]);
+            }
+            pos += len;
+        }
+
+        data.extend_from_slice(&crc32(original).to_le_bytes());
+        data.extend_from_slice(&crc32(modified).to_le_bytes());
+        let patch_crc = crc32(&data);
+        data.extend_from_slice(&patch_crc.to_le_bytes());
+        Self {
+            kind: PatchKind::Bps,
+            data,
+        }
+    }
+
+    // Validate the patch checksum and split the patch into its body and source and target
+    // checksums.
+    fn split_footer(&self) -> NesResult<(&[u8], u32, u32)> {
+        if self.data.len() < FOOTER_SIZE + 4 {
+            bail!("invalid {:?} patch: too short", self.kind);
+        }
+        let (body, footer) = self.data.split_at(self.data.len() - FOOTER_SIZE);
+        let crc =
+            |i: usize| u32::from_le_bytes([footer[i], footer[i + 1], footer[i + 2], footer[i + 3]]);
+        let (source_crc, target_crc, patch_crc) = (crc(0), crc(4), crc(8));
+        if crc32(&self.data[..self.data.len() - 4]) != patch_crc {
+            bail!("invalid {:?} patch: patch checksum mismatch", self.kind);
+        }
+        Ok((body, source_crc, target_crc))
+    }
+
+    fn apply_ups(&self, rom: &[u8]) -> NesResult<Vec<u8>> {
+        let (body, source_crc, target_crc) = self.split_footer()?;
+        let mut pos = UPS_MAGIC.len();
+        let mut source_size = decode_number(body, &mut pos)?;
+        let mut target_size = decode_number(body, &mut pos)?;
+
+        // UPS patches can be applied in either direction
+        let rom_crc = crc32(rom);
+        let (expected_crc, reverse) = if rom_crc == source_crc {
+            (target_crc, false)
+        } else if rom_crc == target_crc && rom.len() == target_size {
+            (source_crc, true)
+        } else {
+            bail!(
+                "ups patch source checksum mismatch. expected {source_crc:08X}, found {rom_crc:08X}"
+            );
+        };
+        if reverse {
+            std::mem::swap(&mut source_size, &mut target_size);
+        }
+        if rom.len() != source_size {
+            bail!(
+                "ups patch source size mismatch. expected {source_size}, found {}",
+                rom.len()
+            );
+        }
+
+        let mut target = rom.to_vec();
+        target.resize(target_size, 0x00);
+        let mut offset = 0;
+        while pos < body.len() {
+            offset += decode_number(body, &mut pos)?;
+            loop {
+                let Some(byte) = body.get(pos) else {
+                    bail!("invalid ups patch: truncated hunk");
+                };
+                pos += 1;
+                if *byte == 0x00 {
+                    break;
+                }
+                if let Some(target) = target.get_mut(offset) {
+                    *target ^= byte;
+                }
+                offset += 1;
+            }
+            offset += 1;
+        }
+
+        let crc = crc32(&target);
+        if crc != expected_crc {
+            bail!(
+                "ups patch target checksum mismatch. expected {expected_crc:08X}, found {crc:08X}"
+            );
+        }
+        Ok(target)
+    }
+
+    fn apply_bps(&self, rom: &[u8]) -> NesResult<Vec<u8>> {
+        let (body, source_crc, target_crc) = self.split_footer()?;
+        let rom_crc = crc32(rom);
+        if rom_crc != source_crc {
+            bail!("bps patch source checksum mismatch. expected {source_crc:08X}, found {rom_crc:08X}");
+        }
+        let mut pos = BPS_MAGIC.len();
+        let source_size = decode_number(body, &mut pos)?;
+        let target_size = decode_number(body, &mut pos)?;
+        if rom.len() != source_size {
+            bail!(
+                "bps patch source size mismatch. expected {source_size}, found {}",
+                rom.len()
+            );
+        }
+        pos += decode_number(body, &mut pos)?; // Skip metadata
+
+        let mut target = Vec::with_capacity(target_size);
+        let mut source_offset = 0usize;
+        let mut target_offset = 0usize;
+        while pos < body.len() {
+            let action = decode_number(body, &mut pos)?;
+            let len = (action >> 2) + 1;
+            let out = target.len();
+            match (action & 0x03) as u64 {
+                BpsAction::SOURCE_READ => {
+                    let Some(data) = rom.get(out..out + len) else {
+                        bail!("invalid bps patch: source read out of bounds");
+                    };
+                    target.extend_from_slice(data);
+                }
+                BpsAction::TARGET_READ => {
+                    let Some(data) = body.get(pos..pos + len) else {
+                        bail!("invalid bps patch: truncated target read");
+                    };
+                    target.extend_from_slice(data);
+                    pos += len;
+                }
+                BpsAction::SOURCE_COPY => {
+                    source_offset = relative_offset(source_offset, body, &mut pos)?;
+                    let Some(data) = rom.get(source_offset..source_offset + len) else {
+                        bail!("invalid bps patch: source copy out of bounds");
+                    };
+                    target.extend_from_slice(data);
+                    source_offset += len;
+                }
+                _ => {
+                    target_offset = relative_offset(target_offset, body, &mut pos)?;
+                    if target_offset >= out {
+                        bail!("invalid bps patch: target copy out of bounds");
+                    }
+                    // Copies can overlap the output, repeating earlier bytes
+                    for _ in 0..len {
+                        target.push(target[target_offset]);
+                        target_offset += 1;
+                    }
+                }
+            }
+        }
+
+        if target.len() != target_size {
+            bail!(
+                "bps patch target size mismatch. expected {target_size}, found {}",
+                target.len()
+            );
+        }
+        let crc = crc32(&target);
+        if crc != target_crc {
+            bail!("bps patch target checksum mismatch. expected {target_crc:08X}, found {crc:08X}");
+        }
+        Ok(target)
+    }
+}
+
+struct BpsAction;
+
+impl BpsAction {
+    const SOURCE_READ: u64 = 0;
+    const TARGET_READ: u64 = 1;
+    const SOURCE_COPY: u64 = 2;
+}
+
+/// IPS records, shared by ROM patches and Famicom Disk System disk saves.
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+#[must_use]
+pub(crate) struct Ips {
+    // (offset, data) records
+    records: Vec<(usize, Vec<u8>)>,
+    // Truncate extension, used when the modified data is smaller
+    truncate: Option<usize>,
+}
+
+impl Ips {
+    // Offsets are 24-bit
+    const MAX_SIZE: usize = 0x0100_0000;
+    const MAX_RECORD: usize = 0xFFFF;
+    // A record at this offset would be read as `EOF`
+    const EOF_OFFSET: usize = 0x0045_4F46;
+
+    /// Diff `original` and `modified`, which must be no larger than 16MB.
+    pub(crate) fn new(original: &[u8], modified: &[u8]) -> Self {
+        let mut records: Vec<(usize, Vec<u8>)> = vec![];
+        for (offset, byte) in modified.iter().enumerate() {
+            if original.get(offset) == Some(byte) {
+                continue;
+            }
+            match records.last_mut() {
+                Some((start, data))
+                    if *start + data.len() == offset && data.len() < Self::MAX_RECORD =>
+                {
+                    data.push(*byte);
+                }
+                _ if offset == Self::EOF_OFFSET => {
+                    records.push((offset - 1, modified[offset - 1..=offset].to_vec()));
+                }
+                _ => records.push((offset, vec![*byte])),
+            }
+        }
+        let truncate = (modified.len() < original.len()).then_some(modified.len());
+        Self { records, truncate }
+    }
+
+    /// Parse IPS patch data.
+    pub(crate) fn load(mut patch: &[u8]) -> NesResult<Self> {
+        let Some(rest) = patch.strip_prefix(IPS_MAGIC) else {
+            bail!("invalid ips patch: missing header");
+        };
+        patch = rest;
+        let mut records = vec![];
+        while !patch.starts_with(IPS_EOF) {
+            let [o1, o2, o3, s1, s2, rest @ ..] = patch else {
+                bail!("invalid ips patch: truncated record");
+            };
+            let offset = usize::from(*o1) << 16 | usize::from(*o2) << 8 | usize::from(*o3);
+            let size = usize::from(*s1) << 8 | usize::from(*s2);
+            let (data, rest) = if size == 0 {
+                // RLE record
+                let [r1, r2, value, rest @ ..] = rest else {
+                    bail!("invalid ips patch: truncated rle record");
+                };
+                let size = usize::from(*r1) << 8 | usize::from(*r2);
+                (vec![*value; size], rest)
+            } else {
+                if rest.len() < size {
+                    bail!("invalid ips patch: truncated record");
+                }
+                let (data, rest) = rest.split_at(size);
+                (data.to_vec(), rest)
+            };
+            records.push((offset, data));
+            patch = rest;
+        }
+        let truncate = match patch[IPS_EOF.len()..] {
+            [t1, t2, t3, ..] => {
+                Some(usize::from(t1) << 16 | usize::from(t2) << 8 | usize::from(t3))
+            }
+            _ => None,
+        };
+        Ok(Self { records, truncate })
+    }
+
+    #[inline]
+    #[must_use]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.records.is_empty() && self.truncate.is_none()
+    }
+
+    /// Apply records to `data`, growing it as needed.
+    pub(crate) fn apply(&self, data: &mut Vec<u8>) {
+        for (offset, record) in &self.records {
+            let end = offset + record.len();
+            if end > data.len() {
+                data.resize(end, 0x00);
+            }
+            data[*offset..end].copy_from_slice(record);
+        }
+        if let Some(len) = self.truncate {
+            data.truncate(len);
+        }
+    }
+
+    /// Serialize as IPS patch data.
+    #[must_use]
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut patch = IPS_MAGIC.to_vec();
+        for (offset, data) in &self.records {
+            patch.extend_from_slice(&(*offset as u32).to_be_bytes()[1..]);
+            patch.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            patch.extend_from_slice(data);
+        }
+        patch.extend_from_slice(IPS_EOF);
+        if let Some(len) = self.truncate {
+            patch.extend_from_slice(&(len as u32).to_be_bytes()[1..]);
+        }
+        patch
+    }
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc::new();
+    crc.update(data);
+    crc.sum()
+}
+
+// UPS and BPS variable-length number, where each byte holds 7 bits and the high bit marks the
+// last byte.
+fn decode_number(data: &[u8], pos: &mut usize) -> NesResult<usize> {
+    let mut value = 0u64;
+    let mut shift = 1u64;
+    loop {
+        let Some(byte) = data.get(*pos) else {
+            bail!("invalid patch: truncated number");
+        };
+        *pos += 1;
+        value = u64::from(byte & 0x7F)
+            .checked_mul(shift)
+            .and_then(|n| n.checked_add(value))
+            .context("invalid patch: number overflow")?;
+        if byte & 0x80 == 0x80 {
+            break;
+        }
+        shift = shift
+            .checked_shl(7)
+            .context("invalid patch: number overflow")?;
+        value = value
+            .checked_add(shift)
+            .context("invalid patch: number overflow")?;
+    }
+    usize::try_from(value).context("invalid patch: number overflow")
+}
+
+fn encode_number(mut value: u64, data: &mut Vec<u8>) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            data.push(0x80 | byte);
+            break;
+        }
+        data.push(byte);
+        value -= 1;
+    }
+}
+
+// BPS copy offset, stored as a signed distance from the previous copy.
+fn relative_offset(offset: usize, data: &[u8], pos: &mut usize) -> NesResult<usize> {
+    let value = decode_number(data, pos)?;
+    let distance = value >> 1;
+    let offset = if value & 0x01 == 0x01 {
+        offset.checked_sub(distance)
+    } else {
+        offset.checked_add(distance)
+    };
+    offset.context("invalid bps patch: copy offset out of bounds")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roms() -> (Vec<u8>, Vec<u8>) {
+        let original = (0..=255).cycle().take(0x4000).collect::<Vec<u8>>();
+        let mut modified = original.clone();
+        modified[0x10..0x20].fill(0xEA);
+        modified[0x3FFF] = 0x00;
+        modified.extend([0x01, 0x02, 0x03]);
+        (original, modified)
+    }
+
+    #[test]
+    fn ips_patch() {
+        let (original, modified) = roms();
+        let patch = Patch::create_ips(&original, &modified).expect("valid patch");
+        assert_eq!(patch.kind(), PatchKind::Ips);
+        assert_eq!(patch.apply(&original).expect("patched"), modified);
+
+        let truncate = Patch::create_ips(&modified, &original).expect("valid patch");
+        assert_eq!(truncate.apply(&modified).expect("patched"), original);
+
+        #[rustfmt::skip]
+        let rle = Patch::new(vec![
+            b'P', b'A', b'T', b'C', b'H',
+            0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x03, 0xFF,
+            b'E', b'O', b'F',
+        ])
+        .expect("valid patch");
+        assert_eq!(
+            rle.apply(&[0x00; 6]).expect("patched"),
+            [0, 0, 0xFF, 0xFF, 0xFF, 0]
+        );
+    }
+
+    #[test]
+    fn bps_patch() {
+        let (original, modified) = roms();
+        let patch = Patch::create_bps(&original, &modified);
+        assert_eq!(
+            Patch::new(patch.data().to_vec()).expect("valid patch"),
+            patch
+        );
+        assert_eq!(patch.apply(&original).expect("patched"), modified);
+        assert!(patch.apply(&modified).is_err());
+
+        let mut corrupted = patch.data().to_vec();
+        corrupted[10] ^= 0xFF;
+        let corrupted = Patch::new(corrupted).expect("valid header");
+        assert!(corrupted.apply(&original).is_err());
+    }
+
+    #[test]
+    fn ups_patch() {
+        // Change byte 1 from 0x01 to 0x10, and append 0x05
+        let original = [0x00, 0x01, 0x02, 0x03];
+        let modified = [0x00, 0x10, 0x02, 0x03, 0x05];
+        let mut data = UPS_MAGIC.to_vec();
+        encode_number(original.len() as u64, &mut data);
+        encode_number(modified.len() as u64, &mut data);
+        encode_number(1, &mut data);
+        data.extend([0x01 ^ 0x10, 0x00]);
+        encode_number(1, &mut data);
+        data.extend([0x05, 0x00]);
+        data.extend(crc32(&original).to_le_bytes());
+        data.extend(crc32(&modified).to_le_bytes());
+        data.extend(crc32(&data).to_le_bytes());
+
+        let patch = Patch::new(data).expect("valid patch");
+        assert_eq!(patch.kind(), PatchKind::Ups);
+        assert_eq!(patch.apply(&original).expect("patched"), modified);
+        assert!(patch.apply(&[0xFF; 4]).is_err());
+    }
+
+    #[test]
+    fn number_encoding() {
+        for value in [0, 1, 127, 128, 255, 16_383, 16_384, 0x0100_0000] {
+            let mut data = vec![];
+            encode_number(value, &mut data);
+            let mut pos = 0;
+            assert_eq!(
+                decode_number(&data, &mut pos).expect("valid number"),
+                value as usize
+            );
+            assert_eq!(pos, data.len());
+        }
+    }
+}
@@ -0,0 +1,169 @@
+//! PlayChoice-10 cartridge data.
+//!
+//! PlayChoice-10 dumps append an 8K INST-ROM, holding the title and instruction screens shown by
+//! the menu CPU, and a 32 byte PROM used as a security key, after CHR-ROM.
+//!
+//! <https://www.nesdev.org/wiki/PlayChoice-10>
+//! <https://www.nesdev.org/wiki/INES#PlayChoice_INST-ROM>
+
+use crate::NesResult;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::{io::Read, time::Duration};
+
+/// INST-ROM and PROM data of a PlayChoice-10 cartridge.
+#[derive(Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[must_use]
+pub struct PlayChoice {
+    inst_rom: Vec<u8>,
+    prom: Vec<u8>,
+}
+
+impl PlayChoice {
+    pub const INST_ROM_SIZE: usize = 8 * 1024;
+    pub const PROM_SIZE: usize = 32;
+
+    // Shortest run of characters considered text.
+    const MIN_TEXT_LEN: usize = 4;
+
+    /// Load the INST-ROM and PROM following CHR-ROM. Returns `None` if the INST-ROM is missing,
+    /// which is common for dumps that set the PlayChoice-10 flag but omit the extra data. The
+    /// PROM is optional.
+    ///
+    /// # Errors
+    ///
+    /// If reading the ROM data fails, then an error is returned.
+    pub fn load<F: Read>(rom_data: &mut F) -> NesResult<Option<Self>> {
+        let mut data = Vec::with_capacity(Self::INST_ROM_SIZE + Self::PROM_SIZE);
+        rom_data
+            .take((Self::INST_ROM_SIZE + Self::PROM_SIZE) as u64)
+            .read_to_end(&mut data)
+            .context("failed to read playchoice data")?;
+        if data.len() < Self::INST_ROM_SIZE {
+            log::warn!(
+                "missing playchoice inst-rom, expected {} bytes after chr-rom but found {}",
+                Self::INST_ROM_SIZE,
+                data.len()
+            );
+            return Ok(None);
+        }
+        let prom = data.split_off(Self::INST_ROM_SIZE);
+        if !prom.is_empty() && prom.len() < Self::PROM_SIZE {
+            log::warn!("truncated playchoice prom: {} bytes", prom.len());
+        }
+        Ok(Some(Self {
+            inst_rom: data,
+            prom,
+        }))
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn inst_rom(&self) -> &[u8] {
+        &self.inst_rom
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn prom(&self) -> &[u8] {
+        &self.prom
+    }
+
+    /// Title and instruction text found in the INST-ROM, one line per run of printable ASCII.
+    #[must_use]
+    pub fn instructions(&self) -> String {
+        self.inst_rom
+            .split(|byte| !(byte.is_ascii_graphic() || *byte == b' '))
+            .map(|line| String::from_utf8_lossy(line).trim().to_string())
+            .filter(|line| line.len() >= Self::MIN_TEXT_LEN)
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+impl std::fmt::Debug for PlayChoice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        f.debug_struct("PlayChoice")
+            .field("inst_rom_len", &self.inst_rom.len())
+            .field("prom_len", &self.prom.len())
+            .finish()
+    }
+}
+
+/// Time limit bought by each credit on a PlayChoice-10. Play stops once the time runs out until
+/// another credit is inserted.
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[must_use]
+pub struct PlayTimer {
+    credit: Duration,
+    remaining: Duration,
+}
+
+impl PlayTimer {
+    /// Create a timer with no time remaining, granting `credit` of play time per credit.
+    pub const fn new(credit: Duration) -> Self {
+        Self {
+            credit,
+            remaining: Duration::ZERO,
+        }
+    }
+
+    #[inline]
+    #[must_use]
+    pub const fn credit(&self) -> Duration {
+        self.credit
+    }
+
+    #[inline]
+    #[must_use]
+    pub const fn remaining(&self) -> Duration {
+        self.remaining
+    }
+
+    #[inline]
+    pub fn insert_credit(&mut self) {
+        self.remaining += self.credit;
+    }
+
+    /// Count down by `elapsed`, returning whether any time was remaining.
+    pub fn tick(&mut self, elapsed: Duration) -> bool {
+        if self.remaining.is_zero() {
+            false
+        } else {
+            self.remaining = self.remaining.saturating_sub(elapsed);
+            true
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_inst_rom() {
+        let mut data = vec![0xFF; PlayChoice::INST_ROM_SIZE + PlayChoice::PROM_SIZE];
+        data[0x10..0x1A].copy_from_slice(b"SUPER GAME");
+        data[0x40..0x43].copy_from_slice(b"ABC");
+        let playchoice = PlayChoice::load(&mut data.as_slice())
+            .expect("valid data")
+            .expect("inst-rom");
+        assert_eq!(playchoice.inst_rom().len(), PlayChoice::INST_ROM_SIZE);
+        assert_eq!(playchoice.prom().len(), PlayChoice::PROM_SIZE);
+        assert_eq!(playchoice.instructions(), "SUPER GAME");
+
+        let missing = PlayChoice::load(&mut [0x00; 16].as_slice()).expect("valid data");
+        assert!(missing.is_none());
+    }
+
+    #[test]
+    fn play_timer() {
+        let frame = Duration::from_millis(500);
+        let mut timer = PlayTimer::new(Duration::from_secs(1));
+        assert!(!timer.tick(frame));
+        timer.insert_credit();
+        assert!(timer.tick(frame));
+        assert!(timer.tick(frame));
+        assert!(!timer.tick(frame));
+    }
+}
@@ -0,0 +1,279 @@
+//! Public ROM databases used to identify dumps by checksum.
+//!
+//! Supports No-Intro DAT files (Logiqx XML) and the NesCartDB XML export. Both hash headerless
+//! PRG-ROM followed by CHR-ROM, see `Cart::rom_hashes`.
+//!
+//! <https://datomatic.no-intro.org>
+//! <https://nescartdb.com>
+
+use super::hash::{from_hex, RomHashes};
+use crate::{common::NesRegion, NesResult};
+use anyhow::{bail, Context};
+use roxmltree::{Document, Node};
+use std::{collections::HashMap, fs, path::Path};
+
+/// Database a `RomInfo` was loaded from.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[must_use]
+pub enum RomDbKind {
+    NoIntro,
+    NesCartDb,
+}
+
+/// A ROM database entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[must_use]
+pub struct RomInfo {
+    pub title: String,
+    pub crc32: u32,
+    pub md5: Option<[u8; 16]>,
+    pub sha1: Option<[u8; 20]>,
+    pub board: Option<String>,
+    pub pcb: Option<String>,
+    pub mapper_num: Option<u16>,
+    pub region: Option<NesRegion>,
+}
+
+impl RomInfo {
+    /// Returns whether this entry matches `hashes`. The CRC32 must match, as well as any other
+    /// checksums the entry has.
+    #[must_use]
+    pub fn matches(&self, hashes: &RomHashes) -> bool {
+        self.crc32 == hashes.crc32
+            && self.md5.map_or(true, |md5| md5 == hashes.md5)
+            && self.sha1.map_or(true, |sha1| sha1 == hashes.sha1)
+    }
+}
+
+/// A ROM database keyed by CRC32.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[must_use]
+pub struct RomDb {
+    kind: RomDbKind,
+    roms: HashMap<u32, Vec<RomInfo>>,
+}
+
+impl RomDb {
+    /// Load a No-Intro DAT or NesCartDB XML database, detected by the root element.
+    ///
+    /// # Errors
+    ///
+    /// If the XML is invalid, the format isn't recognized, or an entry has an invalid checksum,
+    /// then an error is returned.
+    pub fn load(xml: &str) -> NesResult<Self> {
+        let doc = Document::parse(xml).context("invalid rom database xml")?;
+        let root = doc.root_element();
+        match root.tag_name().name() {
+            "datafile" => Self::load_no_intro(root),
+            "database" => Self::load_nes_cart_db(root),
+            tag => bail!("unrecognized rom database root element <{tag}>"),
+        }
+    }
+
+    /// Load a No-Intro DAT or NesCartDB XML database from a file path.
+    ///
+    /// # Errors
+    ///
+    /// If the file cannot be read or is not a valid database, then an error is returned.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> NesResult<Self> {
+        let path = path.as_ref();
+        let xml = fs::read_to_string(path)
+            .with_context(|| format!("failed to read rom database {path:?}"))?;
+        Self::load(&xml).with_context(|| format!("failed to load rom database {path:?}"))
+    }
+
+    #[inline]
+    pub const fn kind(&self) -> RomDbKind {
+        self.kind
+    }
+
+    /// Find the entry matching `hashes`.
+    #[must_use]
+    pub fn find(&self, hashes: &RomHashes) -> Option<&RomInfo> {
+        self.roms
+            .get(&hashes.crc32)?
+            .iter()
+            .find(|rom| rom.matches(hashes))
+    }
+
+    /// Number of entries.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.roms.values().map(Vec::len).sum()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.roms.is_empty()
+    }
+
+    // <datafile>
+    //   <game name="Title (USA)">
+    //     <rom name="Title (USA).nes" size="40960" crc="..." md5="..." sha1="..."/>
+    //   </game>
+    // </datafile>
+    fn load_no_intro(root: Node<'_, '_>) -> NesResult<Self> {
+        let mut db = Self::new(RomDbKind::NoIntro);
+        for game in root.children().filter(|node| node.has_tag_name("game")) {
+            let title = game.attribute("name").unwrap_or_default();
+            for rom in game.children().filter(|node| node.has_tag_name("rom")) {
+                let Some(crc32) = Self::parse_crc32(rom, title)? else {
+                    continue;
+                };
+                db.insert(RomInfo {
+                    title: title.to_string(),
+                    crc32,
+                    md5: Self::parse_digest(rom, "md5", title)?,
+                    sha1: Self::parse_digest(rom, "sha1", title)?,
+                    board: None,
+                    pcb: None,
+                    mapper_num: None,
+                    region: None,
+                });
+            }
+        }
+        Ok(db)
+    }
+
+    // <database>
+    //   <game name="Title" region="USA">
+    //     <cartridge system="NES-NTSC" crc="..." sha1="...">
+    //       <board type="NES-NROM-256" pcb="NES-NROM-256-04" mapper="0"/>
+    //     </cartridge>
+    //   </game>
+    // </database>
+    fn load_nes_cart_db(root: Node<'_, '_>) -> NesResult<Self> {
+        let mut db = Self::new(RomDbKind::NesCartDb);
+        for game in root.children().filter(|node| node.has_tag_name("game")) {
+            let title = game.attribute("name").unwrap_or_default();
+            for cart in game
+                .children()
+                .filter(|node| node.has_tag_name("cartridge"))
+            {
+                let Some(crc32) = Self::parse_crc32(cart, title)? else {
+                    continue;
+                };
+                let board = cart.children().find(|node| node.has_tag_name("board"));
+                let region = match cart.attribute("system") {
+                    Some("NES-NTSC" | "Famicom") => Some(NesRegion::Ntsc),
+                    Some(system) if system.starts_with("NES-PAL") => Some(NesRegion::Pal),
+                    Some("Dendy") => Some(NesRegion::Dendy),
+                    _ => None,
+                };
+                db.insert(RomInfo {
+                    title: title.to_string(),
+                    crc32,
+                    md5: None,
+                    sha1: Self::parse_digest(cart, "sha1", title)?,
+                    board: board
+                        .and_then(|board| board.attribute("type"))
+                        .map(ToString::to_string),
+                    pcb: board
+                        .and_then(|board| board.attribute("pcb"))
+                        .map(ToString::to_string),
+                    mapper_num: board
+                        .and_then(|board| board.attribute("mapper"))
+                        .and_then(|mapper| mapper.parse().ok()),
+                    region,
+                });
+            }
+        }
+        Ok(db)
+    }
+
+    fn new(kind: RomDbKind) -> Self {
+        Self {
+            kind,
+            roms: HashMap::new(),
+        }
+    }
+
+    fn insert(&mut self, rom: RomInfo) {
+        self.roms.entry(rom.crc32).or_default().push(rom);
+    }
+
+    fn parse_crc32(node: Node<'_, '_>, title: &str) -> NesResult<Option<u32>> {
+        node.attribute("crc")
+            .map(|crc| {
+                u32::from_str_radix(crc, 16)
+                    .with_context(|| format!("invalid crc {crc:?} for {title:?}"))
+            })
+            .transpose()
+    }
+
+    fn parse_digest<const N: usize>(
+        node: Node<'_, '_>,
+        attribute: &str,
+        title: &str,
+    ) -> NesResult<Option<[u8; N]>> {
+        node.attribute(attribute)
+            .map(|digest| {
+                from_hex(digest).with_context(|| format!("invalid {attribute} for {title:?}"))
+            })
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_intro_dat() {
+        let hashes = RomHashes::new(b"rom");
+        let xml = format!(
+            r#"<?xml version="1.0"?>
+            <datafile>
+                <header><name>Nintendo - Nintendo Entertainment System (Headerless)</name></header>
+                <game name="Game (USA)">
+                    <description>Game (USA)</description>
+                    <rom name="Game (USA).nes" size="3" crc="{}" md5="{}" sha1="{}"/>
+                </game>
+                <game name="Other (Japan)">
+                    <rom name="Other (Japan).nes" size="3" crc="{}"/>
+                </game>
+            </datafile>"#,
+            hashes.crc32_hex().to_lowercase(),
+            hashes.md5_hex(),
+            hashes.sha1_hex(),
+            hashes.crc32_hex(),
+        );
+        let db = RomDb::load(&xml).expect("valid dat");
+        assert_eq!(db.kind(), RomDbKind::NoIntro);
+        assert_eq!(db.len(), 2);
+        assert_eq!(
+            db.find(&hashes).map(|rom| rom.title.as_str()),
+            Some("Game (USA)")
+        );
+        assert_eq!(db.find(&RomHashes::new(b"other")), None);
+
+        assert!(RomDb::load("<datafile><game><rom crc=\"xyz\"/></game></datafile>").is_err());
+        assert!(RomDb::load("<softwarelist/>").is_err());
+    }
+
+    #[test]
+    fn nes_cart_db() {
+        let hashes = RomHashes::new(b"rom");
+        let xml = format!(
+            r#"<database version="1.0" conformance="strict">
+                <game name="Game" region="Europe">
+                    <cartridge system="NES-PAL-B" crc="{}" sha1="{}">
+                        <board type="NES-SNROM" pcb="NES-SNROM-05" mapper="1">
+                            <wram size="8k" battery="1"/>
+                        </board>
+                    </cartridge>
+                </game>
+            </database>"#,
+            hashes.crc32_hex(),
+            hashes.sha1_hex(),
+        );
+        let db = RomDb::load(&xml).expect("valid db");
+        assert_eq!(db.kind(), RomDbKind::NesCartDb);
+        let rom = db.find(&hashes).expect("matching entry");
+        assert_eq!(rom.title, "Game");
+        assert_eq!(rom.board.as_deref(), Some("NES-SNROM"));
+        assert_eq!(rom.pcb.as_deref(), Some("NES-SNROM-05"));
+        assert_eq!(rom.mapper_num, Some(1));
+        assert_eq!(rom.region, Some(NesRegion::Pal));
+    }
+}
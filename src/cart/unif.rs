@@ -0,0 +1,272 @@
+//! UNIF (Universal NES Image Format) ROM images.
+//!
+//! UNIF files identify the cartridge board by name instead of by mapper number, and store data
+//! as a series of tagged chunks following a 32 byte header.
+//!
+//! <https://www.nesdev.org/wiki/UNIF>
+
+use crate::{common::NesRegion, NesResult};
+use anyhow::{bail, Context};
+use std::io::Read;
+
+const MAGIC: &[u8] = b"UNIF";
+const HEADER_SIZE: usize = 32;
+// Number of numbered PRG/CHR chunks, `PRG0` through `PRGF`
+const ROM_CHUNKS: usize = 16;
+// Board name prefixes that don't affect hardware
+const BOARD_PREFIXES: [&str; 6] = ["NES-", "UNL-", "HVC-", "BTL-", "BMC-", "IREM-"];
+
+/// Nametable mirroring as stored in the `MIRR` chunk.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[must_use]
+pub enum UnifMirroring {
+    Horizontal,
+    Vertical,
+    SingleScreenA,
+    SingleScreenB,
+    FourScreen,
+    MapperControlled,
+}
+
+/// A UNIF ROM image.
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+#[must_use]
+pub struct Unif {
+    board: String,
+    name: Option<String>,
+    prg_rom: Vec<u8>,
+    chr_rom: Vec<u8>,
+    mirroring: Option<UnifMirroring>,
+    battery: bool,
+    region: Option<NesRegion>,
+}
+
+impl Unif {
+    /// Returns whether `data` starts with the UNIF signature.
+    #[must_use]
+    pub fn is_unif(data: &[u8]) -> bool {
+        data.starts_with(MAGIC)
+    }
+
+    /// Load a UNIF image, ignoring chunks that don't affect emulation.
+    ///
+    /// # Errors
+    ///
+    /// If the UNIF signature is missing, a chunk is truncated, or there is no `MAPR` or PRG
+    /// chunk, then an error is returned.
+    pub fn load<F: Read>(rom_data: &mut F) -> NesResult<Self> {
+        let mut data = vec![];
+        rom_data
+            .read_to_end(&mut data)
+            .context("failed to read unif rom")?;
+        if !Self::is_unif(&data) {
+            bail!("unif header signature not found");
+        } else if data.len() < HEADER_SIZE {
+            bail!("invalid unif header: expected {HEADER_SIZE} bytes");
+        }
+
+        let mut unif = Self::default();
+        let mut prg_chunks: [Vec<u8>; ROM_CHUNKS] = Default::default();
+        let mut chr_chunks: [Vec<u8>; ROM_CHUNKS] = Default::default();
+        let mut chunks = &data[HEADER_SIZE..];
+        while !chunks.is_empty() {
+            let [i1, i2, i3, i4, l1, l2, l3, l4, rest @ ..] = chunks else {
+                bail!("invalid unif rom: truncated chunk header");
+            };
+            let id = [*i1, *i2, *i3, *i4];
+            let len = u32::from_le_bytes([*l1, *l2, *l3, *l4]) as usize;
+            if rest.len() < len {
+                bail!(
+                    "invalid unif rom: chunk {:?} expected {} bytes, found {}",
+                    String::from_utf8_lossy(&id),
+                    len,
+                    rest.len()
+                );
+            }
+            let (chunk, rest) = rest.split_at(len);
+            match &id {
+                b"MAPR" => unif.board = Self::read_string(chunk),
+                b"NAME" => unif.name = Some(Self::read_string(chunk)),
+                b"MIRR" => {
+                    unif.mirroring = chunk.first().map(|mirroring| match mirroring {
+                        0 => UnifMirroring::Horizontal,
+                        1 => UnifMirroring::Vertical,
+                        2 => UnifMirroring::SingleScreenA,
+                        3 => UnifMirroring::SingleScreenB,
+                        4 => UnifMirroring::FourScreen,
+                        _ => UnifMirroring::MapperControlled,
+                    });
+                }
+                b"BATR" => unif.battery = chunk.first().map_or(true, |battery| *battery > 0),
+                b"TVCI" => {
+                    unif.region = chunk.first().and_then(|tv| match tv {
+                        0 => Some(NesRegion::Ntsc),
+                        1 => Some(NesRegion::Pal),
+                        _ => None,
+                    });
+                }
+                [b'P', b'R', b'G', index] | [b'C', b'H', b'R', index] => {
+                    let Some(index) = char::from(*index).to_digit(16) else {
+                        log::warn!(
+                            "skipping unknown unif chunk {:?}",
+                            String::from_utf8_lossy(&id)
+                        );
+                        chunks = rest;
+                        continue;
+                    };
+                    let chunks = if id[0] == b'P' {
+                        &mut prg_chunks
+                    } else {
+                        &mut chr_chunks
+                    };
+                    chunks[index as usize] = chunk.to_vec();
+                }
+                _ => log::debug!("skipping unif chunk {:?}", String::from_utf8_lossy(&id)),
+            }
+            chunks = rest;
+        }
+
+        if unif.board.is_empty() {
+            bail!("invalid unif rom: missing board name (MAPR chunk)");
+        }
+        unif.prg_rom = prg_chunks.concat();
+        unif.chr_rom = chr_chunks.concat();
+        if unif.prg_rom.is_empty() {
+            bail!("invalid unif rom: missing prg-rom (PRG chunks)");
+        }
+        Ok(unif)
+    }
+
+    /// Board name from the `MAPR` chunk.
+    #[inline]
+    #[must_use]
+    pub fn board(&self) -> &str {
+        &self.board
+    }
+
+    /// Internal game name from the `NAME` chunk.
+    #[inline]
+    #[must_use]
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn prg_rom(&self) -> &[u8] {
+        &self.prg_rom
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn chr_rom(&self) -> &[u8] {
+        &self.chr_rom
+    }
+
+    #[inline]
+    #[must_use]
+    pub const fn mirroring(&self) -> Option<UnifMirroring> {
+        self.mirroring
+    }
+
+    /// Returns whether the board has battery-backed Save RAM.
+    #[inline]
+    #[must_use]
+    pub const fn battery_backed(&self) -> bool {
+        self.battery
+    }
+
+    /// Returns the region from the `TVCI` chunk, or `None` if unspecified or it supports both.
+    #[inline]
+    #[must_use]
+    pub const fn region(&self) -> Option<NesRegion> {
+        self.region
+    }
+
+    /// Returns the `iNES` mapper and submapper numbers for the board, or `None` if the board isn't
+    /// implemented.
+    #[must_use]
+    pub fn mapper_num(&self) -> Option<(u16, u8)> {
+        let board = BOARD_PREFIXES
+            .iter()
+            .find_map(|prefix| self.board.strip_prefix(prefix))
+            .unwrap_or(&self.board);
+        let mapper = match board {
+            "NROM" | "NROM-128" | "NROM-256" | "RROM" | "RROM-128" | "SROM" | "HROM" => (0, 0),
+            "SAROM" | "SBROM" | "SCROM" | "SC1ROM" | "SEROM" | "SFROM" | "SGROM" | "SHROM"
+            | "SH1ROM" | "SIROM" | "SJROM" | "SKROM" | "SLROM" | "SL1ROM" | "SL2ROM" | "SL3ROM"
+            | "SLRROM" | "SMROM" | "SNROM" | "SOROM" | "SUROM" | "SXROM" => (1, 0),
+            "UNROM" | "UOROM" => (2, 0),
+            "CNROM" => (3, 0),
+            "TBROM" | "TEROM" | "TFROM" | "TGROM" | "TKROM" | "TLROM" | "TL1ROM" | "TL2ROM"
+            | "TNROM" | "TR1ROM" | "TSROM" | "TVROM" | "B4" | "HKROM" => (4, 0),
+            "EKROM" | "ELROM" | "ETROM" | "EWROM" => (5, 0),
+            "AMROM" | "ANROM" | "AN1ROM" | "AOROM" => (7, 0),
+            "PNROM" | "PEEOROM" => (9, 0),
+            "FJROM" | "FKROM" => (10, 0),
+            "GNROM" | "MHROM" => (66, 0),
+            "BTR" | "JLROM" | "JSROM" => (69, 0),
+            "CAMERICA-BF9093" => (71, 0),
+            "CAMERICA-BF9097" => (71, 1),
+            _ => return None,
+        };
+        Some(mapper)
+    }
+
+    // Null-terminated UTF-8 string.
+    fn read_string(chunk: &[u8]) -> String {
+        let end = chunk.iter().position(|b| *b == 0x00).unwrap_or(chunk.len());
+        String::from_utf8_lossy(&chunk[..end]).trim().to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(id: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut chunk = id.to_vec();
+        chunk.extend((data.len() as u32).to_le_bytes());
+        chunk.extend(data);
+        chunk
+    }
+
+    #[test]
+    fn load_unif() {
+        let mut data = MAGIC.to_vec();
+        data.extend(7u32.to_le_bytes());
+        data.resize(HEADER_SIZE, 0x00);
+        data.extend(chunk(b"MAPR", b"NES-SLROM\0"));
+        data.extend(chunk(b"NAME", b"Test Game\0"));
+        data.extend(chunk(b"PRG1", &[0x02; 4]));
+        data.extend(chunk(b"PRG0", &[0x01; 4]));
+        data.extend(chunk(b"CHR0", &[0x03; 8]));
+        data.extend(chunk(b"MIRR", &[0x01]));
+        data.extend(chunk(b"BATR", &[0x01]));
+        data.extend(chunk(b"TVCI", &[0x01]));
+        data.extend(chunk(b"DINF", &[0x00; 204]));
+
+        let unif = Unif::load(&mut data.as_slice()).expect("valid unif");
+        assert_eq!(unif.board(), "NES-SLROM");
+        assert_eq!(unif.name(), Some("Test Game"));
+        assert_eq!(unif.prg_rom(), [1, 1, 1, 1, 2, 2, 2, 2]);
+        assert_eq!(unif.chr_rom(), [3; 8]);
+        assert_eq!(unif.mirroring(), Some(UnifMirroring::Vertical));
+        assert!(unif.battery_backed());
+        assert_eq!(unif.region(), Some(NesRegion::Pal));
+        assert_eq!(unif.mapper_num(), Some((1, 0)));
+
+        let mut truncated = data.clone();
+        truncated.truncate(data.len() - 10);
+        assert!(Unif::load(&mut truncated.as_slice()).is_err());
+    }
+
+    #[test]
+    fn unimplemented_board() {
+        let unif = Unif {
+            board: "BMC-70in1".to_string(),
+            ..Unif::default()
+        };
+        assert_eq!(unif.mapper_num(), None);
+    }
+}
@@ -264,7 +264,7 @@ pub(crate) mod tests {
     //     let path = path.as_ref();
     //     let mut rom = BufReader::new(File::open(path).expect("failed to open path"));
     //     let mut deck = ControlDeck::default();
-    //     deck.load_rom(&path.to_string_lossy(), &mut rom)
+    //     deck.load_rom(&path.to_string_lossy(), &mut rom, &[])
     //         .expect("failed to load rom");
     //     deck.set_filter(VideoFilter::Pixellate);
     //     deck.set_region(NesRegion::Ntsc);
@@ -1,21 +1,42 @@
 use crate::{
     // apu::{Apu, Channel},
     bus::CpuBus,
-    cart::Cart,
+    cart::{
+        archive::Archive,
+        fds::{DiskDiff, DiskImage},
+        game_db::GameDb,
+        hash::RomHashes,
+        patch::Patch,
+        playchoice::{PlayChoice, PlayTimer},
+        Cart,
+    },
     common::{Clock, Kind, NesRegion, Regional, Reset},
     cpu::Cpu,
-    input::{FourPlayer, Joypad, Slot},
-    mapper::Mapper,
+    input::{FourPlayer, Joypad, Slot, VsSystem},
+    mapper::{Fds, Mapped, Mapper},
     mem::RamState,
     ppu::Ppu,
-    video::{Video, VideoFilter},
+    recorder::Recorder,
+    sram::SramManager,
+    video::{
+        crt::CrtConfig,
+        gif::{GifConfig, GifRecorder},
+        ntsc::NtscConfig,
+        overscan::Overscan,
+        screenshot, Video, VideoFilter,
+    },
     NesResult,
 };
-use anyhow::anyhow;
-use std::{io::Read, ops::ControlFlow};
+use anyhow::{anyhow, Context};
+use std::{
+    io::{Read, Write},
+    ops::{ControlFlow, Range},
+    path::PathBuf,
+    time::Duration,
+};
 
 /// Represents an NES Control Deck
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 #[must_use]
 pub struct ControlDeck {
     running: bool,
@@ -23,7 +44,14 @@ pub struct ControlDeck {
     region: NesRegion,
     video: Video,
     loaded_rom: Option<String>,
+    rom_hashes: Option<RomHashes>,
     cycles_remaining: f32,
+    timer_cycles: usize,
+    playchoice: Option<PlayChoice>,
+    play_timer: Option<PlayTimer>,
+    fds_bios: Option<Vec<u8>>,
+    game_db: Option<GameDb>,
+    sram_manager: Option<SramManager>,
     cpu: Cpu,
 }
 
@@ -33,7 +61,33 @@ impl Default for ControlDeck {
     }
 }
 
+// Clones don't persist saves, so a stale copy such as a rewind snapshot can't overwrite newer
+// progress when it's dropped.
+impl Clone for ControlDeck {
+    fn clone(&self) -> Self {
+        Self {
+            running: self.running,
+            ram_state: self.ram_state,
+            region: self.region,
+            video: self.video.clone(),
+            loaded_rom: self.loaded_rom.clone(),
+            rom_hashes: self.rom_hashes,
+            cycles_remaining: self.cycles_remaining,
+            timer_cycles: self.timer_cycles,
+            playchoice: self.playchoice.clone(),
+            play_timer: self.play_timer,
+            fds_bios: self.fds_bios.clone(),
+            game_db: self.game_db.clone(),
+            sram_manager: None,
+            cpu: self.cpu.clone(),
+        }
+    }
+}
+
 impl ControlDeck {
+    // CPU cycles between updates of timers driven by emulated time, about 2ms
+    const TIMER_CYCLES: usize = 4096;
+
     /// Create a NES `ControlDeck`.
     pub fn new(ram_state: RamState) -> Self {
         let cpu = Cpu::new(CpuBus::new(ram_state));
@@ -43,25 +97,109 @@ impl ControlDeck {
             region: NesRegion::default(),
             video: Video::default(),
             loaded_rom: None,
+            rom_hashes: None,
             cycles_remaining: 0.0,
+            timer_cycles: 0,
+            playchoice: None,
+            play_timer: None,
+            fds_bios: None,
+            game_db: None,
+            sram_manager: None,
             cpu,
         }
     }
 
-    /// Loads a ROM cartridge into memory
+    /// Loads a ROM cartridge or Famicom Disk System image into memory. Disk images require a
+    /// BIOS set with `set_fds_bios`. Gzip, zip and 7z archives are decompressed, loading the first
+    /// ROM entry. Use `Archive` to pick a different entry. `patches` are applied in order before
+    /// the ROM header is read, and `iNES` headers are corrected from the game database. If a save
+    /// directory is set with `set_sram_dir`, the previous ROM's save is flushed and the new ROM's
+    /// save is loaded.
     ///
     /// # Errors
     ///
-    /// If there is any issue loading the ROM, then an error is returned.
-    pub fn load_rom<S: ToString, F: Read>(&mut self, name: S, rom: &mut F) -> NesResult<()> {
-        self.loaded_rom = Some(name.to_string());
-        let cart = Cart::from_rom(name, rom, self.ram_state)?;
+    /// If there is any issue loading or patching the ROM, then an error is returned.
+    pub fn load_rom<S: ToString, F: Read>(
+        &mut self,
+        name: S,
+        rom: &mut F,
+        patches: &[Patch],
+    ) -> NesResult<()> {
+        let name = name.to_string();
+        let mut data = vec![];
+        rom.read_to_end(&mut data)
+            .with_context(|| format!("failed to read rom {name:?}"))?;
+        if Archive::is_archive(&data) {
+            let (entry, rom) = Archive::new(data)?.read_rom()?;
+            log::info!("loading {entry:?} from archive {name:?}");
+            data = rom;
+        }
+        for patch in patches {
+            data = patch
+                .apply(&data)
+                .with_context(|| format!("failed to apply {:?} patch to {name:?}", patch.kind()))?;
+        }
+        // Disk saves are stored separately as a `DiskDiff`
+        let is_disk_image = DiskImage::is_disk_image(&data);
+        let cart = if is_disk_image {
+            let bios = self
+                .fds_bios
+                .as_ref()
+                .ok_or_else(|| anyhow!("loading a disk image requires an fds bios"))?;
+            Cart::from_fds(&name, &data, bios, self.ram_state)?
+        } else {
+            Cart::from_rom_with_db(
+                &name,
+                &mut data.as_slice(),
+                self.ram_state,
+                self.game_db.as_ref(),
+            )?
+        };
+        let rom_hashes = (!is_disk_image).then(|| cart.rom_hashes());
+        // Read the new save before swapping carts, so a failure leaves the current game running
+        let save = match (&self.sram_manager, &rom_hashes) {
+            (Some(sram_manager), Some(hashes)) => sram_manager.read(hashes)?,
+            _ => None,
+        };
+
+        if let Err(err) = self.flush_sram() {
+            log::error!("failed to save {:?}: {err:?}", self.loaded_rom);
+        }
+        if let Some(sram_manager) = &mut self.sram_manager {
+            sram_manager.close();
+        }
+        self.loaded_rom = Some(name);
         self.set_region(cart.region());
+        self.video.set_ppu_variant(cart.ppu_variant());
+        self.playchoice = cart.playchoice().cloned();
+        self.rom_hashes = rom_hashes;
         self.cpu.load_cart(cart);
         self.reset(Kind::Hard);
+        if let Some(hashes) = rom_hashes {
+            self.open_sram_save(&hashes, save.as_deref());
+        }
+        Ok(())
+    }
+
+    fn load_sram_save(&mut self, hashes: &RomHashes) -> NesResult<()> {
+        let Some(sram_manager) = &self.sram_manager else {
+            return Ok(());
+        };
+        let save = sram_manager.read(hashes)?;
+        self.open_sram_save(hashes, save.as_deref());
         Ok(())
     }
 
+    fn open_sram_save(&mut self, hashes: &RomHashes, save: Option<&[u8]>) {
+        if let Some(save) = save {
+            self.load_battery_ram(save);
+        }
+        let battery_ram = self.battery_ram();
+        if let Some(sram_manager) = &mut self.sram_manager {
+            sram_manager.open(hashes, &battery_ram);
+        }
+    }
+
     #[inline]
     pub fn load_cpu(&mut self, cpu: Cpu) {
         self.cpu = cpu;
@@ -90,13 +228,86 @@ impl ControlDeck {
         self.cpu.load_sram(sram);
     }
 
+    /// Returns all battery-backed memory as stored in a save file: battery-backed PRG-RAM
+    /// followed by memory held by the mapper, such as a serial EEPROM.
+    #[must_use]
+    pub fn battery_ram(&self) -> Vec<u8> {
+        let mut battery_ram = vec![];
+        if self.cart_battery_backed() {
+            battery_ram.extend_from_slice(self.sram());
+        }
+        if let Some(ram) = self.mapper().battery_ram() {
+            battery_ram.extend_from_slice(ram);
+        }
+        battery_ram
+    }
+
+    /// Load battery-backed memory as returned by `battery_ram`. Data beyond the size of the
+    /// current cart's battery-backed memory is ignored.
+    pub fn load_battery_ram(&mut self, data: &[u8]) {
+        let mut mapper_ram = data;
+        if self.cart_battery_backed() {
+            let mut sram = self.sram().to_vec();
+            let len = sram.len().min(data.len());
+            sram[..len].copy_from_slice(&data[..len]);
+            self.load_sram(sram);
+            mapper_ram = &data[len..];
+        }
+        self.mapper_mut().load_battery_ram(mapper_ram);
+    }
+
+    /// Set the directory battery-backed saves are loaded from and flushed to, or disable save
+    /// persistence with `None`. Saves are flushed periodically while running, when a new ROM is
+    /// loaded, and when the `ControlDeck` is dropped. Clones of the `ControlDeck` don't persist
+    /// saves.
+    ///
+    /// # Errors
+    ///
+    /// If flushing the current save or loading the save for the current ROM fails, then an error
+    /// is returned.
+    pub fn set_sram_dir(&mut self, dir: Option<PathBuf>) -> NesResult<()> {
+        self.flush_sram()?;
+        self.sram_manager = dir.map(SramManager::new);
+        if let Some(hashes) = self.rom_hashes {
+            self.load_sram_save(&hashes)?;
+        }
+        Ok(())
+    }
+
+    #[inline]
+    #[must_use]
+    pub const fn sram_manager(&self) -> Option<&SramManager> {
+        self.sram_manager.as_ref()
+    }
+
+    #[inline]
+    pub fn sram_manager_mut(&mut self) -> Option<&mut SramManager> {
+        self.sram_manager.as_mut()
+    }
+
+    /// Write battery-backed memory to the save file if it changed since it was last written.
+    ///
+    /// # Errors
+    ///
+    /// If the save file can't be written, then an error is returned.
+    pub fn flush_sram(&mut self) -> NesResult<()> {
+        if self.sram_manager.is_some() {
+            let battery_ram = self.battery_ram();
+            if let Some(sram_manager) = &mut self.sram_manager {
+                sram_manager.flush(&battery_ram)?;
+            }
+        }
+        Ok(())
+    }
+
     #[inline]
     #[must_use]
     pub fn wram(&self) -> &[u8] {
         self.cpu.wram()
     }
 
-    /// Get a frame worth of pixels.
+    /// Get a frame worth of RGBA pixels. Dimensions depend on the current filter, overscan and
+    /// post-processing, see `frame_dimensions`.
     #[inline]
     #[must_use]
     pub fn frame_buffer(&mut self) -> &[u8] {
@@ -105,6 +316,87 @@ impl ControlDeck {
         self.video.output()
     }
 
+    /// Encode the current filtered frame as a PNG image.
+    ///
+    /// # Errors
+    ///
+    /// If encoding or writing the image fails, then an error is returned.
+    pub fn screenshot<W: Write>(&mut self, writer: W) -> NesResult<()> {
+        self.encode_screenshot(writer, &[])
+    }
+
+    /// Encode the current filtered frame as a PNG image, including the loaded ROM name, frame
+    /// number and region as text metadata.
+    ///
+    /// # Errors
+    ///
+    /// If encoding or writing the image fails, then an error is returned.
+    pub fn screenshot_with_metadata<W: Write>(&mut self, writer: W) -> NesResult<()> {
+        let mut metadata = Vec::with_capacity(3);
+        if let Some(rom) = &self.loaded_rom {
+            metadata.push((screenshot::KEYWORD_ROM, rom.clone()));
+        }
+        metadata.push((screenshot::KEYWORD_FRAME, self.frame_number().to_string()));
+        metadata.push((screenshot::KEYWORD_REGION, self.region.as_ref().to_string()));
+        self.encode_screenshot(writer, &metadata)
+    }
+
+    fn encode_screenshot<W: Write>(
+        &mut self,
+        writer: W,
+        metadata: &[(&str, String)],
+    ) -> NesResult<()> {
+        let (width, height) = self.frame_dimensions();
+        let frame = self.frame_buffer();
+        screenshot::encode_png(writer, width, height, frame, metadata)
+    }
+
+    /// Record the current filtered frame and the audio samples generated since they were last
+    /// cleared.
+    ///
+    /// # Errors
+    ///
+    /// If the frame dimensions don't match the recording or writing fails, then an error is
+    /// returned.
+    pub fn record_frame<R: Recorder>(&mut self, recorder: &mut R) -> NesResult<()> {
+        self.video
+            .apply_filter(self.cpu.frame_buffer(), self.cpu.frame_number());
+        recorder.record_frame(self.video.output(), self.cpu.audio_samples())
+    }
+
+    /// Run the emulation through a range of frame numbers, recording it as an animated GIF
+    /// using the 64-color system palette. Frames before the start of the range are run without
+    /// being captured. Recording stops early at the end of the range, at the configured maximum
+    /// duration, or if the CPU halts.
+    ///
+    /// # Errors
+    ///
+    /// If the CPU encounters an invalid opcode or writing fails, then an error is returned.
+    pub fn record_gif<W: Write>(
+        &mut self,
+        writer: W,
+        frames: Range<u32>,
+        config: GifConfig,
+    ) -> NesResult<W> {
+        // GIFs carry no audio, so samples are dropped each frame to keep them from piling up
+        while self.frame_number() < frames.start {
+            let status = self.clock_frame()?;
+            self.clear_audio_samples();
+            if status.is_break() {
+                break;
+            }
+        }
+        let mut gif = GifRecorder::new(writer, self.region, config)?;
+        while frames.contains(&self.frame_number()) {
+            let status = self.clock_frame()?;
+            self.clear_audio_samples();
+            if status.is_break() || !gif.add_frame(self.cpu.frame_buffer())? {
+                break;
+            }
+        }
+        gif.finish()
+    }
+
     /// Get the current frame number.
     #[inline]
     #[must_use]
@@ -143,7 +435,11 @@ impl ControlDeck {
     ///
     /// If CPU encounteres an invalid opcode, an error is returned.
     pub fn clock_instr(&mut self) -> NesResult<ControlFlow<usize, usize>> {
+        if self.play_time_expired() {
+            return Ok(ControlFlow::Break(0));
+        }
         let cycles = self.clock();
+        self.clock_timers(cycles);
         if self.cpu_corrupted() {
             Err(anyhow!("cpu corrupted"))
         } else {
@@ -157,6 +453,10 @@ impl ControlDeck {
     ///
     /// If CPU encounteres an invalid opcode, an error is returned.
     pub fn clock_seconds(&mut self, seconds: f32) -> NesResult<ControlFlow<usize, usize>> {
+        if self.play_time_expired() {
+            self.cycles_remaining = 0.0;
+            return Ok(ControlFlow::Break(0));
+        }
         self.cycles_remaining += self.clock_rate() * seconds;
         let mut total_cycles = 0;
         while self.cycles_remaining > 0.0 {
@@ -189,10 +489,18 @@ impl ControlDeck {
     where
         F: FnMut(&mut Cpu),
     {
+        if self.play_time_expired() {
+            self.cycles_remaining = 0.0;
+            return Ok(ControlFlow::Break(0));
+        }
         self.cycles_remaining += self.clock_rate() * seconds;
         let mut total_cycles = 0;
         while self.cycles_remaining > 0.0 {
+            if self.play_time_expired() {
+                return Ok(ControlFlow::Break(total_cycles));
+            }
             let cycles = self.cpu.clock_inspect(&mut inspect);
+            self.clock_timers(cycles);
             total_cycles += cycles;
             self.cycles_remaining -= cycles as f32;
         }
@@ -221,6 +529,29 @@ impl ControlDeck {
         Ok(ControlFlow::Continue(total_cycles))
     }
 
+    // Advances timers driven by emulated time, flushing the save when it's due.
+    fn clock_timers(&mut self, cycles: usize) {
+        self.timer_cycles += cycles;
+        if self.timer_cycles < Self::TIMER_CYCLES {
+            return;
+        }
+        let elapsed =
+            Duration::from_secs_f64(self.timer_cycles as f64 / f64::from(self.cpu.clock_rate()));
+        self.timer_cycles = 0;
+        if let (Some(_), Some(timer)) = (&self.playchoice, &mut self.play_timer) {
+            timer.tick(elapsed);
+        }
+        if self
+            .sram_manager
+            .as_mut()
+            .is_some_and(|sram_manager| sram_manager.tick(elapsed))
+        {
+            if let Err(err) = self.flush_sram() {
+                log::error!("failed to save {:?}: {err:?}", self.loaded_rom);
+            }
+        }
+    }
+
     /// Steps the control deck a single scanline.
     ///
     /// # Errors
@@ -309,6 +640,120 @@ impl ControlDeck {
         self.cpu.joypad_mut(slot)
     }
 
+    /// Set the Famicom Disk System BIOS, usually named `disksys.rom`, used to load disk images.
+    #[inline]
+    pub fn set_fds_bios(&mut self, bios: Vec<u8>) {
+        self.fds_bios = Some(bios);
+    }
+
+    /// Set a user game database, whose records take precedence over the builtin database when
+    /// correcting ROM headers.
+    #[inline]
+    pub fn set_game_db(&mut self, game_db: GameDb) {
+        self.game_db = Some(game_db);
+    }
+
+    /// Returns the Famicom Disk System drive, if a disk image is loaded.
+    #[inline]
+    #[must_use]
+    pub const fn fds(&self) -> Option<&Fds> {
+        match self.mapper() {
+            Mapper::Fds(fds) => Some(fds),
+            _ => None,
+        }
+    }
+
+    /// Returns a mutable reference to the Famicom Disk System drive to switch disk sides, if a
+    /// disk image is loaded.
+    #[inline]
+    #[must_use]
+    pub fn fds_mut(&mut self) -> Option<&mut Fds> {
+        match self.mapper_mut() {
+            Mapper::Fds(fds) => Some(fds),
+            _ => None,
+        }
+    }
+
+    /// Write changes made to the loaded disk image as an IPS patch, leaving the original image
+    /// untouched.
+    ///
+    /// # Errors
+    ///
+    /// If no disk image is loaded or writing fails, then an error is returned.
+    pub fn save_disk_changes<W: Write>(&self, writer: W) -> NesResult<()> {
+        let fds = self.fds().ok_or_else(|| anyhow!("no disk image loaded"))?;
+        fds.disk_diff().save(writer)
+    }
+
+    /// Restore changes to the loaded disk image saved by `save_disk_changes`.
+    ///
+    /// # Errors
+    ///
+    /// If no disk image is loaded or the changes are invalid, then an error is returned.
+    pub fn load_disk_changes<R: Read>(&mut self, reader: R) -> NesResult<()> {
+        let diff = DiskDiff::load(reader)?;
+        let fds = self
+            .fds_mut()
+            .ok_or_else(|| anyhow!("no disk image loaded"))?;
+        fds.apply_disk_diff(&diff);
+        Ok(())
+    }
+
+    /// Returns the PlayChoice-10 INST-ROM and PROM data, if the loaded ROM has them.
+    #[inline]
+    #[must_use]
+    pub const fn playchoice(&self) -> Option<&PlayChoice> {
+        self.playchoice.as_ref()
+    }
+
+    /// Enable the PlayChoice-10 play timer, granting `credit` of play time per inserted credit,
+    /// or disable it with `None`. While enabled, PlayChoice-10 ROMs stop running once the time
+    /// runs out until `insert_credit` is called, with every clock method returning
+    /// `ControlFlow::Break` without advancing.
+    #[inline]
+    pub fn set_play_timer(&mut self, credit: Option<Duration>) {
+        self.play_timer = credit.map(PlayTimer::new);
+    }
+
+    /// Returns whether a PlayChoice-10 ROM is loaded and its play timer has run out.
+    #[must_use]
+    pub fn play_time_expired(&self) -> bool {
+        self.playchoice.is_some()
+            && self
+                .play_timer
+                .is_some_and(|timer| timer.remaining().is_zero())
+    }
+
+    /// Returns the PlayChoice-10 play timer, if enabled.
+    #[inline]
+    #[must_use]
+    pub const fn play_timer(&self) -> Option<&PlayTimer> {
+        self.play_timer.as_ref()
+    }
+
+    /// Add a credit of play time to the PlayChoice-10 play timer.
+    #[inline]
+    pub fn insert_credit(&mut self) {
+        if let Some(timer) = &mut self.play_timer {
+            timer.insert_credit();
+        }
+    }
+
+    /// Returns the Vs. System coin-op inputs, if a Vs. System ROM is loaded.
+    #[inline]
+    #[must_use]
+    pub const fn vs_system(&self) -> Option<&VsSystem> {
+        self.cpu.vs_system()
+    }
+
+    /// Returns a mutable reference to the Vs. System coin-op inputs to set DIP switches, insert
+    /// coins or press the service button, if a Vs. System ROM is loaded.
+    #[inline]
+    #[must_use]
+    pub fn vs_system_mut(&mut self) -> Option<&mut VsSystem> {
+        self.cpu.vs_system_mut()
+    }
+
     /// Returns the zapper aiming position for the given controller slot.
     #[inline]
     #[must_use]
@@ -335,6 +780,60 @@ impl ControlDeck {
         self.video.set_filter(filter);
     }
 
+    /// Get the current NTSC filter parameters.
+    #[inline]
+    pub const fn ntsc_config(&self) -> NtscConfig {
+        self.video.ntsc_config()
+    }
+
+    /// Set the NTSC filter parameters such as hue, saturation and artifacts. Lookup tables are
+    /// regenerated on the next frame if any parameter changed.
+    #[inline]
+    pub fn set_ntsc_config(&mut self, config: NtscConfig) {
+        self.video.set_ntsc_config(config);
+    }
+
+    /// Get the current CRT post-process parameters, if enabled.
+    #[inline]
+    #[must_use]
+    pub fn crt_config(&self) -> Option<CrtConfig> {
+        self.video.crt_config()
+    }
+
+    /// Enable the CRT post-process with the given parameters, or disable it with `None`. It is
+    /// applied after the current video filter.
+    #[inline]
+    pub fn set_crt_config(&mut self, config: Option<CrtConfig>) {
+        self.video.set_crt_config(config);
+    }
+
+    /// Get the overscan cropped from each edge of the frame, in NES pixels.
+    #[inline]
+    pub const fn overscan(&self) -> Overscan {
+        self.video.overscan()
+    }
+
+    /// Set the overscan to crop from each edge of the frame, or use the default for the current
+    /// region with `None`.
+    #[inline]
+    pub fn set_overscan(&mut self, overscan: Option<Overscan>) {
+        self.video.set_overscan(overscan);
+    }
+
+    /// Stretch the frame width to the pixel aspect ratio of the current region, e.g. 8:7 for NTSC.
+    #[inline]
+    pub fn set_pixel_aspect(&mut self, enabled: bool) {
+        self.video.set_pixel_aspect(enabled);
+    }
+
+    /// Width and height in pixels of the frame returned by `frame_buffer`, after cropping overscan
+    /// and applying the current filter and post-processing.
+    #[inline]
+    #[must_use]
+    pub fn frame_dimensions(&self) -> (u32, u32) {
+        (self.video.width(), self.video.height())
+    }
+
     /// Enable Zapper gun.
     #[inline]
     pub fn connect_zapper(&mut self, enabled: bool) {
@@ -377,6 +876,14 @@ impl ControlDeck {
     }
 }
 
+impl Drop for ControlDeck {
+    fn drop(&mut self) {
+        if let Err(err) = self.flush_sram() {
+            log::error!("failed to save {:?}: {err:?}", self.loaded_rom);
+        }
+    }
+}
+
 impl Clock for ControlDeck {
     /// Steps the control deck a single clock cycle.
     fn clock(&mut self) -> usize {
@@ -396,6 +903,7 @@ impl Regional for ControlDeck {
     fn set_region(&mut self, region: NesRegion) {
         self.region = region;
         self.cpu.set_region(region);
+        self.video.set_region(region);
     }
 }
 
@@ -406,3 +914,172 @@ impl Reset for ControlDeck {
         self.running = true;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    // Battery-backed NROM that stores $42 to $6000 and loops forever
+    fn battery_rom() -> Vec<u8> {
+        let mut rom = b"NES\x1a\x01\x01\x02\x00".to_vec();
+        rom.resize(16, 0x00);
+        let mut prg_rom = vec![0xEA; 0x4000];
+        prg_rom[..8].copy_from_slice(&[0xA9, 0x42, 0x8D, 0x00, 0x60, 0x4C, 0x05, 0x80]);
+        prg_rom[0x3FFC..0x3FFE].copy_from_slice(&[0x00, 0x80]);
+        rom.extend(prg_rom);
+        rom.resize(rom.len() + 0x2000, 0x00);
+        rom
+    }
+
+    #[test]
+    fn play_timer_stops_clocking() {
+        let mut rom = battery_rom();
+        rom[7] = 0x02;
+        rom.resize(
+            rom.len() + PlayChoice::INST_ROM_SIZE + PlayChoice::PROM_SIZE,
+            0x00,
+        );
+        let mut deck = ControlDeck::default();
+        deck.load_rom("playchoice.nes", &mut rom.as_slice(), &[])
+            .expect("valid rom");
+        assert!(deck.playchoice().is_some());
+        deck.set_play_timer(Some(Duration::from_millis(100)));
+        assert!(deck.play_time_expired());
+        let start = deck.frame_number();
+        assert_eq!(
+            deck.clock_frame().expect("valid clock"),
+            ControlFlow::Break(0)
+        );
+        assert_eq!(deck.frame_number(), start);
+
+        deck.insert_credit();
+        assert!(deck.clock_frame().expect("valid clock").is_continue());
+        assert!(deck.clock_seconds(1.0).expect("valid clock").is_break());
+        assert!(deck.play_time_expired());
+        let frame = deck.frame_number();
+        assert!(
+            (start + 6..=start + 8).contains(&frame),
+            "stopped after ~100ms: {frame}"
+        );
+
+        // Recording a range past the expired timer stops instead of spinning forever
+        let gif = deck
+            .record_gif(vec![], frame..frame + 10, GifConfig::default())
+            .expect("valid gif");
+        assert!(!gif.is_empty());
+        assert_eq!(deck.frame_number(), frame);
+        assert!(deck.clock_seconds(1.0).expect("valid clock").is_break());
+        assert_eq!(deck.frame_number(), frame);
+    }
+
+    #[test]
+    fn sram_flushes_while_clocking() {
+        let dir = std::env::temp_dir().join(format!("tetanes_deck_sram_{}", std::process::id()));
+        let rom = battery_rom();
+        let mut deck = ControlDeck::default();
+        deck.set_sram_dir(Some(dir.clone())).expect("valid dir");
+        deck.load_rom("battery.nes", &mut rom.as_slice(), &[])
+            .expect("valid rom");
+        let path = deck
+            .sram_manager()
+            .and_then(SramManager::path)
+            .map(PathBuf::from)
+            .expect("save path");
+        deck.sram_manager_mut()
+            .expect("sram manager")
+            .set_flush_interval(Duration::from_millis(10));
+
+        assert!(deck.clock_seconds(0.05).expect("valid clock").is_continue());
+        assert_eq!(fs::read(&path).expect("flushed save")[0], 0x42);
+
+        // Dropping a stale clone doesn't overwrite newer progress
+        let clone = deck.clone();
+        assert!(clone.sram_manager().is_none());
+        let mut sram = deck.sram().to_vec();
+        sram[0] = 0x99;
+        deck.load_sram(sram);
+        deck.flush_sram().expect("flushed save");
+        drop(clone);
+        assert_eq!(fs::read(&path).expect("flushed save")[0], 0x99);
+
+        drop(deck);
+        fs::remove_dir_all(&dir).expect("removed test dir");
+    }
+
+    #[test]
+    fn battery_ram_saves() {
+        let dir = std::env::temp_dir().join(format!("tetanes_deck_battery_{}", std::process::id()));
+        // MMC4 with battery-backed PRG-RAM and no save memory held by the mapper
+        let mut rom = battery_rom();
+        rom[6] = 0xA2;
+        let mut deck = ControlDeck::default();
+        deck.set_sram_dir(Some(dir.clone())).expect("valid dir");
+        deck.load_rom("battery.nes", &mut rom.as_slice(), &[])
+            .expect("valid rom");
+        assert_eq!(deck.sram().len(), 0x2000);
+        assert_eq!(deck.battery_ram(), deck.sram());
+
+        // Data past the cart's battery-backed memory is ignored
+        let mut save = vec![0x5A; 0x2000];
+        save.extend([0xA5; 16]);
+        deck.load_battery_ram(&save);
+        assert_eq!(deck.battery_ram(), save[..0x2000]);
+        deck.flush_sram().expect("flushed save");
+        let path = deck
+            .sram_manager()
+            .and_then(SramManager::path)
+            .map(PathBuf::from)
+            .expect("save path");
+        assert_eq!(fs::read(path).expect("flushed save"), save[..0x2000]);
+
+        // Reloading restores the save
+        deck.load_rom("battery.nes", &mut rom.as_slice(), &[])
+            .expect("valid rom");
+        assert_eq!(deck.battery_ram(), save[..0x2000]);
+
+        drop(deck);
+        fs::remove_dir_all(&dir).expect("removed test dir");
+    }
+
+    #[test]
+    fn unreadable_save_keeps_loaded_rom() {
+        let dir =
+            std::env::temp_dir().join(format!("tetanes_deck_unreadable_{}", std::process::id()));
+        let rom = battery_rom();
+        let mut deck = ControlDeck::default();
+        deck.set_sram_dir(Some(dir.clone())).expect("valid dir");
+        deck.load_rom("battery.nes", &mut rom.as_slice(), &[])
+            .expect("valid rom");
+        let path = deck
+            .sram_manager()
+            .and_then(SramManager::path)
+            .map(PathBuf::from)
+            .expect("save path");
+
+        // A directory in place of the new ROM's save can't be read
+        let mut other_rom = battery_rom();
+        other_rom[17] = 0x43;
+        let hashes = Cart::from_rom("other.nes", &mut other_rom.as_slice(), RamState::default())
+            .expect("valid rom")
+            .rom_hashes();
+        let other_path = deck
+            .sram_manager()
+            .expect("sram manager")
+            .save_path(&hashes);
+        fs::create_dir_all(other_path).expect("created save dir");
+        assert!(deck
+            .load_rom("other.nes", &mut other_rom.as_slice(), &[])
+            .is_err());
+        assert_eq!(deck.loaded_rom().as_deref(), Some("battery.nes"));
+        assert_eq!(
+            deck.sram_manager().and_then(SramManager::path),
+            Some(path.as_path())
+        );
+        assert!(deck.clock_frame().expect("valid clock").is_continue());
+        assert_eq!(deck.sram()[0], 0x42);
+
+        drop(deck);
+        fs::remove_dir_all(&dir).expect("removed test dir");
+    }
+}
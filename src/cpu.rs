@@ -7,7 +7,7 @@ use crate::{
     bus::CpuBus,
     cart::Cart,
     common::{Clock, Kind, NesRegion, Regional, Reset},
-    input::{FourPlayer, Joypad, Slot, Zapper},
+    input::{FourPlayer, Joypad, Slot, VsSystem, Zapper},
     mapper::Mapper,
     mem::{Access, Mem},
     ppu::Ppu,
@@ -276,6 +276,18 @@ impl Cpu {
         self.bus.joypad_mut(slot)
     }
 
+    #[inline]
+    #[must_use]
+    pub const fn vs_system(&self) -> Option<&VsSystem> {
+        self.bus.vs_system()
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn vs_system_mut(&mut self) -> Option<&mut VsSystem> {
+        self.bus.vs_system_mut()
+    }
+
     #[inline]
     pub fn connect_zapper(&mut self, enabled: bool) {
         self.bus.connect_zapper(enabled);
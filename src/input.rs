@@ -85,6 +85,7 @@ pub struct Input {
     zapper: Zapper,
     turbo_timer: u32,
     four_player: FourPlayer,
+    vs_system: Option<VsSystem>,
 }
 
 impl Input {
@@ -99,6 +100,7 @@ impl Input {
             zapper: Zapper::new(),
             turbo_timer: 30,
             four_player: FourPlayer::default(),
+            vs_system: None,
         }
     }
 
@@ -137,6 +139,48 @@ impl Input {
         self.four_player = four_player;
         self.reset(Kind::Hard);
     }
+
+    #[inline]
+    #[must_use]
+    pub const fn vs_system(&self) -> Option<&VsSystem> {
+        self.vs_system.as_ref()
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn vs_system_mut(&mut self) -> Option<&mut VsSystem> {
+        self.vs_system.as_mut()
+    }
+
+    /// Enable/Disable Vs. System coin-op inputs. DIP switches are kept if already enabled.
+    #[inline]
+    pub fn set_vs_system(&mut self, enabled: bool) {
+        match (enabled, self.vs_system) {
+            (true, None) => self.vs_system = Some(VsSystem::new()),
+            (false, _) => self.vs_system = None,
+            _ => (),
+        }
+    }
+
+    // Vs. System reads share $4016/$4017 with the serial controller data, and both controller
+    // ports are swapped relative to the NES.
+    fn read_vs(&mut self, vs: VsSystem, slot: Slot) -> u8 {
+        let port = match (slot, vs.swap_ports) {
+            (Slot::One, true) => Slot::Two,
+            (Slot::Two, true) => Slot::One,
+            (slot, _) => slot,
+        };
+        self.joypads[port as usize].read() | vs.read(slot)
+    }
+
+    const fn peek_vs(&self, vs: VsSystem, slot: Slot) -> u8 {
+        let port = match (slot, vs.swap_ports) {
+            (Slot::One, true) => Slot::Two,
+            (Slot::Two, true) => Slot::One,
+            (slot, _) => slot,
+        };
+        self.joypads[port as usize].peek() | vs.read(slot)
+    }
 }
 
 impl InputRegisters for Input {
@@ -144,6 +188,9 @@ impl InputRegisters for Input {
         // Read $4016/$4017 D0 8x for controller #1/#2.
         // Read $4016/$4017 D0 8x for controller #3/#4.
         // Read $4016/$4017 D0 8x for signature: 0b00010000/0b00100000
+        if let Some(vs) = self.vs_system {
+            return self.read_vs(vs, slot);
+        }
         let zapper = if slot == Slot::Two {
             self.zapper.read(ppu)
         } else {
@@ -176,6 +223,9 @@ impl InputRegisters for Input {
         // Read $4016/$4017 D0 8x for controller #1/#2.
         // Read $4016/$4017 D0 8x for controller #3/#4.
         // Read $4016/$4017 D0 8x for signature: 0b00010000/0b00100000
+        if let Some(vs) = self.vs_system {
+            return self.peek_vs(vs, slot);
+        }
         let zapper = if slot == Slot::Two {
             self.zapper.read(ppu)
         } else {
@@ -217,6 +267,9 @@ impl InputRegisters for Input {
 impl Clock for Input {
     fn clock(&mut self) -> usize {
         self.zapper.clock();
+        if let Some(vs) = &mut self.vs_system {
+            vs.clock();
+        }
         self.turbo_timer -= 1;
         if self.turbo_timer == 0 {
             // Roughly 20Hz
@@ -245,6 +298,9 @@ impl Reset for Input {
             sig.reset(kind);
         }
         self.zapper.reset(kind);
+        if let Some(vs) = &mut self.vs_system {
+            vs.reset(kind);
+        }
     }
 }
 
@@ -401,6 +457,133 @@ impl Reset for Joypad {
     }
 }
 
+/// Vs. System coin-op inputs: DIP switches, coin slots and the service button.
+///
+/// <https://www.nesdev.org/wiki/Vs._System>
+#[derive(Default, Debug, Copy, Clone, Serialize, Deserialize)]
+#[must_use]
+pub struct VsSystem {
+    dip_switches: u8,
+    service: bool,
+    coins: [u32; 2],
+    coin_counter: u32,
+    coin_counter_enabled: bool,
+    swap_ports: bool,
+}
+
+impl VsSystem {
+    // Coin switches stay closed for roughly 4 frames per coin
+    const COIN_CYCLES: u32 = 4 * 29_780;
+
+    pub const fn new() -> Self {
+        Self {
+            dip_switches: 0x00,
+            service: false,
+            coins: [0; 2],
+            coin_counter: 0,
+            coin_counter_enabled: false,
+            swap_ports: true,
+        }
+    }
+
+    /// DIP switches 1-8, with switch 1 in bit 0.
+    #[inline]
+    #[must_use]
+    pub const fn dip_switches(&self) -> u8 {
+        self.dip_switches
+    }
+
+    #[inline]
+    pub fn set_dip_switches(&mut self, dip_switches: u8) {
+        self.dip_switches = dip_switches;
+    }
+
+    /// Insert a coin into the coin slot for player one or two.
+    #[inline]
+    pub fn insert_coin(&mut self, slot: Slot) {
+        match slot {
+            Slot::One => self.coins[0] = Self::COIN_CYCLES,
+            Slot::Two => self.coins[1] = Self::COIN_CYCLES,
+            Slot::Three | Slot::Four => (),
+        }
+    }
+
+    #[inline]
+    pub fn set_service(&mut self, pressed: bool) {
+        self.service = pressed;
+    }
+
+    /// Number of coins counted by the mechanical coin counter at $4020.
+    #[inline]
+    #[must_use]
+    pub const fn coin_counter(&self) -> u32 {
+        self.coin_counter
+    }
+
+    /// Whether player one is read from $4017 and player two from $4016, as wired on the Vs.
+    /// System. Enabled by default.
+    #[inline]
+    #[must_use]
+    pub const fn swap_ports(&self) -> bool {
+        self.swap_ports
+    }
+
+    #[inline]
+    pub fn set_swap_ports(&mut self, swap: bool) {
+        self.swap_ports = swap;
+    }
+
+    // $4016 | R | 0 | Serial controller data
+    //       |   | 2 | Service button
+    //       |   | 3-4 | DIP switches 1-2
+    //       |   | 5-6 | Coin slots 1-2
+    // $4017 | R | 0 | Serial controller data
+    //       |   | 2-7 | DIP switches 3-8
+    #[must_use]
+    pub const fn read(&self, slot: Slot) -> u8 {
+        match slot {
+            Slot::One => {
+                let mut val = (self.service as u8) << 2 | (self.dip_switches & 0x03) << 3;
+                if self.coins[0] > 0 {
+                    val |= 0x20;
+                }
+                if self.coins[1] > 0 {
+                    val |= 0x40;
+                }
+                val
+            }
+            Slot::Two => self.dip_switches & 0xFC,
+            Slot::Three | Slot::Four => 0x00,
+        }
+    }
+
+    // $4020 | W | 0 | Coin counter, incremented each time it's enabled
+    pub fn write_coin_counter(&mut self, val: u8) {
+        let enabled = val & 0x01 == 0x01;
+        if enabled && !self.coin_counter_enabled {
+            self.coin_counter += 1;
+        }
+        self.coin_counter_enabled = enabled;
+    }
+}
+
+impl Clock for VsSystem {
+    fn clock(&mut self) -> usize {
+        for coin in &mut self.coins {
+            *coin = coin.saturating_sub(1);
+        }
+        1
+    }
+}
+
+impl Reset for VsSystem {
+    fn reset(&mut self, _kind: Kind) {
+        self.service = false;
+        self.coins = [0; 2];
+        self.coin_counter_enabled = false;
+    }
+}
+
 #[derive(Default, Debug, Copy, Clone, Serialize, Deserialize)]
 #[must_use]
 pub struct Zapper {
@@ -514,8 +697,38 @@ impl Reset for Zapper {
 
 #[cfg(test)]
 mod tests {
+    use super::*;
     // use crate::test_roms;
 
+    #[test]
+    fn vs_system_inputs() {
+        let ppu = Ppu::new();
+        let mut input = Input::new();
+        input.set_vs_system(true);
+        let vs = input.vs_system_mut().expect("vs system");
+        vs.set_dip_switches(0b1010_0110);
+        vs.insert_coin(Slot::Two);
+        input
+            .joypad_mut(Slot::One)
+            .set_button(JoypadBtnState::A, true);
+
+        // Player one is read from $4017
+        assert_eq!(input.read(Slot::One, &ppu), 0x40 | 0b1_0000);
+        assert_eq!(input.read(Slot::Two, &ppu), 0b1010_0100 | 0x01);
+
+        for _ in 0..VsSystem::COIN_CYCLES {
+            input.clock();
+        }
+        assert_eq!(input.peek(Slot::One, &ppu) & 0x60, 0x00);
+
+        let vs = input.vs_system_mut().expect("vs system");
+        vs.write_coin_counter(0x01);
+        vs.write_coin_counter(0x01);
+        vs.write_coin_counter(0x00);
+        vs.write_coin_counter(0x01);
+        assert_eq!(vs.coin_counter(), 2);
+    }
+
     // test_roms!(
     //     "test_roms/input",
     //     #[ignore = "todo"]
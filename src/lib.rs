@@ -58,6 +58,8 @@ pub mod mem;
 #[cfg(not(target_arch = "wasm32"))]
 // pub mod nes;
 pub mod ppu;
+pub mod recorder;
+pub mod sram;
 pub mod video;
 
 pub type NesError = anyhow::Error;
@@ -17,9 +17,15 @@ pub use m004_txrom::{Mmc3Revision, Txrom};
 pub use m005_exrom::Exrom;
 pub use m007_axrom::Axrom;
 pub use m009_pxrom::Pxrom;
+pub use m010_fxrom::Fxrom;
+pub use m020_fds::Fds;
+pub use m021_m022_m023_m025_vrc2_4::{Vrc2_4, Vrc2_4Revision};
 pub use m024_m026_vrc6::Vrc6;
 pub use m066_gxrom::Gxrom;
+pub use m069_fme7::Fme7;
 pub use m071_bf909x::{Bf909Revision, Bf909x};
+pub use m085_vrc7::Vrc7;
+pub use m099_vs::Vs;
 
 pub mod m000_nrom;
 pub mod m001_sxrom;
@@ -29,9 +35,15 @@ pub mod m004_txrom;
 pub mod m005_exrom;
 pub mod m007_axrom;
 pub mod m009_pxrom;
+pub mod m010_fxrom;
+pub mod m020_fds;
+pub mod m021_m022_m023_m025_vrc2_4;
 pub mod m024_m026_vrc6;
 pub mod m066_gxrom;
+pub mod m069_fme7;
 pub mod m071_bf909x;
+pub mod m085_vrc7;
+pub mod m099_vs;
 pub mod vrc_irq;
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -56,9 +68,15 @@ pub enum Mapper {
     Exrom,
     Axrom,
     Pxrom,
+    Fxrom,
+    Fds,
+    Vrc2_4,
     Vrc6,
+    Vrc7,
     Gxrom,
+    Fme7,
     Bf909x,
+    Vs,
 }
 
 impl Mapper {
@@ -125,6 +143,11 @@ pub trait Mapped {
     fn ppu_bus_write(&mut self, _addr: u16, _val: u8) {}
     fn cpu_bus_read(&mut self, _addr: u16) {}
     fn cpu_bus_write(&mut self, _addr: u16, _val: u8) {}
+    /// Battery-backed memory held by the mapper instead of PRG-RAM, such as a serial EEPROM.
+    fn battery_ram(&self) -> Option<&[u8]> {
+        None
+    }
+    fn load_battery_ram(&mut self, _ram: &[u8]) {}
 }
 
 #[derive(Debug, Copy, Clone, Serialize, Deserialize)]
@@ -136,3 +159,46 @@ impl Mapped for Empty {}
 impl Clock for Empty {}
 impl Regional for Empty {}
 impl Reset for Empty {}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::Mapper;
+    use crate::cart::Cart;
+    use std::fmt::Debug;
+
+    /// Empty cart with zeroed PRG-ROM and CHR-ROM of the given sizes and the mapper for
+    /// `mapper_num` and `submapper_num` loaded.
+    pub(crate) fn cart(
+        mapper_num: u16,
+        submapper_num: u8,
+        prg_rom_size: usize,
+        chr_rom_size: usize,
+    ) -> Cart {
+        let mut cart = Cart::empty();
+        cart.header.mapper_num = mapper_num;
+        cart.header.submapper_num = submapper_num;
+        cart.prg_rom = vec![0x00; prg_rom_size];
+        cart.chr_rom = vec![0x00; chr_rom_size];
+        // Drop the PRG-RAM added by the empty cart's NROM mapper
+        cart.prg_ram.clear();
+        cart.load_mapper().expect("implemented mapper");
+        cart
+    }
+
+    /// Load mapper `M` onto an empty cart, see [`cart`].
+    pub(crate) fn load<M>(
+        mapper_num: u16,
+        submapper_num: u8,
+        prg_rom_size: usize,
+        chr_rom_size: usize,
+    ) -> M
+    where
+        Mapper: TryInto<M>,
+        <Mapper as TryInto<M>>::Error: Debug,
+    {
+        cart(mapper_num, submapper_num, prg_rom_size, chr_rom_size)
+            .mapper
+            .try_into()
+            .expect("expected mapper")
+    }
+}
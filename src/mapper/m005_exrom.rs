@@ -7,9 +7,8 @@ use crate::{
     apu::{
         dmc::Dmc,
         pulse::{OutputFreq, Pulse, PulseChannel},
-        // PULSE_TABLE,
+        PULSE_TABLE,
     },
-    // audio::Audio,
     cart::Cart,
     common::{Clock, Kind, NesRegion, Regional, Reset},
     cpu::Cpu,
@@ -969,17 +968,18 @@ impl MemMap for Exrom {
     }
 }
 
-// impl Audio for Exrom {
-//     #[must_use]
-//     fn output(&self) -> f32 {
-//         let pulse1 = self.pulse1.output();
-//         let pulse2 = self.pulse2.output();
-//         let dmc = self.dmc.output();
-//         let pulse_scale = PULSE_TABLE[PULSE_TABLE.len() - 1] / 15.0;
-//         let out = -(pulse1 + pulse2 + dmc);
-//         pulse_scale * out
-//     }
-// }
+impl Exrom {
+    /// Expansion audio output.
+    #[must_use]
+    pub fn output(&self) -> f32 {
+        let pulse1 = self.pulse1.output();
+        let pulse2 = self.pulse2.output();
+        let dmc = self.dmc.output();
+        let pulse_scale = PULSE_TABLE[PULSE_TABLE.len() - 1] / 15.0;
+        let out = -(pulse1 + pulse2 + dmc);
+        pulse_scale * out
+    }
+}
 
 impl Clock for Exrom {
     fn clock(&mut self) -> usize {
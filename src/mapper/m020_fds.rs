@@ -0,0 +1,825 @@
+//! Famicom Disk System RAM Adapter (Mapper 020)
+//!
+//! <https://www.nesdev.org/wiki/Family_Computer_Disk_System>
+//! <https://www.nesdev.org/wiki/FDS_audio>
+
+use crate::{
+    cart::{
+        fds::{DiskDiff, DiskImage},
+        Cart,
+    },
+    common::{Clock, Kind, Regional, Reset},
+    mapper::{Mapped, MappedRead, MappedWrite, Mapper, MemMap},
+    ppu::Mirroring,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Default, Debug, Copy, Clone, Serialize, Deserialize)]
+#[must_use]
+pub struct FdsRegs {
+    irq_reload: u16,
+    irq_counter: u16,
+    irq_repeat: bool,
+    irq_enabled: bool,
+    timer_irq: bool,
+    disk_regs_enabled: bool,
+    sound_regs_enabled: bool,
+    write_data: u8,
+    read_data: u8,
+    motor_on: bool,
+    reset_transfer: bool,
+    read_mode: bool,
+    crc_control: bool,
+    disk_ready: bool,
+    disk_irq_enabled: bool,
+    disk_irq: bool,
+    transfer_complete: bool,
+    ext_connector: u8,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[must_use]
+pub struct Fds {
+    regs: FdsRegs,
+    mirroring: Mirroring,
+    audio: FdsAudio,
+    // Original image sides, used to diff disk writes
+    image: Vec<Vec<u8>>,
+    // Sides as seen by the drive, with gaps
+    sides: Vec<Vec<u8>>,
+    disk_side: Option<usize>,
+    disk_position: usize,
+    delay: u32,
+    end_of_head: bool,
+    scanning: bool,
+    gap_ended: bool,
+    crc: u16,
+    prev_crc_control: bool,
+}
+
+impl Fds {
+    const PRG_RAM_SIZE: usize = 32 * 1024;
+    const CHR_RAM_SIZE: usize = 8 * 1024;
+
+    // CPU cycles for the head to return to the start of the disk, and to transfer a byte
+    const SEEK_DELAY: u32 = 50_000;
+    const BYTE_DELAY: u32 = 150;
+
+    pub fn load(cart: &mut Cart, image: &DiskImage) -> Mapper {
+        cart.add_prg_ram(Self::PRG_RAM_SIZE);
+        cart.add_chr_ram(Self::CHR_RAM_SIZE);
+        let fds = Self {
+            regs: FdsRegs::default(),
+            mirroring: Mirroring::Vertical,
+            audio: FdsAudio::new(),
+            image: image.sides().to_vec(),
+            sides: image
+                .sides()
+                .iter()
+                .map(|side| DiskImage::add_gaps(side))
+                .collect(),
+            disk_side: Some(0),
+            disk_position: 0,
+            delay: 0,
+            end_of_head: true,
+            scanning: false,
+            gap_ended: false,
+            crc: 0,
+            prev_crc_control: false,
+        };
+        fds.into()
+    }
+
+    /// Number of disk sides in the loaded image.
+    #[inline]
+    #[must_use]
+    pub fn side_count(&self) -> usize {
+        self.sides.len()
+    }
+
+    /// Currently inserted disk side, if any.
+    #[inline]
+    #[must_use]
+    pub const fn disk_side(&self) -> Option<usize> {
+        self.disk_side
+    }
+
+    /// Insert a disk side, or eject the disk with `None`. Games expect the disk to be ejected
+    /// for a moment before inserting another side.
+    pub fn insert_disk(&mut self, side: Option<usize>) {
+        self.disk_side = side.filter(|side| *side < self.sides.len());
+        self.disk_position = 0;
+        self.end_of_head = true;
+        self.scanning = false;
+    }
+
+    /// Changes written to the disk, relative to the original image.
+    pub fn disk_diff(&self) -> DiskDiff {
+        let modified = self
+            .sides
+            .iter()
+            .map(|side| DiskImage::remove_gaps(side))
+            .collect::<Vec<_>>()
+            .concat();
+        DiskDiff::new(&self.image.concat(), &modified)
+    }
+
+    /// Restore changes previously written to the disk.
+    pub fn apply_disk_diff(&mut self, diff: &DiskDiff) {
+        let mut image = self.image.concat();
+        diff.apply(&mut image);
+        self.sides = image
+            .chunks(DiskImage::SIDE_SIZE)
+            .map(DiskImage::add_gaps)
+            .collect();
+    }
+
+    /// Expansion audio output.
+    #[inline]
+    #[must_use]
+    pub fn output(&self) -> f32 {
+        self.audio.output()
+    }
+
+    fn clock_timer_irq(&mut self) {
+        if self.regs.irq_enabled {
+            if self.regs.irq_counter == 0 {
+                self.regs.timer_irq = true;
+                self.regs.irq_counter = self.regs.irq_reload;
+                if !self.regs.irq_repeat {
+                    self.regs.irq_enabled = false;
+                }
+            } else {
+                self.regs.irq_counter -= 1;
+            }
+        }
+    }
+
+    fn update_crc(&mut self, val: u8) {
+        for bit in 0..8 {
+            let carry = self.crc & 0x01 == 0x01;
+            self.crc = (self.crc >> 1) | (u16::from((val >> bit) & 0x01) << 15);
+            if carry {
+                self.crc ^= 0x8408;
+            }
+        }
+    }
+
+    // Transfers one byte between the disk and the data registers every `BYTE_DELAY` cycles
+    // while the motor is on, starting over after the head reaches the end of the disk.
+    fn clock_disk(&mut self) {
+        let Some(side) = self.disk_side.filter(|_| self.regs.motor_on) else {
+            self.end_of_head = true;
+            self.scanning = false;
+            return;
+        };
+        if self.regs.reset_transfer && !self.scanning {
+            return;
+        }
+        if self.end_of_head {
+            self.delay = Self::SEEK_DELAY;
+            self.end_of_head = false;
+            self.disk_position = 0;
+            self.gap_ended = false;
+            return;
+        }
+        if self.delay > 0 {
+            self.delay -= 1;
+            return;
+        }
+
+        self.scanning = true;
+        let mut needs_irq = self.regs.disk_irq_enabled;
+        if self.regs.read_mode {
+            let val = self.sides[side][self.disk_position];
+            if !self.prev_crc_control {
+                self.update_crc(val);
+            }
+            if !self.regs.disk_ready {
+                self.gap_ended = false;
+                self.crc = 0;
+            } else if val > 0 && !self.gap_ended {
+                // The block start mark ends the gap and isn't transferred
+                self.gap_ended = true;
+                needs_irq = false;
+            }
+            if self.gap_ended {
+                self.regs.transfer_complete = true;
+                self.regs.read_data = val;
+                self.regs.disk_irq |= needs_irq;
+            }
+        } else {
+            let mut val = 0x00;
+            if !self.regs.crc_control {
+                self.regs.transfer_complete = true;
+                val = self.regs.write_data;
+                self.regs.disk_irq |= needs_irq;
+            }
+            if !self.regs.disk_ready {
+                val = 0x00;
+            }
+            if self.regs.crc_control {
+                if !self.prev_crc_control {
+                    self.update_crc(0x00);
+                    self.update_crc(0x00);
+                }
+                val = (self.crc & 0xFF) as u8;
+                self.crc >>= 8;
+            } else {
+                self.update_crc(val);
+            }
+            // The write head trails the read head by two bytes
+            if let Some(pos) = self.disk_position.checked_sub(2) {
+                self.sides[side][pos] = val;
+            }
+            self.gap_ended = false;
+        }
+
+        self.prev_crc_control = self.regs.crc_control;
+        self.disk_position += 1;
+        if self.disk_position >= self.sides[side].len() {
+            self.regs.motor_on = false;
+            self.end_of_head = true;
+        } else {
+            self.delay = Self::BYTE_DELAY;
+        }
+    }
+}
+
+impl MemMap for Fds {
+    // PPU $0000..=$1FFF 8K CHR-RAM
+    // CPU $4020..=$4026 RAM Adapter registers
+    // CPU $4030..=$4033 RAM Adapter status
+    // CPU $4040..=$4097 Audio registers
+    // CPU $6000..=$DFFF 32K PRG-RAM
+    // CPU $E000..=$FFFF 8K BIOS
+
+    fn map_read(&mut self, addr: u16) -> MappedRead {
+        let val = self.map_peek(addr);
+        match addr {
+            0x4030 if self.regs.disk_regs_enabled => {
+                self.regs.transfer_complete = false;
+                self.regs.timer_irq = false;
+                self.regs.disk_irq = false;
+            }
+            0x4031 if self.regs.disk_regs_enabled => {
+                self.regs.transfer_complete = false;
+                self.regs.disk_irq = false;
+            }
+            _ => (),
+        }
+        val
+    }
+
+    fn map_peek(&self, addr: u16) -> MappedRead {
+        // Unused bits return the upper byte of the address on the open bus
+        match addr {
+            0x0000..=0x1FFF => MappedRead::Chr(addr.into()),
+            0x4030..=0x4033 if self.regs.disk_regs_enabled => {
+                let val = match addr {
+                    // [.... ..TD]: Timer IRQ, Data transfer
+                    0x4030 => {
+                        u8::from(self.regs.timer_irq) | u8::from(self.regs.transfer_complete) << 1
+                    }
+                    0x4031 => return MappedRead::Data(self.regs.read_data),
+                    // [.... .PRS]: Write protected, not Ready, disk not Set
+                    0x4032 => {
+                        let ejected = u8::from(self.disk_side.is_none());
+                        let not_ready = u8::from(self.disk_side.is_none() || !self.scanning);
+                        ejected | not_ready << 1 | ejected << 2
+                    }
+                    // Battery is always good
+                    _ => self.regs.ext_connector & 0x7F | 0x80,
+                };
+                MappedRead::Data(0x40 | val)
+            }
+            0x4040..=0x4092 if self.regs.sound_regs_enabled => {
+                self.audio
+                    .read_register(addr)
+                    .map_or(MappedRead::None, |val| {
+                        // Only the lower 6 bits are driven
+                        MappedRead::Data(0x40 | val)
+                    })
+            }
+            0x6000..=0xDFFF => MappedRead::PrgRam((addr - 0x6000).into()),
+            0xE000..=0xFFFF => MappedRead::PrgRom((addr & 0x1FFF).into()),
+            _ => MappedRead::None,
+        }
+    }
+
+    fn map_write(&mut self, addr: u16, val: u8) -> MappedWrite {
+        match addr {
+            0x0000..=0x1FFF => return MappedWrite::Chr(addr.into(), val),
+            0x4020 => self.regs.irq_reload = (self.regs.irq_reload & 0xFF00) | u16::from(val),
+            0x4021 => {
+                self.regs.irq_reload = (self.regs.irq_reload & 0x00FF) | (u16::from(val) << 8);
+            }
+            0x4022 => {
+                // [.... ..ER]: Enabled, Repeat
+                self.regs.irq_repeat = val & 0x01 == 0x01;
+                self.regs.irq_enabled = val & 0x02 == 0x02 && self.regs.disk_regs_enabled;
+                if self.regs.irq_enabled {
+                    self.regs.irq_counter = self.regs.irq_reload;
+                } else {
+                    self.regs.timer_irq = false;
+                }
+            }
+            0x4023 => {
+                // [.... ..SD]: Sound registers enabled, Disk registers enabled
+                self.regs.disk_regs_enabled = val & 0x01 == 0x01;
+                self.regs.sound_regs_enabled = val & 0x02 == 0x02;
+                if !self.regs.disk_regs_enabled {
+                    self.regs.irq_enabled = false;
+                    self.regs.timer_irq = false;
+                    self.regs.disk_irq = false;
+                }
+            }
+            0x4024 if self.regs.disk_regs_enabled => {
+                self.regs.write_data = val;
+                self.regs.transfer_complete = false;
+                self.regs.disk_irq = false;
+            }
+            0x4025 if self.regs.disk_regs_enabled => {
+                // [IS1C HRTM]
+                //  |||| ||||
+                //  |||| |||+- Motor on
+                //  |||| ||+-- Transfer reset
+                //  |||| |+--- 1: Read, 0: Write
+                //  |||| +---- 1: Horizontal, 0: Vertical mirroring
+                //  |||+------ CRC control
+                //  |+-------- Disk ready, start reading/writing after the next gap
+                //  +--------- Byte transfer IRQ enabled
+                self.regs.motor_on = val & 0x01 == 0x01;
+                self.regs.reset_transfer = val & 0x02 == 0x02;
+                self.regs.read_mode = val & 0x04 == 0x04;
+                self.mirroring = if val & 0x08 == 0x08 {
+                    Mirroring::Horizontal
+                } else {
+                    Mirroring::Vertical
+                };
+                self.regs.crc_control = val & 0x10 == 0x10;
+                self.regs.disk_ready = val & 0x40 == 0x40;
+                self.regs.disk_irq_enabled = val & 0x80 == 0x80;
+                self.regs.disk_irq = false;
+            }
+            0x4026 if self.regs.disk_regs_enabled => self.regs.ext_connector = val,
+            0x4040..=0x408A if self.regs.sound_regs_enabled => {
+                self.audio.write_register(addr, val);
+            }
+            0x6000..=0xDFFF => return MappedWrite::PrgRam((addr - 0x6000).into(), val),
+            _ => (),
+        }
+        MappedWrite::None
+    }
+}
+
+impl Mapped for Fds {
+    #[inline]
+    fn irq_pending(&self) -> bool {
+        self.regs.timer_irq || self.regs.disk_irq
+    }
+
+    #[inline]
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    #[inline]
+    fn set_mirroring(&mut self, mirroring: Mirroring) {
+        self.mirroring = mirroring;
+    }
+}
+
+impl Clock for Fds {
+    fn clock(&mut self) -> usize {
+        self.clock_timer_irq();
+        self.audio.clock();
+        self.clock_disk();
+        1
+    }
+}
+
+impl Reset for Fds {
+    fn reset(&mut self, kind: Kind) {
+        self.regs = FdsRegs::default();
+        self.mirroring = Mirroring::Vertical;
+        self.end_of_head = true;
+        self.scanning = false;
+        self.audio.reset(kind);
+    }
+}
+
+impl Regional for Fds {}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[must_use]
+pub struct FdsAudio {
+    wave_table: Vec<u8>,
+    wave_write: bool,
+    wave_halt: bool,
+    envelopes_halt: bool,
+    master_volume: u8,
+    master_env_speed: u8,
+    wave_accum: u16,
+    wave_pos: u8,
+    volume: FdsEnvelope,
+    mod_env: FdsEnvelope,
+    modulator: FdsModulator,
+    out: u8,
+}
+
+impl Default for FdsAudio {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FdsAudio {
+    // Master volume 2/2, 2/3, 2/4 and 2/5, scaled by a maximum gain of 32 and wave of 63
+    const MASTER_VOLUME: [u32; 4] = [36, 24, 17, 14];
+
+    fn new() -> Self {
+        Self {
+            wave_table: vec![0; 64],
+            wave_write: false,
+            wave_halt: false,
+            envelopes_halt: false,
+            master_volume: 0,
+            master_env_speed: 0xE8,
+            wave_accum: 0,
+            wave_pos: 0,
+            volume: FdsEnvelope::new(),
+            mod_env: FdsEnvelope::new(),
+            modulator: FdsModulator::new(),
+            out: 0,
+        }
+    }
+
+    #[inline]
+    #[must_use]
+    fn output(&self) -> f32 {
+        f32::from(self.out) / 63.0
+    }
+
+    fn read_register(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x4040..=0x407F => Some(self.wave_table[(addr & 0x3F) as usize]),
+            0x4090 => Some(self.volume.gain),
+            0x4092 => Some(self.mod_env.gain),
+            _ => None,
+        }
+    }
+
+    fn write_register(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x4040..=0x407F if self.wave_write => {
+                self.wave_table[(addr & 0x3F) as usize] = val & 0x3F;
+            }
+            0x4080 => self.volume.write_control(val, self.master_env_speed),
+            0x4082 => self.volume.write_freq_lo(val),
+            0x4083 => {
+                // [HE.. FFFF]: Halt wave, halt Envelopes, Frequency high
+                self.volume.write_freq_hi(val);
+                self.wave_halt = val & 0x80 == 0x80;
+                self.envelopes_halt = val & 0x40 == 0x40;
+                if self.wave_halt {
+                    self.wave_accum = 0;
+                    self.wave_pos = 0;
+                }
+                if self.envelopes_halt {
+                    self.volume.reset_timer(self.master_env_speed);
+                    self.mod_env.reset_timer(self.master_env_speed);
+                }
+            }
+            0x4084 => self.mod_env.write_control(val, self.master_env_speed),
+            0x4085 => self.modulator.set_counter(val & 0x7F),
+            0x4086 => self.modulator.write_freq_lo(val),
+            0x4087 => self.modulator.write_freq_hi(val),
+            0x4088 => self.modulator.write_table(val),
+            0x4089 => {
+                // [W... ..VV]: Wave table Write enable, master Volume
+                self.wave_write = val & 0x80 == 0x80;
+                self.master_volume = val & 0x03;
+            }
+            0x408A => self.master_env_speed = val,
+            _ => (),
+        }
+    }
+
+    fn update_output(&mut self) {
+        let gain = u32::from(self.volume.gain.min(32));
+        let level = gain * Self::MASTER_VOLUME[self.master_volume as usize];
+        let sample = u32::from(self.wave_table[self.wave_pos as usize]);
+        self.out = (sample * level / 1152) as u8;
+    }
+}
+
+impl Clock for FdsAudio {
+    fn clock(&mut self) -> usize {
+        let freq = self.volume.frequency;
+        if !self.wave_halt && !self.envelopes_halt {
+            self.volume.clock(self.master_env_speed);
+            self.mod_env.clock(self.master_env_speed);
+        }
+        self.modulator.clock();
+        let pitch = self.modulator.pitch(freq, self.mod_env.gain);
+
+        // Output is held while the wave table is being written
+        if !self.wave_write {
+            self.update_output();
+            if !self.wave_halt && pitch > 0 {
+                let (accum, overflow) = self.wave_accum.overflowing_add(pitch);
+                self.wave_accum = accum;
+                if overflow {
+                    self.wave_pos = (self.wave_pos + 1) & 0x3F;
+                }
+            }
+        }
+        1
+    }
+}
+
+impl Reset for FdsAudio {
+    fn reset(&mut self, _kind: Kind) {
+        *self = Self::new();
+    }
+}
+
+#[derive(Default, Debug, Copy, Clone, Serialize, Deserialize)]
+#[must_use]
+struct FdsEnvelope {
+    speed: u8,
+    gain: u8,
+    increase: bool,
+    disabled: bool,
+    timer: u32,
+    frequency: u16,
+}
+
+impl FdsEnvelope {
+    const fn new() -> Self {
+        Self {
+            speed: 0,
+            gain: 0,
+            increase: false,
+            disabled: true,
+            timer: 0,
+            frequency: 0,
+        }
+    }
+
+    // [DISS SSSS]: Disabled, Increase, Speed or gain when disabled
+    fn write_control(&mut self, val: u8, master_speed: u8) {
+        self.speed = val & 0x3F;
+        self.increase = val & 0x40 == 0x40;
+        self.disabled = val & 0x80 == 0x80;
+        if self.disabled {
+            self.gain = self.speed;
+        }
+        self.reset_timer(master_speed);
+    }
+
+    fn write_freq_lo(&mut self, val: u8) {
+        self.frequency = (self.frequency & 0x0F00) | u16::from(val);
+    }
+
+    fn write_freq_hi(&mut self, val: u8) {
+        self.frequency = (self.frequency & 0x00FF) | (u16::from(val & 0x0F) << 8);
+    }
+
+    fn reset_timer(&mut self, master_speed: u8) {
+        self.timer = 8 * (u32::from(self.speed) + 1) * u32::from(master_speed);
+    }
+
+    fn clock(&mut self, master_speed: u8) {
+        if self.disabled || master_speed == 0 {
+            return;
+        }
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer == 0 {
+            self.reset_timer(master_speed);
+            if self.increase && self.gain < 32 {
+                self.gain += 1;
+            } else if !self.increase && self.gain > 0 {
+                self.gain -= 1;
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[must_use]
+struct FdsModulator {
+    table: Vec<u8>,
+    table_pos: u8,
+    counter: i8,
+    frequency: u16,
+    accum: u16,
+    halt: bool,
+}
+
+impl FdsModulator {
+    // Counter adjustment for each 3-bit table entry, where 4 resets the counter
+    const ADJUST: [i8; 8] = [0, 1, 2, 4, 0, -4, -2, -1];
+
+    fn new() -> Self {
+        Self {
+            table: vec![0; 64],
+            table_pos: 0,
+            counter: 0,
+            frequency: 0,
+            accum: 0,
+            halt: true,
+        }
+    }
+
+    // 7-bit signed counter
+    fn set_counter(&mut self, val: u8) {
+        self.counter = ((val << 1) as i8) >> 1;
+    }
+
+    fn write_freq_lo(&mut self, val: u8) {
+        self.frequency = (self.frequency & 0x0F00) | u16::from(val);
+    }
+
+    // [H... FFFF]: Halt, Frequency high
+    fn write_freq_hi(&mut self, val: u8) {
+        self.frequency = (self.frequency & 0x00FF) | (u16::from(val & 0x0F) << 8);
+        self.halt = val & 0x80 == 0x80;
+        if self.halt {
+            self.accum = 0;
+        }
+    }
+
+    // Table entries can only be written while halted, and each write fills two entries
+    fn write_table(&mut self, val: u8) {
+        if self.halt {
+            for _ in 0..2 {
+                self.table[self.table_pos as usize] = val & 0x07;
+                self.table_pos = (self.table_pos + 1) & 0x3F;
+            }
+        }
+    }
+
+    fn clock(&mut self) {
+        if self.halt || self.frequency == 0 {
+            return;
+        }
+        let (accum, overflow) = self.accum.overflowing_add(self.frequency);
+        self.accum = accum;
+        if overflow {
+            let entry = self.table[self.table_pos as usize];
+            if entry == 4 {
+                self.counter = 0;
+            } else {
+                self.set_counter((self.counter.wrapping_add(Self::ADJUST[entry as usize])) as u8);
+            }
+            self.table_pos = (self.table_pos + 1) & 0x3F;
+        }
+    }
+
+    // Wave pitch after modulation, following the reference implementation on the wiki.
+    fn pitch(&self, freq: u16, gain: u8) -> u16 {
+        if self.halt {
+            return freq;
+        }
+        let counter = i32::from(self.counter);
+        let mut temp = counter * i32::from(gain);
+        let remainder = temp & 0x0F;
+        temp >>= 4;
+        if remainder > 0 && temp & 0x80 == 0 {
+            temp += if counter < 0 { -1 } else { 2 };
+        }
+        if temp >= 192 {
+            temp -= 256;
+        } else if temp < -64 {
+            temp += 256;
+        }
+        temp *= i32::from(freq);
+        let remainder = temp & 0x3F;
+        temp >>= 6;
+        if remainder >= 32 {
+            temp += 1;
+        }
+        (i32::from(freq) + temp).clamp(0, i32::from(u16::MAX)) as u16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEAD_IN_GAP: usize = 28_300 / 8;
+
+    fn disk_image() -> DiskImage {
+        let mut side = b"\x01*NINTENDO-HVC*".to_vec();
+        side.resize(56, 0x00);
+        side.extend([0x02, 0x00]);
+        DiskImage::load(&side).expect("valid image")
+    }
+
+    fn fds(image: &DiskImage) -> Fds {
+        let mut cart = Cart::empty();
+        let Mapper::Fds(fds) = Fds::load(&mut cart, image) else {
+            panic!("expected Fds");
+        };
+        fds
+    }
+
+    // Clock until the next byte is transferred, returning the read data register.
+    fn transfer(fds: &mut Fds) -> u8 {
+        for _ in 0..Fds::SEEK_DELAY + 4096 * Fds::BYTE_DELAY {
+            fds.clock();
+            if fds.map_peek(0x4030) == MappedRead::Data(0x42) {
+                let MappedRead::Data(val) = fds.map_read(0x4031) else {
+                    panic!("expected data");
+                };
+                return val;
+            }
+        }
+        panic!("no byte transferred");
+    }
+
+    #[test]
+    fn disk_transfer() {
+        let image = disk_image();
+        let mut fds = fds(&image);
+        assert_eq!(fds.map_write(0x4023, 0x01), MappedWrite::None);
+
+        // Motor on in read mode, seeking back to the start of the disk
+        assert_eq!(fds.map_write(0x4025, 0x25), MappedWrite::None);
+        fds.clock();
+        assert_eq!(fds.map_peek(0x4032), MappedRead::Data(0x42), "seeking");
+        for _ in 0..=Fds::SEEK_DELAY {
+            fds.clock();
+        }
+        assert_eq!(fds.map_peek(0x4032), MappedRead::Data(0x40), "ready");
+
+        // Nothing is transferred until the gap ends with the block start mark
+        assert_eq!(fds.map_write(0x4025, 0x65), MappedWrite::None);
+        assert_eq!(transfer(&mut fds), 0x80, "block start mark");
+        assert_eq!(fds.disk_position, LEAD_IN_GAP + 1);
+        for &expected in &image.sides()[0][..16] {
+            assert_eq!(transfer(&mut fds), expected);
+        }
+
+        // The write head trails the read head by two bytes, so this overwrites the 15th byte
+        assert_eq!(fds.map_write(0x4025, 0x61), MappedWrite::None);
+        assert_eq!(fds.map_write(0x4024, 0xAA), MappedWrite::None);
+        transfer(&mut fds);
+        assert_eq!(fds.map_write(0x4025, 0x60), MappedWrite::None);
+
+        let diff = fds.disk_diff();
+        assert!(!diff.is_empty());
+        let mut expected = image.to_bytes();
+        expected[14] = 0xAA;
+        let mut modified = image.to_bytes();
+        diff.apply(&mut modified);
+        assert_eq!(modified, expected);
+
+        let mut restored = self::fds(&image);
+        restored.apply_disk_diff(&diff);
+        assert_eq!(restored.disk_diff(), diff);
+    }
+
+    #[test]
+    fn wave_output() {
+        let mut fds = fds(&disk_image());
+        let mut write =
+            |addr: u16, val: u8| assert_eq!(fds.map_write(addr, val), MappedWrite::None);
+        write(0x4023, 0x02);
+        write(0x4089, 0x80);
+        for (addr, val) in (0x4040..=0x407F).zip(0..) {
+            write(addr, val);
+        }
+        // Full master volume, fixed gain of 32 and a ramp every 2048 cycles
+        write(0x4089, 0x00);
+        write(0x4080, 0xA0);
+        write(0x4082, 0x00);
+        write(0x4083, 0x08);
+        assert_eq!(fds.map_peek(0x4050), MappedRead::Data(0x50));
+
+        let peak = |fds: &mut Fds| {
+            (0..4096).fold(0.0f32, |peak, _| {
+                fds.clock();
+                peak.max(fds.output())
+            })
+        };
+        assert!((peak(&mut fds) - 1.0).abs() < f32::EPSILON);
+
+        // Halting the wave resets it to the first entry
+        assert_eq!(fds.map_write(0x4083, 0x88), MappedWrite::None);
+        assert_eq!(peak(&mut fds), 0.0);
+    }
+
+    #[test]
+    fn modulator_pitch() {
+        let mut modulator = FdsModulator::new();
+        modulator.write_freq_hi(0x00);
+        assert_eq!(modulator.pitch(0x100, 0), 0x100);
+        modulator.set_counter(0x7F);
+        assert_eq!(modulator.counter, -1);
+        modulator.set_counter(0x10);
+        assert!(modulator.pitch(0x100, 0x20) > 0x100);
+    }
+}
@@ -0,0 +1,381 @@
+//! `VRC2`/`VRC4` (Mappers 021, 022, 023 and 025)
+//!
+//! <https://www.nesdev.org/wiki/VRC2_and_VRC4>
+
+use crate::{
+    cart::Cart,
+    common::{Clock, Kind, Regional, Reset},
+    mapper::{vrc_irq::VrcIrq, Mapped, MappedRead, MappedWrite, Mapper, MemMap},
+    mem::MemBanks,
+    ppu::Mirroring,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[must_use]
+pub enum Vrc2_4Revision {
+    /// VRC2: 1-bit mirroring, no PRG swap mode or IRQ
+    Vrc2,
+    /// VRC4: adds single-screen mirroring, PRG swap mode and an IRQ counter
+    Vrc4,
+}
+
+#[derive(Default, Debug, Copy, Clone, Serialize, Deserialize)]
+#[must_use]
+pub struct Vrc2_4Regs {
+    prg: [u8; 2],
+    prg_swap: bool,
+    chr: [u16; 8],
+    latch: u8,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[must_use]
+pub struct Vrc2_4 {
+    regs: Vrc2_4Regs,
+    revision: Vrc2_4Revision,
+    // CPU address lines connected to the chip's A0 and A1 register select pins
+    pins: [u16; 2],
+    // VRC2a ignores the lowest CHR bank bit
+    chr_shift: usize,
+    has_prg_ram: bool,
+    mirroring: Mirroring,
+    irq: VrcIrq,
+    chr_banks: MemBanks,
+    prg_rom_banks: MemBanks,
+}
+
+impl Vrc2_4 {
+    const PRG_RAM_SIZE: usize = 8 * 1024;
+    const PRG_WINDOW: usize = 8 * 1024;
+    const CHR_WINDOW: usize = 1024;
+    const CHR_RAM_SIZE: usize = 8 * 1024;
+
+    pub fn load(cart: &mut Cart) -> Mapper {
+        // NES 2.0 submappers select the exact board wiring. For iNES 1.0 dumps, where the wiring
+        // is unknown, both candidate address lines are combined since games only ever use one pair.
+        let (revision, pins, chr_shift) = match (cart.mapper_num(), cart.submapper_num()) {
+            (21, 1) => (Vrc2_4Revision::Vrc4, [0x02, 0x04], 0), // VRC4a
+            (21, 2) => (Vrc2_4Revision::Vrc4, [0x40, 0x80], 0), // VRC4c
+            (21, _) => (Vrc2_4Revision::Vrc4, [0x42, 0x84], 0),
+            (22, _) => (Vrc2_4Revision::Vrc2, [0x02, 0x01], 1), // VRC2a
+            (23, 1) => (Vrc2_4Revision::Vrc4, [0x01, 0x02], 0), // VRC4f
+            (23, 2) => (Vrc2_4Revision::Vrc4, [0x04, 0x08], 0), // VRC4e
+            (23, 3) => (Vrc2_4Revision::Vrc2, [0x01, 0x02], 0), // VRC2b
+            (25, 1) => (Vrc2_4Revision::Vrc4, [0x02, 0x01], 0), // VRC4b
+            (25, 2) => (Vrc2_4Revision::Vrc4, [0x08, 0x04], 0), // VRC4d
+            (25, 3) => (Vrc2_4Revision::Vrc2, [0x02, 0x01], 0), // VRC2c
+            (25, _) => (Vrc2_4Revision::Vrc4, [0x0A, 0x05], 0),
+            // Mapper 023
+            _ => (Vrc2_4Revision::Vrc4, [0x05, 0x0A], 0),
+        };
+
+        // VRC2 boards without PRG-RAM expose a 1-bit latch at $6000-$6FFF instead
+        let has_prg_ram = revision == Vrc2_4Revision::Vrc4 || cart.has_prg_ram();
+        if has_prg_ram && cart.prg_ram.len() < Self::PRG_RAM_SIZE {
+            cart.add_prg_ram(Self::PRG_RAM_SIZE);
+        }
+        if !cart.has_chr() {
+            cart.add_chr_ram(Self::CHR_RAM_SIZE);
+        }
+        let mut vrc2_4 = Self {
+            regs: Vrc2_4Regs::default(),
+            revision,
+            pins,
+            chr_shift,
+            has_prg_ram,
+            mirroring: cart.mirroring(),
+            irq: VrcIrq::default(),
+            chr_banks: MemBanks::new(0x0000, 0x1FFF, cart.chr_len(), Self::CHR_WINDOW),
+            prg_rom_banks: MemBanks::new(0x8000, 0xFFFF, cart.prg_rom.len(), Self::PRG_WINDOW),
+        };
+        vrc2_4.update_prg_banks();
+        vrc2_4.into()
+    }
+
+    #[inline]
+    pub const fn revision(&self) -> Vrc2_4Revision {
+        self.revision
+    }
+
+    /// Translate a CPU address to its register address, with A0 and A1 taken from the address
+    /// lines the board wires to the chip.
+    #[inline]
+    #[must_use]
+    const fn register(&self, addr: u16) -> u16 {
+        let a0 = (addr & self.pins[0] != 0) as u16;
+        let a1 = (addr & self.pins[1] != 0) as u16;
+        (addr & 0xF000) | (a1 << 1) | a0
+    }
+
+    fn update_prg_banks(&mut self) {
+        let second_last = self.prg_rom_banks.last().saturating_sub(1);
+        let (bank0, bank2) = if self.regs.prg_swap {
+            (second_last, self.regs.prg[0].into())
+        } else {
+            (self.regs.prg[0].into(), second_last)
+        };
+        self.prg_rom_banks.set(0, bank0);
+        self.prg_rom_banks.set(1, self.regs.prg[1].into());
+        self.prg_rom_banks.set(2, bank2);
+        self.prg_rom_banks.set(3, self.prg_rom_banks.last());
+    }
+
+    fn update_chr_banks(&mut self) {
+        for (slot, bank) in self.regs.chr.iter().enumerate() {
+            self.chr_banks
+                .set(slot, usize::from(*bank) >> self.chr_shift);
+        }
+    }
+
+    fn write_chr(&mut self, addr: u16, val: u8) {
+        // $B000 selects banks 0 and 1, $C000 banks 2 and 3, etc. with A1 picking the bank and A0
+        // picking the low or high bits.
+        let reg = ((((addr >> 12) - 0xB) << 1) | ((addr >> 1) & 0x01)) as usize;
+        let val = u16::from(val);
+        self.regs.chr[reg] = if addr & 0x01 == 0x00 {
+            (self.regs.chr[reg] & 0x1F0) | (val & 0x0F)
+        } else {
+            let mask = match self.revision {
+                Vrc2_4Revision::Vrc2 => 0x0F,
+                Vrc2_4Revision::Vrc4 => 0x1F,
+            };
+            (self.regs.chr[reg] & 0x0F) | ((val & mask) << 4)
+        };
+        self.update_chr_banks();
+    }
+}
+
+impl Mapped for Vrc2_4 {
+    #[inline]
+    fn irq_pending(&self) -> bool {
+        self.irq.pending()
+    }
+
+    #[inline]
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    #[inline]
+    fn set_mirroring(&mut self, mirroring: Mirroring) {
+        self.mirroring = mirroring;
+    }
+}
+
+impl MemMap for Vrc2_4 {
+    // PPU $0000..=$03FF 1K switchable CHR-ROM bank
+    // PPU $0400..=$07FF 1K switchable CHR-ROM bank
+    // PPU $0800..=$0BFF 1K switchable CHR-ROM bank
+    // PPU $0C00..=$0FFF 1K switchable CHR-ROM bank
+    // PPU $1000..=$13FF 1K switchable CHR-ROM bank
+    // PPU $1400..=$17FF 1K switchable CHR-ROM bank
+    // PPU $1800..=$1BFF 1K switchable CHR-ROM bank
+    // PPU $1C00..=$1FFF 1K switchable CHR-ROM bank
+    //
+    // CPU $6000..=$7FFF 8K PRG-RAM bank, or a 1-bit latch on VRC2 boards without PRG-RAM
+    // CPU $8000..=$9FFF 8K switchable PRG-ROM bank, or fixed to the second-last bank
+    // CPU $A000..=$BFFF 8K switchable PRG-ROM bank
+    // CPU $C000..=$DFFF 8K PRG-ROM bank, fixed to the second-last bank, or switchable
+    // CPU $E000..=$FFFF 8K PRG-ROM bank, fixed to the last bank
+
+    fn map_peek(&self, addr: u16) -> MappedRead {
+        match addr {
+            0x0000..=0x1FFF => MappedRead::Chr(self.chr_banks.translate(addr)),
+            0x6000..=0x7FFF if self.has_prg_ram => MappedRead::PrgRam((addr & 0x1FFF).into()),
+            0x6000..=0x6FFF => MappedRead::Data(self.regs.latch),
+            0x8000..=0xFFFF => MappedRead::PrgRom(self.prg_rom_banks.translate(addr)),
+            _ => MappedRead::None,
+        }
+    }
+
+    fn map_write(&mut self, addr: u16, val: u8) -> MappedWrite {
+        match addr {
+            0x0000..=0x1FFF => return MappedWrite::Chr(self.chr_banks.translate(addr), val),
+            0x6000..=0x7FFF if self.has_prg_ram => {
+                return MappedWrite::PrgRam((addr & 0x1FFF).into(), val);
+            }
+            0x6000..=0x6FFF => {
+                self.regs.latch = val & 0x01;
+                return MappedWrite::None;
+            }
+            _ => (),
+        }
+
+        let vrc4 = self.revision == Vrc2_4Revision::Vrc4;
+        match self.register(addr) {
+            0x8000..=0x8003 => {
+                // [...P PPPP]
+                //     | ||||
+                //     +-++++- Select 8 KB PRG-ROM bank at $8000-$9FFF, or $C000-$DFFF when swapped
+                self.regs.prg[0] = val & 0x1F;
+                self.update_prg_banks();
+            }
+            0x9000..=0x9001 if vrc4 => {
+                self.mirroring = match val & 0x03 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::SingleScreenA,
+                    3 => Mirroring::SingleScreenB,
+                    _ => unreachable!("impossible mirroring mode"),
+                };
+            }
+            0x9002 if vrc4 => {
+                // [.... ..MW]
+                //         |+- PRG-RAM enable, ignored as most boards don't honor it
+                //         +-- PRG swap mode
+                self.regs.prg_swap = val & 0x02 == 0x02;
+                self.update_prg_banks();
+            }
+            0x9000..=0x9003 if !vrc4 => {
+                self.mirroring = match val & 0x01 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    _ => unreachable!("impossible mirroring mode"),
+                };
+            }
+            0xA000..=0xA003 => {
+                // [...P PPPP]
+                //     | ||||
+                //     +-++++- Select 8 KB PRG-ROM bank at $A000-$BFFF
+                self.regs.prg[1] = val & 0x1F;
+                self.update_prg_banks();
+            }
+            addr @ 0xB000..=0xE003 => self.write_chr(addr, val),
+            0xF000 if vrc4 => self.irq.write_reload_low(val),
+            0xF001 if vrc4 => self.irq.write_reload_high(val),
+            0xF002 if vrc4 => self.irq.write_control(val),
+            0xF003 if vrc4 => self.irq.acknowledge(),
+            _ => (),
+        }
+        MappedWrite::None
+    }
+}
+
+impl Clock for Vrc2_4 {
+    fn clock(&mut self) -> usize {
+        if self.revision == Vrc2_4Revision::Vrc4 {
+            self.irq.clock();
+        }
+        1
+    }
+}
+
+impl Reset for Vrc2_4 {
+    fn reset(&mut self, kind: Kind) {
+        self.irq.reset(kind);
+    }
+}
+
+impl Regional for Vrc2_4 {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapper::tests::load;
+
+    const PRG_ROM_SIZE: usize = 256 * 1024;
+    const CHR_ROM_SIZE: usize = 256 * 1024;
+
+    #[test]
+    fn prg_swap_mode() {
+        let mut vrc4: Vrc2_4 = load(25, 1, PRG_ROM_SIZE, CHR_ROM_SIZE);
+        let bank = |bank: usize| MappedRead::PrgRom(bank * Vrc2_4::PRG_WINDOW);
+        let last_bank = PRG_ROM_SIZE / Vrc2_4::PRG_WINDOW - 1;
+        assert_eq!(vrc4.map_write(0x8000, 0x03), MappedWrite::None);
+        assert_eq!(vrc4.map_write(0xA000, 0x05), MappedWrite::None);
+        assert_eq!(vrc4.map_peek(0x8000), bank(3));
+        assert_eq!(vrc4.map_peek(0xA000), bank(5));
+        assert_eq!(vrc4.map_peek(0xC000), bank(last_bank - 1));
+        assert_eq!(vrc4.map_peek(0xE000), bank(last_bank));
+
+        // VRC4b wires A1 to the chip's A0, so $9001 is the swap mode register
+        assert_eq!(vrc4.map_write(0x9001, 0x02), MappedWrite::None);
+        assert_eq!(vrc4.map_peek(0x8000), bank(last_bank - 1));
+        assert_eq!(vrc4.map_peek(0xC000), bank(3));
+        assert_eq!(vrc4.map_write(0x9000, 0x03), MappedWrite::None);
+        assert_eq!(vrc4.mirroring(), Mirroring::SingleScreenB);
+    }
+
+    #[test]
+    fn chr_banks() {
+        let bank = |bank: usize| MappedRead::Chr(bank * Vrc2_4::CHR_WINDOW);
+
+        // VRC4f: A0/A1
+        let mut vrc4: Vrc2_4 = load(23, 1, PRG_ROM_SIZE, CHR_ROM_SIZE);
+        assert_eq!(vrc4.map_write(0xB000, 0x05), MappedWrite::None);
+        assert_eq!(vrc4.map_write(0xB001, 0x01), MappedWrite::None);
+        assert_eq!(vrc4.map_write(0xE003, 0x1F), MappedWrite::None);
+        assert_eq!(vrc4.map_write(0xE002, 0x02), MappedWrite::None);
+        assert_eq!(vrc4.map_peek(0x0000), bank(0x15));
+        assert_eq!(vrc4.map_peek(0x1C00), bank(0xF2));
+
+        // VRC4c: A6/A7
+        let mut vrc4: Vrc2_4 = load(21, 2, PRG_ROM_SIZE, CHR_ROM_SIZE);
+        assert_eq!(vrc4.map_write(0xC080, 0x07), MappedWrite::None);
+        assert_eq!(vrc4.map_peek(0x0C00), bank(7));
+        assert_eq!(vrc4.map_write(0xC002, 0x09), MappedWrite::None);
+        assert_eq!(vrc4.map_peek(0x0800), bank(9));
+
+        // VRC2a: A1/A0 with the lowest CHR bit ignored
+        let mut vrc2: Vrc2_4 = load(22, 0, PRG_ROM_SIZE, CHR_ROM_SIZE);
+        assert_eq!(vrc2.map_write(0xB000, 0x07), MappedWrite::None);
+        assert_eq!(vrc2.map_write(0xB002, 0x01), MappedWrite::None);
+        assert_eq!(vrc2.map_peek(0x0000), bank(0x0B));
+    }
+
+    #[test]
+    fn ines_heuristics() {
+        // iNES 1.0 mapper 023 responds to both VRC4e and VRC4f wiring
+        let mut vrc4: Vrc2_4 = load(23, 0, PRG_ROM_SIZE, CHR_ROM_SIZE);
+        assert_eq!(vrc4.map_write(0x9000, 0x01), MappedWrite::None);
+        assert_eq!(vrc4.mirroring(), Mirroring::Horizontal);
+        assert_eq!(vrc4.map_write(0x9004, 0x00), MappedWrite::None);
+        assert_eq!(vrc4.mirroring(), Mirroring::Vertical);
+        assert_eq!(vrc4.map_write(0xB00C, 0x03), MappedWrite::None);
+        assert_eq!(vrc4.map_write(0xB003, 0x00), MappedWrite::None);
+        assert_eq!(vrc4.map_peek(0x0400), MappedRead::Chr(0x0000));
+        assert_eq!(vrc4.map_peek(0x0000), MappedRead::Chr(0x0000));
+        assert_eq!(vrc4.map_write(0xB00C, 0x04), MappedWrite::None);
+        assert_eq!(
+            vrc4.map_peek(0x0400),
+            MappedRead::Chr(0x40 * Vrc2_4::CHR_WINDOW)
+        );
+    }
+
+    #[test]
+    fn vrc2_latch() {
+        let mut vrc2: Vrc2_4 = load(23, 3, PRG_ROM_SIZE, CHR_ROM_SIZE);
+        assert_eq!(vrc2.revision(), Vrc2_4Revision::Vrc2);
+        assert_eq!(vrc2.map_write(0x6000, 0xFF), MappedWrite::None);
+        assert_eq!(vrc2.map_peek(0x6000), MappedRead::Data(0x01));
+        assert_eq!(vrc2.map_write(0x9002, 0x01), MappedWrite::None);
+        assert_eq!(vrc2.mirroring(), Mirroring::Horizontal);
+        assert_eq!(vrc2.map_write(0x9002, 0x02), MappedWrite::None);
+        assert_eq!(vrc2.mirroring(), Mirroring::Vertical);
+
+        let vrc4: Vrc2_4 = load(23, 2, PRG_ROM_SIZE, CHR_ROM_SIZE);
+        assert_eq!(vrc4.map_peek(0x6000), MappedRead::PrgRam(0x0000));
+    }
+
+    #[test]
+    fn irq() {
+        let mut vrc4: Vrc2_4 = load(21, 1, PRG_ROM_SIZE, CHR_ROM_SIZE);
+        assert_eq!(vrc4.map_write(0xF000, 0x0E), MappedWrite::None);
+        assert_eq!(vrc4.map_write(0xF002, 0x0F), MappedWrite::None);
+        assert_eq!(vrc4.map_write(0xF004, 0x06), MappedWrite::None);
+        vrc4.clock();
+        assert!(!vrc4.irq_pending());
+        vrc4.clock();
+        assert!(vrc4.irq_pending());
+        assert_eq!(vrc4.map_write(0xF006, 0x00), MappedWrite::None);
+        assert!(!vrc4.irq_pending());
+
+        let mut vrc2: Vrc2_4 = load(22, 0, PRG_ROM_SIZE, CHR_ROM_SIZE);
+        assert_eq!(vrc2.map_write(0xF000, 0xFF), MappedWrite::None);
+        assert_eq!(vrc2.map_write(0xF001, 0x0F), MappedWrite::None);
+        assert_eq!(vrc2.map_write(0xF003, 0x06), MappedWrite::None);
+        vrc2.clock();
+        assert!(!vrc2.irq_pending());
+    }
+}
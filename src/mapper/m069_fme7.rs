@@ -0,0 +1,654 @@
+//! `Sunsoft FME-7`/`5A`/`5B` (Mapper 069)
+//!
+//! <https://www.nesdev.org/wiki/Sunsoft_FME-7>
+//! <https://www.nesdev.org/wiki/Sunsoft_5B_audio>
+
+use crate::{
+    cart::Cart,
+    common::{Clock, Kind, Regional, Reset},
+    mapper::{Mapped, MappedRead, MappedWrite, Mapper, MemMap},
+    mem::MemBanks,
+    ppu::Mirroring,
+};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+#[derive(Default, Debug, Copy, Clone, Serialize, Deserialize)]
+#[must_use]
+pub struct Fme7Regs {
+    command: u8,
+    // [ERBB BBBB]
+    //  ||++-++++- Select 8K bank at $6000-$7FFF
+    //  |+-------- 0: PRG-ROM, 1: PRG-RAM
+    //  +--------- PRG-RAM enable
+    prg_6000: u8,
+    irq_enabled: bool,
+    irq_counter_enabled: bool,
+    irq_counter: u16,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[must_use]
+pub struct Fme7 {
+    regs: Fme7Regs,
+    mirroring: Mirroring,
+    irq_pending: bool,
+    audio: Sunsoft5bAudio,
+    chr_banks: MemBanks,
+    prg_ram_banks: MemBanks,
+    prg_rom_6000_banks: MemBanks,
+    prg_rom_banks: MemBanks,
+}
+
+impl Fme7 {
+    const PRG_RAM_SIZE: usize = 8 * 1024;
+    const PRG_WINDOW: usize = 8 * 1024;
+    const CHR_WINDOW: usize = 1024;
+    const CHR_RAM_SIZE: usize = 8 * 1024;
+
+    const PRG_BANK_MASK: u8 = 0x3F;
+    const PRG_RAM_SELECT_MASK: u8 = 0x40;
+    const PRG_RAM_ENABLE_MASK: u8 = 0x80;
+
+    pub fn load(cart: &mut Cart) -> Mapper {
+        if !cart.has_prg_ram() {
+            cart.add_prg_ram(Self::PRG_RAM_SIZE);
+        }
+        if !cart.has_chr() {
+            cart.add_chr_ram(Self::CHR_RAM_SIZE);
+        }
+        let mut fme7 = Self {
+            regs: Fme7Regs::default(),
+            mirroring: cart.mirroring(),
+            irq_pending: false,
+            audio: Sunsoft5bAudio::new(),
+            chr_banks: MemBanks::new(0x0000, 0x1FFF, cart.chr_len(), Self::CHR_WINDOW),
+            prg_ram_banks: MemBanks::new(0x6000, 0x7FFF, cart.prg_ram.len(), Self::PRG_WINDOW),
+            prg_rom_6000_banks: MemBanks::new(0x6000, 0x7FFF, cart.prg_rom.len(), Self::PRG_WINDOW),
+            prg_rom_banks: MemBanks::new(0x8000, 0xFFFF, cart.prg_rom.len(), Self::PRG_WINDOW),
+        };
+        let last_bank = fme7.prg_rom_banks.last();
+        fme7.prg_rom_banks.set(3, last_bank);
+        fme7.into()
+    }
+
+    /// Expansion audio output.
+    #[inline]
+    #[must_use]
+    pub const fn output(&self) -> f32 {
+        self.audio.output()
+    }
+
+    /// Returns whether square `channel` (0-2) is audible.
+    #[inline]
+    #[must_use]
+    pub const fn channel_enabled(&self, channel: usize) -> bool {
+        !self.audio.muted[channel]
+    }
+
+    /// Mute or unmute square `channel` (0-2).
+    #[inline]
+    pub fn toggle_channel(&mut self, channel: usize) {
+        self.audio.muted[channel] = !self.audio.muted[channel];
+    }
+
+    #[inline]
+    #[must_use]
+    const fn prg_ram_selected(&self) -> bool {
+        self.regs.prg_6000 & Self::PRG_RAM_SELECT_MASK == Self::PRG_RAM_SELECT_MASK
+    }
+
+    #[inline]
+    #[must_use]
+    const fn prg_ram_enabled(&self) -> bool {
+        self.regs.prg_6000 & Self::PRG_RAM_ENABLE_MASK == Self::PRG_RAM_ENABLE_MASK
+    }
+
+    fn write_parameter(&mut self, val: u8) {
+        match self.regs.command {
+            0x00..=0x07 => self.chr_banks.set(self.regs.command.into(), val.into()),
+            0x08 => {
+                self.regs.prg_6000 = val;
+                let bank = (val & Self::PRG_BANK_MASK).into();
+                self.prg_ram_banks.set(0, bank);
+                self.prg_rom_6000_banks.set(0, bank);
+            }
+            0x09..=0x0B => {
+                let slot = usize::from(self.regs.command - 0x09);
+                self.prg_rom_banks
+                    .set(slot, (val & Self::PRG_BANK_MASK).into());
+            }
+            0x0C => {
+                self.mirroring = match val & 0x03 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::SingleScreenA,
+                    3 => Mirroring::SingleScreenB,
+                    _ => unreachable!("impossible mirroring mode"),
+                };
+            }
+            0x0D => {
+                // [C... ...T]
+                //  |       +- IRQ enable
+                //  +--------- IRQ counter enable
+                self.regs.irq_enabled = val & 0x01 == 0x01;
+                self.regs.irq_counter_enabled = val & 0x80 == 0x80;
+                self.irq_pending = false;
+            }
+            0x0E => self.regs.irq_counter = (self.regs.irq_counter & 0xFF00) | u16::from(val),
+            0x0F => {
+                self.regs.irq_counter = (self.regs.irq_counter & 0x00FF) | (u16::from(val) << 8);
+            }
+            _ => unreachable!("impossible command: {}", self.regs.command),
+        }
+    }
+}
+
+impl Mapped for Fme7 {
+    #[inline]
+    fn irq_pending(&self) -> bool {
+        self.irq_pending
+    }
+
+    #[inline]
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    #[inline]
+    fn set_mirroring(&mut self, mirroring: Mirroring) {
+        self.mirroring = mirroring;
+    }
+}
+
+impl MemMap for Fme7 {
+    // PPU $0000..=$03FF 1K switchable CHR bank
+    // PPU $0400..=$07FF 1K switchable CHR bank
+    // PPU $0800..=$0BFF 1K switchable CHR bank
+    // PPU $0C00..=$0FFF 1K switchable CHR bank
+    // PPU $1000..=$13FF 1K switchable CHR bank
+    // PPU $1400..=$17FF 1K switchable CHR bank
+    // PPU $1800..=$1BFF 1K switchable CHR bank
+    // PPU $1C00..=$1FFF 1K switchable CHR bank
+    //
+    // CPU $6000..=$7FFF 8K switchable PRG-ROM or PRG-RAM bank
+    // CPU $8000..=$9FFF 8K switchable PRG-ROM bank
+    // CPU $A000..=$BFFF 8K switchable PRG-ROM bank
+    // CPU $C000..=$DFFF 8K switchable PRG-ROM bank
+    // CPU $E000..=$FFFF 8K PRG-ROM bank, fixed to the last bank
+
+    fn map_peek(&self, addr: u16) -> MappedRead {
+        match addr {
+            0x0000..=0x1FFF => MappedRead::Chr(self.chr_banks.translate(addr)),
+            0x6000..=0x7FFF if self.prg_ram_selected() => {
+                if self.prg_ram_enabled() {
+                    MappedRead::PrgRam(self.prg_ram_banks.translate(addr))
+                } else {
+                    MappedRead::None
+                }
+            }
+            0x6000..=0x7FFF => MappedRead::PrgRom(self.prg_rom_6000_banks.translate(addr)),
+            0x8000..=0xFFFF => MappedRead::PrgRom(self.prg_rom_banks.translate(addr)),
+            _ => MappedRead::None,
+        }
+    }
+
+    fn map_write(&mut self, addr: u16, val: u8) -> MappedWrite {
+        match addr {
+            0x0000..=0x1FFF => return MappedWrite::Chr(self.chr_banks.translate(addr), val),
+            0x6000..=0x7FFF if self.prg_ram_selected() && self.prg_ram_enabled() => {
+                return MappedWrite::PrgRam(self.prg_ram_banks.translate(addr), val);
+            }
+            0x8000..=0x9FFF => self.regs.command = val & 0x0F,
+            0xA000..=0xBFFF => self.write_parameter(val),
+            0xC000..=0xDFFF => self.audio.write_select(val),
+            0xE000..=0xFFFF => self.audio.write_data(val),
+            _ => (),
+        }
+        MappedWrite::None
+    }
+}
+
+impl Clock for Fme7 {
+    fn clock(&mut self) -> usize {
+        if self.regs.irq_counter_enabled {
+            self.regs.irq_counter = self.regs.irq_counter.wrapping_sub(1);
+            if self.regs.irq_counter == 0xFFFF && self.regs.irq_enabled {
+                self.irq_pending = true;
+            }
+        }
+        self.audio.clock();
+        1
+    }
+}
+
+impl Reset for Fme7 {
+    fn reset(&mut self, kind: Kind) {
+        self.regs = Fme7Regs::default();
+        self.irq_pending = false;
+        self.audio.reset(kind);
+    }
+}
+
+impl Regional for Fme7 {}
+
+// Output level for each 5-bit envelope or volume level, in 1.5dB steps
+static VOLUME_TABLE: Lazy<[f32; 32]> = Lazy::new(|| {
+    let mut table = [0.0; 32];
+    for (level, val) in table.iter_mut().enumerate().skip(1) {
+        *val = 10f32.powf(-((31 - level) as f32) * 1.5 / 20.0);
+    }
+    table
+});
+
+#[derive(Default, Debug, Copy, Clone, Serialize, Deserialize)]
+#[must_use]
+pub struct Sunsoft5bSquare {
+    period: u16,
+    counter: u16,
+    // [...E VVVV] Envelope enable and volume
+    volume: u8,
+    tone_disabled: bool,
+    noise_disabled: bool,
+    out: bool,
+}
+
+impl Sunsoft5bSquare {
+    fn clock(&mut self) {
+        self.counter += 1;
+        if self.counter >= self.period.max(1) {
+            self.counter = 0;
+            self.out = !self.out;
+        }
+    }
+
+    // 5-bit output level, with 4-bit volumes spanning 3dB steps
+    const fn level(&self, noise: bool, envelope: u8) -> u8 {
+        let on = (self.out || self.tone_disabled) && (noise || self.noise_disabled);
+        if !on {
+            0
+        } else if self.volume & 0x10 == 0x10 {
+            envelope
+        } else if self.volume & 0x0F == 0 {
+            0
+        } else {
+            ((self.volume & 0x0F) << 1) | 0x01
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[must_use]
+pub struct Sunsoft5bNoise {
+    period: u8,
+    counter: u16,
+    shift: u32,
+}
+
+impl Default for Sunsoft5bNoise {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sunsoft5bNoise {
+    const fn new() -> Self {
+        Self {
+            period: 0,
+            counter: 0,
+            shift: 0x01,
+        }
+    }
+
+    fn clock(&mut self) {
+        // Noise runs at half the rate of the squares
+        let period = if self.period == 0 {
+            1
+        } else {
+            self.period as u16
+        };
+        self.counter += 1;
+        if self.counter >= 2 * period {
+            self.counter = 0;
+            // 17-bit LFSR
+            let feedback = (self.shift ^ (self.shift >> 3)) & 0x01;
+            self.shift = (self.shift >> 1) | (feedback << 16);
+        }
+    }
+
+    #[inline]
+    #[must_use]
+    const fn out(&self) -> bool {
+        self.shift & 0x01 == 0x01
+    }
+}
+
+#[derive(Default, Debug, Copy, Clone, Serialize, Deserialize)]
+#[must_use]
+pub struct Sunsoft5bEnvelope {
+    period: u16,
+    counter: u16,
+    step: u8,
+    attack: u8,
+    alternate: bool,
+    hold: bool,
+    holding: bool,
+}
+
+impl Sunsoft5bEnvelope {
+    // [.... CAaH] Continue, Attack, Alternate and Hold. Restarts the envelope.
+    fn write_shape(&mut self, val: u8) {
+        self.attack = if val & 0x04 == 0x04 { 0x1F } else { 0x00 };
+        if val & 0x08 == 0x00 {
+            // Without continue, the envelope stops at zero after one cycle
+            self.hold = true;
+            self.alternate = self.attack == 0x1F;
+        } else {
+            self.hold = val & 0x01 == 0x01;
+            self.alternate = val & 0x02 == 0x02;
+        }
+        self.step = 0x1F;
+        self.counter = 0;
+        self.holding = false;
+    }
+
+    fn clock(&mut self) {
+        let period = if self.period == 0 { 1 } else { self.period };
+        self.counter += 1;
+        if self.counter < period {
+            return;
+        }
+        self.counter = 0;
+        if self.holding {
+            return;
+        }
+        if self.step > 0 {
+            self.step -= 1;
+        } else if self.hold {
+            if self.alternate {
+                self.attack ^= 0x1F;
+            }
+            self.holding = true;
+        } else {
+            if self.alternate {
+                self.attack ^= 0x1F;
+            }
+            self.step = 0x1F;
+        }
+    }
+
+    // 5-bit output level
+    #[inline]
+    #[must_use]
+    const fn level(&self) -> u8 {
+        self.step ^ self.attack
+    }
+}
+
+/// AY-3-8910 derived sound chip with 3 square channels, a noise generator and an envelope
+/// generator, all clocked at the CPU rate.
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[must_use]
+pub struct Sunsoft5bAudio {
+    select: u8,
+    squares: [Sunsoft5bSquare; 3],
+    noise: Sunsoft5bNoise,
+    envelope: Sunsoft5bEnvelope,
+    muted: [bool; 3],
+    divider: u8,
+    out: f32,
+}
+
+impl Default for Sunsoft5bAudio {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sunsoft5bAudio {
+    // Squares toggle every `period` steps of 16 CPU cycles, for a 32 * period cycle wave
+    const CLOCK_DIVIDER: u8 = 16;
+
+    fn new() -> Self {
+        Self {
+            select: 0x00,
+            squares: [Sunsoft5bSquare::default(); 3],
+            noise: Sunsoft5bNoise::new(),
+            envelope: Sunsoft5bEnvelope::default(),
+            muted: [false; 3],
+            divider: 0,
+            out: 0.0,
+        }
+    }
+
+    #[inline]
+    #[must_use]
+    const fn output(&self) -> f32 {
+        self.out
+    }
+
+    // Selects with any of the upper 4 bits set don't match a register, so data writes are ignored
+    #[inline]
+    fn write_select(&mut self, val: u8) {
+        self.select = val;
+    }
+
+    fn write_data(&mut self, val: u8) {
+        match self.select {
+            0x00 | 0x02 | 0x04 => {
+                let square = &mut self.squares[usize::from(self.select >> 1)];
+                square.period = (square.period & 0x0F00) | u16::from(val);
+            }
+            0x01 | 0x03 | 0x05 => {
+                let square = &mut self.squares[usize::from(self.select >> 1)];
+                square.period = (square.period & 0x00FF) | (u16::from(val & 0x0F) << 8);
+            }
+            0x06 => self.noise.period = val & 0x1F,
+            0x07 => {
+                // [..NN NTTT] Noise and tone disable for channels C, B and A
+                for (i, square) in self.squares.iter_mut().enumerate() {
+                    square.tone_disabled = val & (0x01 << i) != 0;
+                    square.noise_disabled = val & (0x08 << i) != 0;
+                }
+            }
+            0x08..=0x0A => self.squares[usize::from(self.select - 0x08)].volume = val & 0x1F,
+            0x0B => self.envelope.period = (self.envelope.period & 0xFF00) | u16::from(val),
+            0x0C => {
+                self.envelope.period = (self.envelope.period & 0x00FF) | (u16::from(val) << 8);
+            }
+            0x0D => self.envelope.write_shape(val),
+            _ => (),
+        }
+    }
+}
+
+impl Clock for Sunsoft5bAudio {
+    fn clock(&mut self) -> usize {
+        self.divider += 1;
+        if self.divider == Self::CLOCK_DIVIDER {
+            self.divider = 0;
+            for square in &mut self.squares {
+                square.clock();
+            }
+            self.noise.clock();
+            self.envelope.clock();
+        }
+
+        let noise = self.noise.out();
+        let envelope = self.envelope.level();
+        let mut out = 0.0;
+        for (square, muted) in self.squares.iter().zip(self.muted) {
+            if !muted {
+                out += VOLUME_TABLE[usize::from(square.level(noise, envelope))];
+            }
+        }
+        self.out = out / 3.0;
+        1
+    }
+}
+
+impl Reset for Sunsoft5bAudio {
+    fn reset(&mut self, _kind: Kind) {
+        let muted = self.muted;
+        *self = Self::new();
+        self.muted = muted;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapper::tests::load;
+
+    const PRG_ROM_SIZE: usize = 256 * 1024;
+    const CHR_ROM_SIZE: usize = 256 * 1024;
+
+    fn command(fme7: &mut Fme7, command: u8, val: u8) {
+        assert_eq!(fme7.map_write(0x8000, command), MappedWrite::None);
+        assert_eq!(fme7.map_write(0xA000, val), MappedWrite::None);
+    }
+
+    fn audio(fme7: &mut Fme7, reg: u8, val: u8) {
+        assert_eq!(fme7.map_write(0xC000, reg), MappedWrite::None);
+        assert_eq!(fme7.map_write(0xE000, val), MappedWrite::None);
+    }
+
+    fn peak(fme7: &mut Fme7, cycles: usize) -> f32 {
+        let mut peak = 0.0f32;
+        for _ in 0..cycles {
+            fme7.clock();
+            peak = peak.max(fme7.output());
+        }
+        peak
+    }
+
+    #[test]
+    fn banks() {
+        let mut fme7: Fme7 = load(69, 0, PRG_ROM_SIZE, CHR_ROM_SIZE);
+        let prg = |bank: usize| MappedRead::PrgRom(bank * Fme7::PRG_WINDOW);
+        let chr = |bank: usize| MappedRead::Chr(bank * Fme7::CHR_WINDOW);
+        command(&mut fme7, 0x09, 0x01);
+        command(&mut fme7, 0x0A, 0x02);
+        command(&mut fme7, 0x0B, 0x43);
+        assert_eq!(fme7.map_peek(0x8000), prg(1));
+        assert_eq!(fme7.map_peek(0xA000), prg(2));
+        assert_eq!(fme7.map_peek(0xC000), prg(3));
+        assert_eq!(
+            fme7.map_peek(0xE000),
+            prg(PRG_ROM_SIZE / Fme7::PRG_WINDOW - 1)
+        );
+        for bank in 0..8u8 {
+            command(&mut fme7, bank, 0x20 + bank);
+        }
+        for bank in 0..8u8 {
+            assert_eq!(
+                fme7.map_peek(u16::from(bank) * 0x0400),
+                chr((0x20 + bank).into())
+            );
+        }
+        command(&mut fme7, 0x0C, 0x03);
+        assert_eq!(fme7.mirroring(), Mirroring::SingleScreenB);
+    }
+
+    #[test]
+    fn prg_6000_window() {
+        let mut fme7: Fme7 = load(69, 0, PRG_ROM_SIZE, CHR_ROM_SIZE);
+        command(&mut fme7, 0x08, 0x05);
+        assert_eq!(
+            fme7.map_peek(0x6001),
+            MappedRead::PrgRom(5 * Fme7::PRG_WINDOW + 1)
+        );
+        assert_eq!(fme7.map_write(0x6001, 0x42), MappedWrite::None);
+
+        // RAM selected but disabled
+        command(&mut fme7, 0x08, 0x40);
+        assert_eq!(fme7.map_peek(0x6000), MappedRead::None);
+        assert_eq!(fme7.map_write(0x6000, 0x42), MappedWrite::None);
+
+        command(&mut fme7, 0x08, 0xC0);
+        assert_eq!(fme7.map_peek(0x7FFF), MappedRead::PrgRam(0x1FFF));
+        assert_eq!(
+            fme7.map_write(0x6000, 0x42),
+            MappedWrite::PrgRam(0x0000, 0x42)
+        );
+    }
+
+    #[test]
+    fn irq() {
+        let mut fme7: Fme7 = load(69, 0, PRG_ROM_SIZE, CHR_ROM_SIZE);
+        command(&mut fme7, 0x0E, 0x01);
+        command(&mut fme7, 0x0F, 0x00);
+        command(&mut fme7, 0x0D, 0x81);
+        fme7.clock();
+        assert!(!fme7.irq_pending());
+        fme7.clock();
+        assert!(fme7.irq_pending());
+        command(&mut fme7, 0x0D, 0x81);
+        assert!(!fme7.irq_pending());
+
+        // The counter keeps running without IRQs enabled
+        command(&mut fme7, 0x0D, 0x80);
+        for _ in 0..0x10000 {
+            fme7.clock();
+        }
+        assert!(!fme7.irq_pending());
+        assert_eq!(fme7.regs.irq_counter, 0xFFFF);
+    }
+
+    #[test]
+    fn squares() {
+        let mut fme7: Fme7 = load(69, 0, PRG_ROM_SIZE, CHR_ROM_SIZE);
+        assert_eq!(peak(&mut fme7, 1024), 0.0);
+
+        // Channel B tone at full volume
+        audio(&mut fme7, 0x02, 0x10);
+        audio(&mut fme7, 0x07, 0x3D);
+        audio(&mut fme7, 0x09, 0x0F);
+        assert!((peak(&mut fme7, 1024) - 1.0 / 3.0).abs() < f32::EPSILON);
+
+        fme7.toggle_channel(1);
+        assert!(!fme7.channel_enabled(1));
+        assert_eq!(peak(&mut fme7, 1024), 0.0);
+        fme7.toggle_channel(1);
+
+        // Registers are ignored unless the upper select bits are clear
+        audio(&mut fme7, 0x19, 0x00);
+        assert!(peak(&mut fme7, 1024) > 0.0);
+        audio(&mut fme7, 0x09, 0x00);
+        assert_eq!(peak(&mut fme7, 1024), 0.0);
+    }
+
+    #[test]
+    fn envelope() {
+        let mut envelope = Sunsoft5bEnvelope::default();
+        envelope.period = 1;
+
+        // Decay once then hold at zero
+        envelope.write_shape(0x00);
+        assert_eq!(envelope.level(), 0x1F);
+        for _ in 0..0x1F {
+            envelope.clock();
+        }
+        assert_eq!(envelope.level(), 0x00);
+        envelope.clock();
+        envelope.clock();
+        assert_eq!(envelope.level(), 0x00);
+
+        // Attack then hold at the top
+        envelope.write_shape(0x0D);
+        assert_eq!(envelope.level(), 0x00);
+        for _ in 0..0x20 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.level(), 0x1F);
+
+        // Triangle
+        envelope.write_shape(0x0E);
+        for _ in 0..0x20 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.level(), 0x1F);
+        envelope.clock();
+        assert_eq!(envelope.level(), 0x1E);
+    }
+}
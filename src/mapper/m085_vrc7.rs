@@ -0,0 +1,845 @@
+//! `VRC7` (Mapper 085)
+//!
+//! <https://www.nesdev.org/wiki/VRC7>
+//! <https://www.nesdev.org/wiki/VRC7_audio>
+
+use crate::{
+    cart::Cart,
+    common::{Clock, Kind, Regional, Reset},
+    mapper::{vrc_irq::VrcIrq, Mapped, MappedRead, MappedWrite, Mapper, MemMap},
+    mem::MemBanks,
+    ppu::Mirroring,
+};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[must_use]
+pub struct Vrc7 {
+    control: u8,
+    // CPU address line connected to the chip's register select pin. A4 on VRC7a, A3 on VRC7b
+    pin: u16,
+    mirroring: Mirroring,
+    irq: VrcIrq,
+    audio: Vrc7Audio,
+    chr_banks: MemBanks,
+    prg_rom_banks: MemBanks,
+}
+
+impl Vrc7 {
+    const PRG_RAM_SIZE: usize = 8 * 1024;
+    const PRG_WINDOW: usize = 8 * 1024;
+    const CHR_WINDOW: usize = 1024;
+    const CHR_RAM_SIZE: usize = 8 * 1024;
+
+    const SILENCE_MASK: u8 = 0x40;
+    const PRG_RAM_ENABLE_MASK: u8 = 0x80;
+
+    pub fn load(cart: &mut Cart) -> Mapper {
+        if !cart.has_prg_ram() {
+            cart.add_prg_ram(Self::PRG_RAM_SIZE);
+        }
+        if !cart.has_chr() {
+            cart.add_chr_ram(Self::CHR_RAM_SIZE);
+        }
+        // iNES 1.0 dumps don't say which line is wired, so respond to either
+        let pin = match cart.submapper_num() {
+            1 => 0x08,
+            2 => 0x10,
+            _ => 0x18,
+        };
+        let mut vrc7 = Self {
+            control: 0x00,
+            pin,
+            mirroring: cart.mirroring(),
+            irq: VrcIrq::default(),
+            audio: Vrc7Audio::new(),
+            chr_banks: MemBanks::new(0x0000, 0x1FFF, cart.chr_len(), Self::CHR_WINDOW),
+            prg_rom_banks: MemBanks::new(0x8000, 0xFFFF, cart.prg_rom.len(), Self::PRG_WINDOW),
+        };
+        let last_bank = vrc7.prg_rom_banks.last();
+        vrc7.prg_rom_banks.set(3, last_bank);
+        vrc7.into()
+    }
+
+    /// Expansion audio output.
+    #[inline]
+    #[must_use]
+    pub const fn output(&self) -> f32 {
+        self.audio.output()
+    }
+
+    /// Returns whether FM `channel` (0-5) is audible.
+    #[inline]
+    #[must_use]
+    pub const fn channel_enabled(&self, channel: usize) -> bool {
+        !self.audio.muted[channel]
+    }
+
+    /// Mute or unmute FM `channel` (0-5).
+    #[inline]
+    pub fn toggle_channel(&mut self, channel: usize) {
+        self.audio.muted[channel] = !self.audio.muted[channel];
+    }
+
+    #[inline]
+    #[must_use]
+    const fn prg_ram_enabled(&self) -> bool {
+        self.control & Self::PRG_RAM_ENABLE_MASK == Self::PRG_RAM_ENABLE_MASK
+    }
+}
+
+impl Mapped for Vrc7 {
+    #[inline]
+    fn irq_pending(&self) -> bool {
+        self.irq.pending()
+    }
+
+    #[inline]
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    #[inline]
+    fn set_mirroring(&mut self, mirroring: Mirroring) {
+        self.mirroring = mirroring;
+    }
+}
+
+impl MemMap for Vrc7 {
+    // PPU $0000..=$03FF 1K switchable CHR bank
+    // PPU $0400..=$07FF 1K switchable CHR bank
+    // PPU $0800..=$0BFF 1K switchable CHR bank
+    // PPU $0C00..=$0FFF 1K switchable CHR bank
+    // PPU $1000..=$13FF 1K switchable CHR bank
+    // PPU $1400..=$17FF 1K switchable CHR bank
+    // PPU $1800..=$1BFF 1K switchable CHR bank
+    // PPU $1C00..=$1FFF 1K switchable CHR bank
+    //
+    // CPU $6000..=$7FFF 8K PRG-RAM bank, fixed
+    // CPU $8000..=$9FFF 8K switchable PRG-ROM bank
+    // CPU $A000..=$BFFF 8K switchable PRG-ROM bank
+    // CPU $C000..=$DFFF 8K switchable PRG-ROM bank
+    // CPU $E000..=$FFFF 8K PRG-ROM bank, fixed to the last bank
+
+    fn map_peek(&self, addr: u16) -> MappedRead {
+        match addr {
+            0x0000..=0x1FFF => MappedRead::Chr(self.chr_banks.translate(addr)),
+            0x6000..=0x7FFF if self.prg_ram_enabled() => MappedRead::PrgRam((addr & 0x1FFF).into()),
+            0x8000..=0xFFFF => MappedRead::PrgRom(self.prg_rom_banks.translate(addr)),
+            _ => MappedRead::None,
+        }
+    }
+
+    fn map_write(&mut self, addr: u16, val: u8) -> MappedWrite {
+        match addr {
+            0x0000..=0x1FFF => return MappedWrite::Chr(self.chr_banks.translate(addr), val),
+            0x6000..=0x7FFF if self.prg_ram_enabled() => {
+                return MappedWrite::PrgRam((addr & 0x1FFF).into(), val);
+            }
+            _ => (),
+        }
+
+        let select = if addr & self.pin == 0x00 { 0x00 } else { 0x10 };
+        match (addr & 0xF000) | select {
+            0x8000 => self.prg_rom_banks.set(0, (val & 0x3F).into()),
+            0x8010 => self.prg_rom_banks.set(1, (val & 0x3F).into()),
+            0x9000 => self.prg_rom_banks.set(2, (val & 0x3F).into()),
+            // Audio data is at $9030, so A5 distinguishes it from the register select
+            0x9010 if addr & 0x20 == 0x20 => self.audio.write_data(val),
+            0x9010 => self.audio.write_select(val),
+            addr @ (0xA000..=0xD010) => {
+                let bank = (((addr - 0xA000) >> 11) | ((addr >> 4) & 0x01)) as usize;
+                self.chr_banks.set(bank, val.into());
+            }
+            0xE000 => {
+                // [RS.. ..MM]
+                //  ||     ||
+                //  ||     ++- Mirroring
+                //  |+-------- Silence expansion audio
+                //  +--------- PRG-RAM enable
+                self.control = val;
+                self.mirroring = match val & 0x03 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::SingleScreenA,
+                    3 => Mirroring::SingleScreenB,
+                    _ => unreachable!("impossible mirroring mode"),
+                };
+                self.audio
+                    .set_silenced(val & Self::SILENCE_MASK == Self::SILENCE_MASK);
+            }
+            0xE010 => self.irq.write_reload(val),
+            0xF000 => self.irq.write_control(val),
+            0xF010 => self.irq.acknowledge(),
+            _ => (),
+        }
+        MappedWrite::None
+    }
+}
+
+impl Clock for Vrc7 {
+    fn clock(&mut self) -> usize {
+        self.irq.clock();
+        self.audio.clock();
+        1
+    }
+}
+
+impl Reset for Vrc7 {
+    fn reset(&mut self, kind: Kind) {
+        self.control = 0x00;
+        self.irq.reset(kind);
+        self.audio.reset(kind);
+    }
+}
+
+impl Regional for Vrc7 {}
+
+// Built-in instrument patches 1-15. Patch 0 is the user-defined patch in registers $00-$07.
+// Dumped from the VRC7 die by Nuke.YKT.
+const PATCHES: [[u8; 8]; 15] = [
+    [0x03, 0x21, 0x05, 0x06, 0xE8, 0x81, 0x42, 0x27], // Buzzy bell
+    [0x13, 0x41, 0x14, 0x0D, 0xD8, 0xF6, 0x23, 0x12], // Guitar
+    [0x11, 0x11, 0x08, 0x08, 0xFA, 0xB2, 0x20, 0x12], // Wurly
+    [0x31, 0x61, 0x0C, 0x07, 0xA8, 0x64, 0x61, 0x27], // Flute
+    [0x32, 0x21, 0x1E, 0x06, 0xE1, 0x76, 0x01, 0x28], // Clarinet
+    [0x02, 0x01, 0x06, 0x00, 0xA3, 0xE2, 0xF4, 0xF4], // Synth
+    [0x21, 0x61, 0x1D, 0x07, 0x82, 0x81, 0x11, 0x07], // Trumpet
+    [0x23, 0x21, 0x22, 0x17, 0xA2, 0x72, 0x01, 0x17], // Organ
+    [0x35, 0x11, 0x25, 0x00, 0x40, 0x73, 0x72, 0x01], // Bells
+    [0xB5, 0x01, 0x0F, 0x0F, 0xA8, 0xA5, 0x51, 0x02], // Vibes
+    [0x17, 0xC1, 0x24, 0x07, 0xF8, 0xF8, 0x22, 0x12], // Vibraphone
+    [0x71, 0x23, 0x11, 0x06, 0x65, 0x74, 0x18, 0x16], // Tutti
+    [0x01, 0x02, 0xD3, 0x05, 0xC9, 0x95, 0x03, 0x02], // Fretless
+    [0x61, 0x63, 0x0C, 0x00, 0x94, 0xC0, 0x33, 0xF6], // Synth bass
+    [0x21, 0x72, 0x0D, 0x00, 0xC1, 0xD5, 0x56, 0x06], // Sweep
+];
+
+// Frequency multipliers, doubled so the x0.5 multiplier stays integral
+const MULTIPLIERS: [u32; 16] = [1, 2, 4, 6, 8, 10, 12, 14, 16, 18, 20, 20, 24, 24, 30, 30];
+
+// Key scale level attenuation by upper F-Number bits in 0.375dB units, for octave 7
+const KEY_SCALE_LEVELS: [u16; 16] = [
+    0, 24, 32, 37, 40, 43, 45, 47, 48, 50, 51, 52, 53, 54, 55, 56,
+];
+
+// Vibrato F-Number offsets by upper F-Number bits and vibrato step
+const VIBRATO: [[i16; 8]; 8] = [
+    [0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 1, 0, 0, 0, -1, 0],
+    [0, 1, 2, 1, 0, -1, -2, -1],
+    [0, 1, 3, 1, 0, -1, -3, -1],
+    [0, 2, 4, 2, 0, -2, -4, -2],
+    [0, 2, 5, 2, 0, -2, -5, -2],
+    [0, 3, 6, 3, 0, -3, -6, -3],
+    [0, 3, 7, 3, 0, -3, -7, -3],
+];
+
+// Envelope increments for the low 2 bits of the rate, stepped through by the envelope counter
+const ENVELOPE_STEPS: [[u16; 8]; 4] = [
+    [0, 1, 0, 1, 0, 1, 0, 1],
+    [0, 1, 0, 1, 1, 1, 0, 1],
+    [0, 1, 1, 1, 0, 1, 1, 1],
+    [0, 1, 1, 1, 1, 1, 1, 1],
+];
+const FAST_ENVELOPE_STEPS: [[u16; 8]; 4] = [
+    [1, 1, 1, 1, 1, 1, 1, 1],
+    [1, 1, 1, 2, 1, 1, 1, 2],
+    [1, 2, 1, 2, 1, 2, 1, 2],
+    [1, 2, 2, 2, 1, 2, 2, 2],
+];
+
+// Operators work in the log domain like the real chip: a quarter sine wave of -log2(sin(x)) and
+// an exponent table to convert back, both in 1/256 units.
+static LOG_SIN: Lazy<[u16; 256]> = Lazy::new(|| {
+    let mut table = [0; 256];
+    for (i, val) in table.iter_mut().enumerate() {
+        let x = (i as f64 + 0.5) * std::f64::consts::PI / 512.0;
+        *val = (-x.sin().log2() * 256.0).round() as u16;
+    }
+    table
+});
+static EXP: Lazy<[u16; 256]> = Lazy::new(|| {
+    let mut table = [0; 256];
+    for (i, val) in table.iter_mut().enumerate() {
+        *val = ((-(i as f64) / 256.0).exp2() * 4096.0).round() as u16;
+    }
+    table
+});
+
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[must_use]
+pub enum EnvelopeStage {
+    Attack,
+    Decay,
+    Sustain,
+    #[default]
+    Release,
+}
+
+/// One operator of an FM channel, either the modulator or the carrier.
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[must_use]
+pub struct Vrc7Slot {
+    phase: u32,
+    // Attenuation in 0.1875dB units, 511 is silent
+    envelope: u16,
+    stage: EnvelopeStage,
+}
+
+impl Default for Vrc7Slot {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Vrc7Slot {
+    const MAX_ATTENUATION: u16 = 0x1FF;
+    const PHASE_MASK: u32 = 0x7FFFF;
+
+    const fn new() -> Self {
+        Self {
+            phase: 0,
+            envelope: Self::MAX_ATTENUATION,
+            stage: EnvelopeStage::Release,
+        }
+    }
+
+    fn key_on(&mut self) {
+        self.phase = 0;
+        self.stage = EnvelopeStage::Attack;
+    }
+
+    fn key_off(&mut self) {
+        self.stage = EnvelopeStage::Release;
+    }
+
+    fn clock_envelope(
+        &mut self,
+        patch: &Vrc7Patch,
+        slot: usize,
+        rks: u8,
+        sustain: bool,
+        counter: u32,
+    ) {
+        let rate = |rate: u8| {
+            if rate == 0 {
+                0
+            } else {
+                (4 * rate + rks).min(63)
+            }
+        };
+        match self.stage {
+            EnvelopeStage::Attack => {
+                let rate = rate(patch.attack_rate(slot));
+                if rate >= 60 {
+                    self.envelope = 0;
+                } else {
+                    let step = i32::from(Self::envelope_step(rate, counter));
+                    let delta = (-(i32::from(self.envelope) + 1) * step) >> 4;
+                    self.envelope = (i32::from(self.envelope) + delta).max(0) as u16;
+                }
+                if self.envelope == 0 {
+                    self.stage = EnvelopeStage::Decay;
+                }
+            }
+            EnvelopeStage::Decay => {
+                self.envelope += Self::envelope_step(rate(patch.decay_rate(slot)), counter);
+                if self.envelope >= u16::from(patch.sustain_level(slot)) << 4 {
+                    self.stage = EnvelopeStage::Sustain;
+                }
+            }
+            EnvelopeStage::Sustain => {
+                // Percussive tones keep decaying at the release rate while held
+                if !patch.sustained(slot) {
+                    self.envelope += Self::envelope_step(rate(patch.release_rate(slot)), counter);
+                }
+            }
+            EnvelopeStage::Release => {
+                let release_rate = if sustain {
+                    5
+                } else if patch.sustained(slot) {
+                    patch.release_rate(slot)
+                } else {
+                    7
+                };
+                self.envelope += Self::envelope_step(rate(release_rate), counter);
+            }
+        }
+        self.envelope = self.envelope.min(Self::MAX_ATTENUATION);
+    }
+
+    fn envelope_step(rate: u8, counter: u32) -> u16 {
+        if rate < 4 {
+            0
+        } else if rate < 48 {
+            let shift = 11 - u32::from(rate >> 2);
+            if counter & ((1 << shift) - 1) == 0 {
+                ENVELOPE_STEPS[usize::from(rate & 0x03)][((counter >> shift) & 0x07) as usize]
+            } else {
+                0
+            }
+        } else {
+            FAST_ENVELOPE_STEPS[usize::from(rate & 0x03)][(counter & 0x07) as usize]
+                << ((rate >> 2) - 12)
+        }
+    }
+
+    // Compute a signed 13-bit sample for a 10-bit phase and attenuation in 0.1875dB units.
+    fn compute(phase: u32, attenuation: u16, rectify: bool) -> i32 {
+        let phase = phase & 0x3FF;
+        let negative = phase & 0x200 == 0x200;
+        if negative && rectify {
+            0
+        } else {
+            let quarter = if phase & 0x100 == 0x100 {
+                !phase & 0xFF
+            } else {
+                phase & 0xFF
+            };
+            let level = u32::from(LOG_SIN[quarter as usize]) + (u32::from(attenuation) << 3);
+            let shift = level >> 8;
+            let amplitude = if shift < 13 {
+                i32::from(EXP[(level & 0xFF) as usize] >> shift)
+            } else {
+                0
+            };
+            if negative {
+                -amplitude
+            } else {
+                amplitude
+            }
+        }
+    }
+}
+
+/// An instrument patch. Slot 0 is the modulator and slot 1 the carrier.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Vrc7Patch([u8; 8]);
+
+impl Vrc7Patch {
+    const fn tremolo(&self, slot: usize) -> bool {
+        self.0[slot] & 0x80 == 0x80
+    }
+
+    const fn vibrato(&self, slot: usize) -> bool {
+        self.0[slot] & 0x40 == 0x40
+    }
+
+    const fn sustained(&self, slot: usize) -> bool {
+        self.0[slot] & 0x20 == 0x20
+    }
+
+    const fn key_scale_rate(&self, slot: usize) -> bool {
+        self.0[slot] & 0x10 == 0x10
+    }
+
+    const fn multiplier(&self, slot: usize) -> u32 {
+        MULTIPLIERS[(self.0[slot] & 0x0F) as usize]
+    }
+
+    const fn key_scale_level(&self, slot: usize) -> u8 {
+        self.0[2 + slot] >> 6
+    }
+
+    const fn total_level(&self) -> u8 {
+        self.0[2] & 0x3F
+    }
+
+    const fn rectify(&self, slot: usize) -> bool {
+        let mask = if slot == 0 { 0x08 } else { 0x10 };
+        self.0[3] & mask == mask
+    }
+
+    const fn feedback(&self) -> u8 {
+        self.0[3] & 0x07
+    }
+
+    const fn attack_rate(&self, slot: usize) -> u8 {
+        self.0[4 + slot] >> 4
+    }
+
+    const fn decay_rate(&self, slot: usize) -> u8 {
+        self.0[4 + slot] & 0x0F
+    }
+
+    const fn sustain_level(&self, slot: usize) -> u8 {
+        self.0[6 + slot] >> 4
+    }
+
+    const fn release_rate(&self, slot: usize) -> u8 {
+        self.0[6 + slot] & 0x0F
+    }
+}
+
+#[derive(Default, Debug, Copy, Clone, Serialize, Deserialize)]
+#[must_use]
+pub struct Vrc7Channel {
+    fnum: u16,
+    block: u8,
+    sustain: bool,
+    key_on: bool,
+    instrument: u8,
+    volume: u8,
+    feedback: [i32; 2],
+    slots: [Vrc7Slot; 2],
+}
+
+impl Vrc7Channel {
+    // $20-$25: [..SK BBBF] Sustain, Key on, Block and F-Number bit 8
+    fn write_control(&mut self, val: u8) {
+        self.fnum = (self.fnum & 0xFF) | (u16::from(val & 0x01) << 8);
+        self.block = (val >> 1) & 0x07;
+        self.sustain = val & 0x20 == 0x20;
+        let key_on = val & 0x10 == 0x10;
+        if key_on && !self.key_on {
+            self.slots[0].key_on();
+            self.slots[1].key_on();
+        } else if !key_on && self.key_on {
+            self.slots[1].key_off();
+        }
+        self.key_on = key_on;
+    }
+
+    fn clock(&mut self, patch: &Vrc7Patch, counters: &Vrc7Counters) -> i32 {
+        let rks = ((self.block << 1) | (self.fnum >> 8) as u8) & 0x0F;
+        let ksl_base = KEY_SCALE_LEVELS[usize::from(self.fnum >> 5)]
+            .saturating_sub(16 * u16::from(7 - self.block));
+
+        let mut outputs = [0; 2];
+        for slot in 0..2 {
+            let rks = if patch.key_scale_rate(slot) {
+                rks
+            } else {
+                rks >> 2
+            };
+            self.slots[slot].clock_envelope(patch, slot, rks, self.sustain, counters.envelope);
+
+            let fnum = if patch.vibrato(slot) {
+                let offset = VIBRATO[usize::from(self.fnum >> 6)][counters.vibrato_step()];
+                self.fnum.wrapping_add_signed(offset) & 0x1FF
+            } else {
+                self.fnum
+            };
+            let increment = ((u32::from(fnum) << self.block) * patch.multiplier(slot)) >> 1;
+            self.slots[slot].phase = (self.slots[slot].phase + increment) & Vrc7Slot::PHASE_MASK;
+
+            let key_scale = match patch.key_scale_level(slot) {
+                0 => 0,
+                level => ksl_base >> (3 - level),
+            };
+            let level = if slot == 0 {
+                u16::from(patch.total_level()) << 2
+            } else {
+                u16::from(self.volume) << 4
+            };
+            let tremolo = if patch.tremolo(slot) {
+                counters.tremolo()
+            } else {
+                0
+            };
+            let attenuation = (self.slots[slot].envelope + level + (key_scale << 1) + tremolo)
+                .min(Vrc7Slot::MAX_ATTENUATION);
+
+            let modulation = if slot == 0 {
+                match patch.feedback() {
+                    0 => 0,
+                    feedback => (self.feedback[0] + self.feedback[1]) >> (9 - feedback),
+                }
+            } else {
+                outputs[0] >> 1
+            };
+            let phase = (self.slots[slot].phase >> 9).wrapping_add_signed(modulation);
+            outputs[slot] = Vrc7Slot::compute(phase, attenuation, patch.rectify(slot));
+        }
+        self.feedback = [self.feedback[1], outputs[0]];
+        outputs[1]
+    }
+}
+
+#[derive(Default, Debug, Copy, Clone, Serialize, Deserialize)]
+#[must_use]
+struct Vrc7Counters {
+    envelope: u32,
+    tremolo: u16,
+    vibrato: u16,
+}
+
+impl Vrc7Counters {
+    // Tremolo is a 3.7Hz triangle wave, 210 steps of 64 samples
+    const TREMOLO_PERIOD: u16 = 210 * 64;
+
+    fn clock(&mut self) {
+        self.envelope = self.envelope.wrapping_add(1);
+        self.tremolo = (self.tremolo + 1) % Self::TREMOLO_PERIOD;
+        self.vibrato = self.vibrato.wrapping_add(1);
+    }
+
+    // Tremolo attenuation in 0.1875dB units, up to 4.875dB
+    const fn tremolo(&self) -> u16 {
+        let step = self.tremolo / 64;
+        let level = if step < 105 { step } else { 210 - step };
+        level * 26 / 105
+    }
+
+    // Vibrato is a 6.4Hz wave of 8 steps, 1024 samples each
+    const fn vibrato_step(&self) -> usize {
+        ((self.vibrato >> 10) & 0x07) as usize
+    }
+}
+
+/// YM2413 (OPLL) derived FM synthesizer with 6 channels of 2 operators each.
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[must_use]
+pub struct Vrc7Audio {
+    select: u8,
+    custom_patch: [u8; 8],
+    channels: [Vrc7Channel; 6],
+    muted: [bool; 6],
+    silenced: bool,
+    divider: u8,
+    counters: Vrc7Counters,
+    out: f32,
+}
+
+impl Default for Vrc7Audio {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Vrc7Audio {
+    // The chip generates one sample every 72 cycles of its 3.58MHz clock, or 36 CPU cycles
+    const CLOCK_DIVIDER: u8 = 36;
+    const MAX_OUTPUT: f32 = 6.0 * 4096.0;
+
+    fn new() -> Self {
+        Self {
+            select: 0x00,
+            custom_patch: [0x00; 8],
+            channels: [Vrc7Channel::default(); 6],
+            muted: [false; 6],
+            silenced: false,
+            divider: 0,
+            counters: Vrc7Counters::default(),
+            out: 0.0,
+        }
+    }
+
+    #[inline]
+    #[must_use]
+    const fn output(&self) -> f32 {
+        self.out
+    }
+
+    #[inline]
+    fn write_select(&mut self, val: u8) {
+        self.select = val;
+    }
+
+    fn write_data(&mut self, val: u8) {
+        let channel = usize::from(self.select & 0x0F);
+        match self.select {
+            0x00..=0x07 => self.custom_patch[usize::from(self.select)] = val,
+            0x10..=0x15 => {
+                let channel = &mut self.channels[channel];
+                channel.fnum = (channel.fnum & 0x100) | u16::from(val);
+            }
+            0x20..=0x25 => self.channels[channel].write_control(val),
+            0x30..=0x35 => {
+                // [IIII VVVV] Instrument and volume
+                self.channels[channel].instrument = val >> 4;
+                self.channels[channel].volume = val & 0x0F;
+            }
+            _ => (),
+        }
+    }
+
+    fn set_silenced(&mut self, silenced: bool) {
+        self.silenced = silenced;
+        if silenced {
+            // Silencing holds the chip in reset
+            let (custom_patch, muted) = (self.custom_patch, self.muted);
+            *self = Self::new();
+            self.custom_patch = custom_patch;
+            self.muted = muted;
+            self.silenced = true;
+        }
+    }
+
+    fn patch(&self, instrument: u8) -> Vrc7Patch {
+        match instrument {
+            0 => Vrc7Patch(self.custom_patch),
+            instrument => Vrc7Patch(PATCHES[usize::from(instrument) - 1]),
+        }
+    }
+
+    fn clock_sample(&mut self) {
+        self.counters.clock();
+        let mut sample = 0;
+        for channel in 0..self.channels.len() {
+            let patch = self.patch(self.channels[channel].instrument);
+            let output = self.channels[channel].clock(&patch, &self.counters);
+            if !self.muted[channel] {
+                sample += output;
+            }
+        }
+        self.out = sample as f32 / Self::MAX_OUTPUT;
+    }
+}
+
+impl Clock for Vrc7Audio {
+    fn clock(&mut self) -> usize {
+        if self.silenced {
+            return 0;
+        }
+        self.divider += 1;
+        if self.divider == Self::CLOCK_DIVIDER {
+            self.divider = 0;
+            self.clock_sample();
+        }
+        1
+    }
+}
+
+impl Reset for Vrc7Audio {
+    fn reset(&mut self, _kind: Kind) {
+        let muted = self.muted;
+        *self = Self::new();
+        self.muted = muted;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapper::tests::load;
+
+    const PRG_ROM_SIZE: usize = 512 * 1024;
+    const CHR_ROM_SIZE: usize = 256 * 1024;
+
+    fn clock_samples(vrc7: &mut Vrc7, samples: usize) -> f32 {
+        let mut peak = 0.0f32;
+        for _ in 0..samples {
+            for _ in 0..Vrc7Audio::CLOCK_DIVIDER {
+                vrc7.clock();
+            }
+            peak = peak.max(vrc7.output().abs());
+        }
+        peak
+    }
+
+    #[test]
+    fn banks() {
+        let prg = |bank: usize| MappedRead::PrgRom(bank * Vrc7::PRG_WINDOW);
+        let chr = |bank: usize| MappedRead::Chr(bank * Vrc7::CHR_WINDOW);
+
+        // VRC7a: A4
+        let mut vrc7a: Vrc7 = load(85, 2, PRG_ROM_SIZE, CHR_ROM_SIZE);
+        assert_eq!(vrc7a.map_write(0x8000, 0x01), MappedWrite::None);
+        assert_eq!(vrc7a.map_write(0x8010, 0x02), MappedWrite::None);
+        assert_eq!(vrc7a.map_write(0x9000, 0x03), MappedWrite::None);
+        assert_eq!(vrc7a.map_peek(0x8000), prg(1));
+        assert_eq!(vrc7a.map_peek(0xA000), prg(2));
+        assert_eq!(vrc7a.map_peek(0xC000), prg(3));
+        assert_eq!(
+            vrc7a.map_peek(0xE000),
+            prg(PRG_ROM_SIZE / Vrc7::PRG_WINDOW - 1)
+        );
+        for (i, addr) in [
+            0xA000, 0xA010, 0xB000, 0xB010, 0xC000, 0xC010, 0xD000, 0xD010,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            assert_eq!(vrc7a.map_write(addr, 0x10 + i as u8), MappedWrite::None);
+        }
+        for i in 0..8 {
+            assert_eq!(vrc7a.map_peek(i as u16 * 0x0400), chr(0x10 + i));
+        }
+
+        // VRC7b: A3
+        let mut vrc7b: Vrc7 = load(85, 1, PRG_ROM_SIZE, CHR_ROM_SIZE);
+        assert_eq!(vrc7b.map_write(0x8008, 0x05), MappedWrite::None);
+        assert_eq!(vrc7b.map_peek(0xA000), prg(5));
+        assert_eq!(vrc7b.map_write(0xD008, 0x07), MappedWrite::None);
+        assert_eq!(vrc7b.map_peek(0x1C00), chr(7));
+    }
+
+    #[test]
+    fn control() {
+        let mut vrc7: Vrc7 = load(85, 0, PRG_ROM_SIZE, CHR_ROM_SIZE);
+        assert_eq!(vrc7.map_peek(0x6000), MappedRead::None);
+        assert_eq!(vrc7.map_write(0xE000, 0x82), MappedWrite::None);
+        assert_eq!(vrc7.mirroring(), Mirroring::SingleScreenA);
+        assert_eq!(vrc7.map_peek(0x6000), MappedRead::PrgRam(0x0000));
+        assert_eq!(
+            vrc7.map_write(0x7FFF, 0x42),
+            MappedWrite::PrgRam(0x1FFF, 0x42)
+        );
+    }
+
+    #[test]
+    fn irq() {
+        let mut vrc7: Vrc7 = load(85, 2, PRG_ROM_SIZE, CHR_ROM_SIZE);
+        assert_eq!(vrc7.map_write(0xE010, 0xFE), MappedWrite::None);
+        assert_eq!(vrc7.map_write(0xF000, 0x06), MappedWrite::None);
+        vrc7.clock();
+        assert!(!vrc7.irq_pending());
+        vrc7.clock();
+        assert!(vrc7.irq_pending());
+        assert_eq!(vrc7.map_write(0xF010, 0x00), MappedWrite::None);
+        assert!(!vrc7.irq_pending());
+    }
+
+    #[test]
+    fn audio() {
+        let mut vrc7: Vrc7 = load(85, 2, PRG_ROM_SIZE, CHR_ROM_SIZE);
+        assert_eq!(clock_samples(&mut vrc7, 256), 0.0);
+
+        // Flute at ~440Hz on channel 2, full volume
+        assert_eq!(vrc7.map_write(0x9010, 0x12), MappedWrite::None);
+        assert_eq!(vrc7.map_write(0x9030, 0x20), MappedWrite::None);
+        assert_eq!(vrc7.map_write(0x9010, 0x32), MappedWrite::None);
+        assert_eq!(vrc7.map_write(0x9030, 0x40), MappedWrite::None);
+        assert_eq!(vrc7.map_write(0x9010, 0x22), MappedWrite::None);
+        assert_eq!(vrc7.map_write(0x9030, 0x19), MappedWrite::None);
+        assert!(clock_samples(&mut vrc7, 2048) > 0.01);
+
+        vrc7.toggle_channel(2);
+        assert!(!vrc7.channel_enabled(2));
+        assert_eq!(clock_samples(&mut vrc7, 256), 0.0);
+        vrc7.toggle_channel(2);
+        assert!(clock_samples(&mut vrc7, 256) > 0.01);
+
+        assert_eq!(
+            vrc7.map_write(0xE000, Vrc7::SILENCE_MASK),
+            MappedWrite::None
+        );
+        assert_eq!(clock_samples(&mut vrc7, 256), 0.0);
+        assert!(vrc7.channel_enabled(2));
+    }
+
+    #[test]
+    fn envelope_release() {
+        let mut vrc7: Vrc7 = load(85, 2, PRG_ROM_SIZE, CHR_ROM_SIZE);
+        // Sustained custom patch with instant attack and a fast release
+        for (reg, val) in [0x21, 0x21, 0x00, 0x00, 0xF0, 0xF0, 0x0F, 0x0F]
+            .into_iter()
+            .enumerate()
+        {
+            assert_eq!(vrc7.map_write(0x9010, reg as u8), MappedWrite::None);
+            assert_eq!(vrc7.map_write(0x9030, val), MappedWrite::None);
+        }
+        assert_eq!(vrc7.map_write(0x9010, 0x10), MappedWrite::None);
+        assert_eq!(vrc7.map_write(0x9030, 0x80), MappedWrite::None);
+        assert_eq!(vrc7.map_write(0x9010, 0x20), MappedWrite::None);
+        assert_eq!(vrc7.map_write(0x9030, 0x18), MappedWrite::None);
+        assert!(clock_samples(&mut vrc7, 512) > 0.1);
+        assert!(clock_samples(&mut vrc7, 2048) > 0.1);
+        assert_eq!(vrc7.map_write(0x9030, 0x08), MappedWrite::None);
+        clock_samples(&mut vrc7, 2048);
+        assert_eq!(clock_samples(&mut vrc7, 256), 0.0);
+    }
+}
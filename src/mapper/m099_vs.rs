@@ -0,0 +1,95 @@
+//! Vs. System (Mapper 099)
+//!
+//! <https://www.nesdev.org/wiki/INES_Mapper_099>
+//! <https://www.nesdev.org/wiki/Vs._System>
+
+use crate::{
+    cart::Cart,
+    common::{Clock, Regional, Reset},
+    mapper::{Mapped, MappedRead, MappedWrite, Mapper, MemMap},
+    mem::MemBanks,
+    ppu::Mirroring,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[must_use]
+pub struct Vs {
+    mirroring: Mirroring,
+    chr_banks: MemBanks,
+    prg_rom_banks: MemBanks,
+    // Only 40K PRG-ROM boards (Vs. Gumshoe) switch PRG-ROM
+    prg_rom_switch: bool,
+}
+
+impl Vs {
+    const PRG_RAM_SIZE: usize = 2 * 1024;
+    const CHR_RAM_SIZE: usize = 8 * 1024;
+    const PRG_ROM_WINDOW: usize = 8 * 1024;
+    const CHR_WINDOW: usize = 8 * 1024;
+
+    pub fn load(cart: &mut Cart) -> Mapper {
+        cart.add_prg_ram(Self::PRG_RAM_SIZE);
+        if !cart.has_chr() {
+            cart.add_chr_ram(Self::CHR_RAM_SIZE);
+        }
+        let vs = Self {
+            mirroring: cart.mirroring(),
+            chr_banks: MemBanks::new(0x0000, 0x1FFF, cart.chr_len(), Self::CHR_WINDOW),
+            prg_rom_banks: MemBanks::new(0x8000, 0xFFFF, cart.prg_rom.len(), Self::PRG_ROM_WINDOW),
+            prg_rom_switch: cart.prg_rom.len() > 0x8000,
+        };
+        vs.into()
+    }
+}
+
+impl MemMap for Vs {
+    // PPU $0000..=$1FFF 8K CHR-ROM Bank Switchable
+    // CPU $6000..=$7FFF 2K PRG-RAM, mirrored
+    // CPU $8000..=$9FFF 8K PRG-ROM Bank Switchable for 40K PRG-ROM, otherwise fixed
+    // CPU $A000..=$FFFF 24K PRG-ROM Fixed
+
+    fn map_peek(&self, addr: u16) -> MappedRead {
+        match addr {
+            0x0000..=0x1FFF => MappedRead::Chr(self.chr_banks.translate(addr)),
+            0x6000..=0x7FFF => MappedRead::PrgRam((addr & 0x07FF).into()),
+            0x8000..=0xFFFF => MappedRead::PrgRom(self.prg_rom_banks.translate(addr)),
+            _ => MappedRead::None,
+        }
+    }
+
+    fn map_write(&mut self, addr: u16, val: u8) -> MappedWrite {
+        match addr {
+            0x0000..=0x1FFF => MappedWrite::Chr(self.chr_banks.translate(addr), val),
+            0x6000..=0x7FFF => MappedWrite::PrgRam((addr & 0x07FF).into(), val),
+            _ => MappedWrite::None,
+        }
+    }
+}
+
+impl Mapped for Vs {
+    #[inline]
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    #[inline]
+    fn set_mirroring(&mut self, mirroring: Mirroring) {
+        self.mirroring = mirroring;
+    }
+
+    // $4016 | W | 2 | CHR-ROM bank, and PRG-ROM bank 0 or 4 at $8000 for 40K PRG-ROM
+    fn cpu_bus_write(&mut self, addr: u16, val: u8) {
+        if addr == 0x4016 {
+            let bank = usize::from((val >> 2) & 0x01);
+            self.chr_banks.set(0, bank);
+            if self.prg_rom_switch {
+                self.prg_rom_banks.set(0, bank << 2);
+            }
+        }
+    }
+}
+
+impl Clock for Vs {}
+impl Regional for Vs {}
+impl Reset for Vs {}
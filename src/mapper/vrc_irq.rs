@@ -23,6 +23,19 @@ impl VrcIrq {
         self.reload = val;
     }
 
+    // VRC4 splits the reload value across two registers
+    // [.... LLLL]: Low 4 bits
+    #[inline]
+    pub fn write_reload_low(&mut self, val: u8) {
+        self.reload = (self.reload & 0xF0) | (val & 0x0F);
+    }
+
+    // [.... HHHH]: High 4 bits
+    #[inline]
+    pub fn write_reload_high(&mut self, val: u8) {
+        self.reload = (self.reload & 0x0F) | ((val & 0x0F) << 4);
+    }
+
     pub fn write_control(&mut self, val: u8) {
         self.enabled_after_ack = val & 0x01 == 0x01;
         self.enabled = val & 0x02 == 0x02;
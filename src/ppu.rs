@@ -11,6 +11,7 @@ use serde::{Deserialize, Serialize};
 use sprite::Sprite;
 use status::PpuStatus;
 use std::cmp::Ordering;
+use variant::PpuVariant;
 
 pub mod bus;
 pub mod ctrl;
@@ -19,6 +20,7 @@ pub mod mask;
 pub mod scroll;
 pub mod sprite;
 pub mod status;
+pub mod variant;
 
 /// Nametable Mirroring Mode
 ///
@@ -54,6 +56,7 @@ pub trait PpuRegisters {
 #[must_use]
 pub struct Ppu {
     region: NesRegion,
+    variant: PpuVariant,
     cycle_count: usize,
     // Internal signal that clears status registers and prevents writes and cleared at the end of VBlank
     // https://www.nesdev.org/wiki/PPU_power_up_state
@@ -177,6 +180,7 @@ impl Ppu {
     pub fn new() -> Self {
         let mut ppu = Self {
             region: NesRegion::default(),
+            variant: PpuVariant::default(),
             cycle_count: 0,
             reset_signal: false,
             bus: PpuBus::new(),
@@ -236,6 +240,16 @@ impl Ppu {
         Self::SYSTEM_PALETTE[(pixel as usize) & (Self::SYSTEM_PALETTE.len() - 1)]
     }
 
+    #[inline]
+    pub const fn variant(&self) -> PpuVariant {
+        self.variant
+    }
+
+    #[inline]
+    pub fn set_variant(&mut self, variant: PpuVariant) {
+        self.variant = variant;
+    }
+
     #[inline]
     #[must_use]
     pub const fn cycle(&self) -> u32 {
@@ -906,8 +920,12 @@ impl PpuRegisters for Ppu {
     // Non-mutating version of `read_status`.
     #[inline]
     fn peek_status(&self) -> u8 {
-        // Only upper 3 bits are connected for this register
-        (self.status.read() & 0xE0) | (self.open_bus & 0x1F)
+        // Only upper 3 bits are connected for this register, except on PPUs that return an ID
+        let status = self.status.read() & 0xE0;
+        match self.variant.status_id() {
+            Some(id) => status | id,
+            None => status | (self.open_bus & 0x1F),
+        }
     }
 
     // $2003 | W   | OAMADDR
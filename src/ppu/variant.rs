@@ -0,0 +1,191 @@
+//! PPU chip variants.
+//!
+//! Besides the composite 2C02/2C07, arcade boards such as the Vs. System and PlayChoice-10 use
+//! RGB PPUs. These output a fixed RGB palette instead of a composite signal, and some of them
+//! scramble palette indexes or register addresses as a form of copy protection.
+//!
+//! <https://www.nesdev.org/wiki/PPU_variants>
+//! <https://www.nesdev.org/wiki/NES_2.0#Vs._System_Type>
+
+use crate::ppu::Ppu;
+use serde::{Deserialize, Serialize};
+
+/// PPU chip variant, as specified by the NES 2.0 Vs. PPU type.
+#[derive(Default, Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[allow(non_camel_case_types)]
+#[must_use]
+pub enum PpuVariant {
+    /// Composite PPU (2C02 NTSC, 2C07 PAL).
+    #[default]
+    Rp2c02,
+    Rp2c03b,
+    Rp2c03g,
+    Rp2c04_0001,
+    Rp2c04_0002,
+    Rp2c04_0003,
+    Rp2c04_0004,
+    Rc2c03b,
+    Rc2c03c,
+    Rc2c05_01,
+    Rc2c05_02,
+    Rc2c05_03,
+    Rc2c05_04,
+    Rc2c05_05,
+}
+
+impl PpuVariant {
+    // 2C03 palette in RGB333, written as octal digits.
+    #[rustfmt::skip]
+    const RGB_PALETTE: [u16; 64] = [
+        0o333, 0o014, 0o006, 0o326, 0o403, 0o503, 0o510, 0o420, 0o320, 0o120, 0o031, 0o040, 0o022, 0o000, 0o000, 0o000,
+        0o555, 0o036, 0o027, 0o407, 0o507, 0o704, 0o700, 0o630, 0o430, 0o140, 0o040, 0o053, 0o044, 0o000, 0o000, 0o000,
+        0o777, 0o357, 0o447, 0o637, 0o707, 0o737, 0o740, 0o750, 0o660, 0o360, 0o070, 0o276, 0o077, 0o000, 0o000, 0o000,
+        0o777, 0o567, 0o657, 0o757, 0o747, 0o755, 0o764, 0o772, 0o773, 0o572, 0o473, 0o276, 0o467, 0o000, 0o000, 0o000,
+    ];
+
+    // The 2C04 palette differs from the 2C03 in the otherwise black entries, which it uses to
+    // fill gaps left by its scrambled ordering.
+    #[rustfmt::skip]
+    const RP2C04_PALETTE: [u16; 64] = [
+        0o333, 0o014, 0o006, 0o326, 0o403, 0o503, 0o510, 0o420, 0o320, 0o120, 0o031, 0o040, 0o022, 0o111, 0o003, 0o020,
+        0o555, 0o036, 0o027, 0o407, 0o507, 0o704, 0o700, 0o630, 0o430, 0o140, 0o040, 0o053, 0o044, 0o222, 0o200, 0o310,
+        0o777, 0o357, 0o447, 0o637, 0o707, 0o737, 0o740, 0o750, 0o660, 0o360, 0o070, 0o276, 0o077, 0o444, 0o000, 0o000,
+        0o777, 0o567, 0o657, 0o757, 0o747, 0o755, 0o764, 0o770, 0o773, 0o572, 0o473, 0o276, 0o467, 0o666, 0o653, 0o760,
+    ];
+
+    // Maps the palette index written by a game to an index into `RP2C04_PALETTE`.
+    #[rustfmt::skip]
+    const RP2C04_0001: [u8; 64] = [
+        0x35, 0x23, 0x16, 0x22, 0x1C, 0x09, 0x1D, 0x15, 0x20, 0x00, 0x27, 0x05, 0x04, 0x28, 0x08, 0x20,
+        0x21, 0x3E, 0x1F, 0x29, 0x3C, 0x32, 0x36, 0x12, 0x3F, 0x2B, 0x2E, 0x1E, 0x3D, 0x2D, 0x24, 0x01,
+        0x0E, 0x31, 0x33, 0x2A, 0x2C, 0x0C, 0x1B, 0x14, 0x2E, 0x07, 0x34, 0x06, 0x13, 0x02, 0x26, 0x2E,
+        0x2E, 0x19, 0x10, 0x0A, 0x39, 0x03, 0x37, 0x17, 0x0F, 0x11, 0x0B, 0x0D, 0x38, 0x25, 0x18, 0x3A,
+    ];
+    #[rustfmt::skip]
+    const RP2C04_0002: [u8; 64] = [
+        0x2E, 0x27, 0x18, 0x39, 0x3A, 0x25, 0x1C, 0x31, 0x16, 0x13, 0x38, 0x34, 0x20, 0x23, 0x3C, 0x0B,
+        0x0F, 0x21, 0x06, 0x3D, 0x1B, 0x29, 0x1E, 0x22, 0x1D, 0x24, 0x0E, 0x2B, 0x32, 0x08, 0x2E, 0x03,
+        0x04, 0x36, 0x26, 0x33, 0x11, 0x1F, 0x10, 0x02, 0x14, 0x3F, 0x00, 0x09, 0x12, 0x2E, 0x28, 0x20,
+        0x3E, 0x0D, 0x2A, 0x17, 0x0C, 0x01, 0x15, 0x19, 0x2E, 0x2C, 0x07, 0x37, 0x35, 0x05, 0x0A, 0x2D,
+    ];
+    #[rustfmt::skip]
+    const RP2C04_0003: [u8; 64] = [
+        0x14, 0x25, 0x3A, 0x10, 0x0B, 0x20, 0x31, 0x09, 0x01, 0x2E, 0x36, 0x08, 0x15, 0x3D, 0x3E, 0x3C,
+        0x22, 0x1C, 0x05, 0x12, 0x19, 0x18, 0x17, 0x1B, 0x00, 0x03, 0x2E, 0x02, 0x16, 0x06, 0x34, 0x35,
+        0x23, 0x0F, 0x0E, 0x37, 0x0D, 0x27, 0x26, 0x20, 0x29, 0x04, 0x21, 0x24, 0x11, 0x2D, 0x2E, 0x1F,
+        0x2C, 0x1E, 0x39, 0x33, 0x07, 0x2A, 0x28, 0x1D, 0x0A, 0x2E, 0x32, 0x38, 0x13, 0x2B, 0x3F, 0x0C,
+    ];
+    #[rustfmt::skip]
+    const RP2C04_0004: [u8; 64] = [
+        0x18, 0x03, 0x1C, 0x28, 0x2E, 0x35, 0x01, 0x17, 0x10, 0x1F, 0x2A, 0x0E, 0x36, 0x37, 0x0B, 0x39,
+        0x25, 0x1E, 0x12, 0x34, 0x2E, 0x1D, 0x06, 0x26, 0x3E, 0x1B, 0x22, 0x19, 0x04, 0x2E, 0x3A, 0x21,
+        0x05, 0x0A, 0x07, 0x02, 0x13, 0x14, 0x00, 0x15, 0x0C, 0x3D, 0x11, 0x0F, 0x0D, 0x38, 0x2D, 0x24,
+        0x33, 0x20, 0x08, 0x16, 0x32, 0x27, 0x2C, 0x2E, 0x3F, 0x23, 0x31, 0x09, 0x3C, 0x29, 0x2B, 0x30,
+    ];
+
+    /// Variant for a NES 2.0 Vs. PPU type, stored in the lower nibble of header byte 13.
+    /// Reserved types fall back to the `Rp2c03b`.
+    pub const fn from_vs_ppu_type(ppu_type: u8) -> Self {
+        match ppu_type {
+            1 => Self::Rp2c03g,
+            2 => Self::Rp2c04_0001,
+            3 => Self::Rp2c04_0002,
+            4 => Self::Rp2c04_0003,
+            5 => Self::Rp2c04_0004,
+            6 => Self::Rc2c03b,
+            7 => Self::Rc2c03c,
+            8 => Self::Rc2c05_01,
+            9 => Self::Rc2c05_02,
+            10 => Self::Rc2c05_03,
+            11 => Self::Rc2c05_04,
+            12 => Self::Rc2c05_05,
+            _ => Self::Rp2c03b,
+        }
+    }
+
+    /// Whether this variant outputs RGB instead of a composite video signal.
+    #[inline]
+    #[must_use]
+    pub const fn is_rgb(self) -> bool {
+        !matches!(self, Self::Rp2c02)
+    }
+
+    /// Whether `PPUCTRL` and `PPUMASK` are swapped to $2001 and $2000 respectively.
+    #[inline]
+    #[must_use]
+    pub const fn swaps_ctrl_mask(self) -> bool {
+        matches!(
+            self,
+            Self::Rc2c05_01 | Self::Rc2c05_02 | Self::Rc2c05_03 | Self::Rc2c05_04 | Self::Rc2c05_05
+        )
+    }
+
+    /// Identifier returned in the lower 5 bits of `PPUSTATUS` in place of open bus, if any.
+    /// Games check it to refuse to run on the wrong PPU.
+    #[inline]
+    #[must_use]
+    pub const fn status_id(self) -> Option<u8> {
+        match self {
+            Self::Rc2c05_01 | Self::Rc2c05_04 => Some(0x1B),
+            Self::Rc2c05_02 => Some(0x1D),
+            Self::Rc2c05_03 => Some(0x1C),
+            _ => None,
+        }
+    }
+
+    /// RGB color of a pixel, including emphasis bits. RGB PPUs emphasize a color channel by
+    /// driving it to full intensity, while composite PPUs ignore emphasis, matching
+    /// `Ppu::system_palette`.
+    #[must_use]
+    pub fn rgb(self, pixel: u16) -> (u8, u8, u8) {
+        let index = (pixel & 0x3F) as usize;
+        let color = match self {
+            Self::Rp2c02 => return Ppu::system_palette(pixel),
+            Self::Rp2c04_0001 => Self::RP2C04_PALETTE[Self::RP2C04_0001[index] as usize],
+            Self::Rp2c04_0002 => Self::RP2C04_PALETTE[Self::RP2C04_0002[index] as usize],
+            Self::Rp2c04_0003 => Self::RP2C04_PALETTE[Self::RP2C04_0003[index] as usize],
+            Self::Rp2c04_0004 => Self::RP2C04_PALETTE[Self::RP2C04_0004[index] as usize],
+            _ => Self::RGB_PALETTE[index],
+        };
+        let channel = |value: u16, emphasis: u16| -> u8 {
+            if pixel & emphasis == emphasis {
+                0xFF
+            } else {
+                ((value & 0x07) * 255 / 7) as u8
+            }
+        };
+        (
+            channel(color >> 6, 0x40),
+            channel(color >> 3, 0x80),
+            channel(color, 0x100),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rgb_palettes() {
+        let variant = PpuVariant::Rp2c03b;
+        assert_eq!(variant.rgb(0x20), (0xFF, 0xFF, 0xFF));
+        assert_eq!(variant.rgb(0x16), (0xFF, 0x00, 0x00));
+        // Green emphasis
+        assert_eq!(variant.rgb(0x16 | 0x80), (0xFF, 0xFF, 0x00));
+        // 2C04-0001 index $1A is black
+        assert_eq!(PpuVariant::Rp2c04_0001.rgb(0x1A), (0x00, 0x00, 0x00));
+        assert_eq!(PpuVariant::Rp2c04_0001.rgb(0x00), variant.rgb(0x35));
+        assert_eq!(PpuVariant::Rp2c02.rgb(0x16), Ppu::system_palette(0x16));
+    }
+
+    #[test]
+    fn vs_ppu_type() {
+        assert_eq!(PpuVariant::from_vs_ppu_type(0), PpuVariant::Rp2c03b);
+        assert_eq!(PpuVariant::from_vs_ppu_type(5), PpuVariant::Rp2c04_0004);
+        let variant = PpuVariant::from_vs_ppu_type(9);
+        assert!(variant.swaps_ctrl_mask());
+        assert_eq!(variant.status_id(), Some(0x1D));
+        assert!(!PpuVariant::Rp2c03b.swaps_ctrl_mask());
+    }
+}
@@ -0,0 +1,529 @@
+//! Lossless gameplay recording of video frames and audio samples.
+//!
+//! Frames are RGBA buffers as returned by `ControlDeck::frame_buffer` and audio is mono `f32`
+//! samples as returned by `ControlDeck::audio_samples`. Streams are timed using the exact
+//! rational frame rate of the region so external encoders keep audio and video in sync.
+//!
+//! <https://wiki.multimedia.cx/index.php/YUV4MPEG2>
+//! <http://soundfile.sapp.org/doc/WaveFormat/>
+//! <https://learn.microsoft.com/en-us/windows/win32/directshow/avi-riff-file-reference>
+
+use crate::{common::NesRegion, NesResult};
+use anyhow::{anyhow, Context};
+use std::io::{Seek, SeekFrom, Write};
+
+/// Exact frame rate of a region as a `(numerator, denominator)` pair, e.g. `60.0988` for NTSC.
+///
+/// NTSC renders 89341.5 PPU dots per frame at 236.25 MHz / 44, while PAL and Dendy render 106392
+/// dots at 26.601712 MHz / 5.
+#[must_use]
+pub const fn frame_rate(region: NesRegion) -> (u32, u32) {
+    match region {
+        NesRegion::Ntsc => (39_375_000, 655_171),
+        NesRegion::Pal | NesRegion::Dendy => (3_325_214, 66_495),
+    }
+}
+
+/// A destination for recorded frames and their matching audio.
+pub trait Recorder {
+    /// Record a single RGBA frame along with the audio samples generated during it.
+    ///
+    /// # Errors
+    ///
+    /// If the frame size doesn't match the recording or writing fails, then an error is returned.
+    fn record_frame(&mut self, frame: &[u8], samples: &[f32]) -> NesResult<()>;
+
+    /// Finalize the recording, updating any headers that depend on its length.
+    ///
+    /// # Errors
+    ///
+    /// If writing fails, then an error is returned.
+    fn finish(&mut self) -> NesResult<()>;
+}
+
+/// Recording parameters shared by all formats.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[must_use]
+pub struct RecordConfig {
+    pub width: u32,
+    pub height: u32,
+    pub region: NesRegion,
+    pub sample_rate: u32,
+}
+
+impl RecordConfig {
+    const fn frame_len(&self) -> usize {
+        4 * (self.width * self.height) as usize
+    }
+
+    fn check_frame(&self, frame: &[u8]) -> NesResult<()> {
+        if frame.len() == self.frame_len() {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "frame size {} does not match recording dimensions {}x{}",
+                frame.len(),
+                self.width,
+                self.height
+            ))
+        }
+    }
+}
+
+#[inline]
+fn pcm_sample(sample: f32) -> i16 {
+    (sample.clamp(-1.0, 1.0) * f32::from(i16::MAX)) as i16
+}
+
+#[inline]
+fn u32_len(len: u64) -> NesResult<u32> {
+    u32::try_from(len).map_err(|_| anyhow!("recording exceeds the maximum file size"))
+}
+
+/// Records a `YUV4MPEG2` video stream with full range 4:4:4 chroma, and a 16-bit PCM WAV audio
+/// stream.
+#[derive(Debug)]
+#[must_use]
+pub struct Y4mWavRecorder<V: Write, A: Write + Seek> {
+    config: RecordConfig,
+    video: V,
+    audio: A,
+    planes: Vec<u8>,
+    audio_len: u64,
+    finished: bool,
+}
+
+impl<V: Write, A: Write + Seek> Y4mWavRecorder<V, A> {
+    const WAV_HEADER_LEN: u64 = 44;
+
+    /// Start a recording, writing the stream headers.
+    ///
+    /// # Errors
+    ///
+    /// If writing the headers fails, then an error is returned.
+    pub fn new(config: RecordConfig, mut video: V, mut audio: A) -> NesResult<Self> {
+        let (num, den) = frame_rate(config.region);
+        writeln!(
+            video,
+            "YUV4MPEG2 W{} H{} F{num}:{den} Ip A1:1 C444 XCOLORRANGE=FULL",
+            config.width, config.height,
+        )
+        .context("failed to write y4m header")?;
+        write_wav_header(&mut audio, config.sample_rate, 0)
+            .context("failed to write wav header")?;
+        Ok(Self {
+            config,
+            video,
+            audio,
+            planes: vec![0x00; 3 * (config.width * config.height) as usize],
+            audio_len: 0,
+            finished: false,
+        })
+    }
+}
+
+impl<V: Write, A: Write + Seek> Recorder for Y4mWavRecorder<V, A> {
+    fn record_frame(&mut self, frame: &[u8], samples: &[f32]) -> NesResult<()> {
+        self.config.check_frame(frame)?;
+        let size = (self.config.width * self.config.height) as usize;
+        let (y_plane, chroma) = self.planes.split_at_mut(size);
+        let (u_plane, v_plane) = chroma.split_at_mut(size);
+        for (i, pixel) in frame.chunks_exact(4).enumerate() {
+            let (red, green, blue) = (
+                f32::from(pixel[0]),
+                f32::from(pixel[1]),
+                f32::from(pixel[2]),
+            );
+            // Full range BT.601
+            let y = 0.299 * red + 0.587 * green + 0.114 * blue;
+            y_plane[i] = y.round().clamp(0.0, 255.0) as u8;
+            u_plane[i] = (128.0 + 0.564 * (blue - y)).round().clamp(0.0, 255.0) as u8;
+            v_plane[i] = (128.0 + 0.713 * (red - y)).round().clamp(0.0, 255.0) as u8;
+        }
+        self.video.write_all(b"FRAME\n")?;
+        self.video.write_all(&self.planes)?;
+
+        for sample in samples {
+            self.audio.write_all(&pcm_sample(*sample).to_le_bytes())?;
+        }
+        self.audio_len += 2 * samples.len() as u64;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> NesResult<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+        self.video.flush()?;
+        let data_len = u32_len(self.audio_len)?;
+        self.audio.seek(SeekFrom::Start(0))?;
+        write_wav_header(&mut self.audio, self.config.sample_rate, data_len)
+            .context("failed to update wav header")?;
+        self.audio
+            .seek(SeekFrom::Start(Self::WAV_HEADER_LEN + self.audio_len))?;
+        self.audio.flush()?;
+        Ok(())
+    }
+}
+
+fn write_wav_header<W: Write>(writer: &mut W, sample_rate: u32, data_len: u32) -> NesResult<()> {
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&(36 + data_len).to_le_bytes())?;
+    writer.write_all(b"WAVE")?;
+    writer.write_all(b"fmt ")?;
+    writer.write_all(&16u32.to_le_bytes())?;
+    write_wave_format(writer, sample_rate)?;
+    writer.write_all(b"data")?;
+    writer.write_all(&data_len.to_le_bytes())?;
+    Ok(())
+}
+
+// Mono 16-bit PCM WAVEFORMAT.
+fn write_wave_format<W: Write>(writer: &mut W, sample_rate: u32) -> NesResult<()> {
+    writer.write_all(&1u16.to_le_bytes())?; // PCM
+    writer.write_all(&1u16.to_le_bytes())?; // Channels
+    writer.write_all(&sample_rate.to_le_bytes())?;
+    writer.write_all(&(2 * sample_rate).to_le_bytes())?; // Bytes per second
+    writer.write_all(&2u16.to_le_bytes())?; // Block align
+    writer.write_all(&16u16.to_le_bytes())?; // Bits per sample
+    Ok(())
+}
+
+/// Records an uncompressed AVI with a 24-bit RGB video stream and a 16-bit PCM audio stream.
+///
+/// Only the original AVI 1.0 format is written, limiting recordings to 4GB.
+#[derive(Debug)]
+#[must_use]
+pub struct AviRecorder<W: Write + Seek> {
+    config: RecordConfig,
+    writer: W,
+    frame: Vec<u8>,
+    // Chunk id, flags, offset from the `movi` list and size
+    index: Vec<([u8; 4], u32, u32, u32)>,
+    start: u64,
+    movi: u64,
+    position: u64,
+    frames: u32,
+    samples: u32,
+    finished: bool,
+}
+
+impl<W: Write + Seek> AviRecorder<W> {
+    const AVIF_HASINDEX: u32 = 0x10;
+    const AVIIF_KEYFRAME: u32 = 0x10;
+    const VIDEO_CHUNK: [u8; 4] = *b"00db";
+    const AUDIO_CHUNK: [u8; 4] = *b"01wb";
+
+    /// Start a recording, writing the AVI headers.
+    ///
+    /// # Errors
+    ///
+    /// If writing the headers fails, then an error is returned.
+    pub fn new(config: RecordConfig, mut writer: W) -> NesResult<Self> {
+        let stride = Self::stride(config.width);
+        let start = writer.stream_position()?;
+        Self::write_headers(&mut writer, &config, 0, 4, 0, 0)
+            .context("failed to write avi header")?;
+        let position = writer.stream_position()?;
+        Ok(Self {
+            config,
+            writer,
+            frame: vec![0x00; stride * config.height as usize],
+            index: vec![],
+            start,
+            movi: position - 4,
+            position,
+            frames: 0,
+            samples: 0,
+            finished: false,
+        })
+    }
+
+    // Rows are padded to 4 bytes.
+    const fn stride(width: u32) -> usize {
+        (3 * width as usize + 3) & !3
+    }
+
+    fn write_headers(
+        writer: &mut W,
+        config: &RecordConfig,
+        riff_len: u32,
+        movi_len: u32,
+        frames: u32,
+        samples: u32,
+    ) -> NesResult<()> {
+        let (rate, scale) = frame_rate(config.region);
+        let frame_len = u32_len((Self::stride(config.width) * config.height as usize) as u64)?;
+        let micros_per_frame = (1_000_000 * u64::from(scale) / u64::from(rate)) as u32;
+
+        let le = |v: u32| v.to_le_bytes();
+        let le16 = |v: u16| v.to_le_bytes();
+
+        writer.write_all(b"RIFF")?;
+        writer.write_all(&le(riff_len))?;
+        writer.write_all(b"AVI ")?;
+
+        // hdrl LIST: avih (56 + 8) + video strl (116 + 8) + audio strl (94 + 8)
+        writer.write_all(b"LIST")?;
+        writer.write_all(&le(4 + 64 + 124 + 102))?;
+        writer.write_all(b"hdrl")?;
+
+        writer.write_all(b"avih")?;
+        writer.write_all(&le(56))?;
+        writer.write_all(&le(micros_per_frame))?;
+        writer.write_all(&le(0))?; // Max bytes per second
+        writer.write_all(&le(0))?; // Padding granularity
+        writer.write_all(&le(Self::AVIF_HASINDEX))?;
+        writer.write_all(&le(frames))?;
+        writer.write_all(&le(0))?; // Initial frames
+        writer.write_all(&le(2))?; // Streams
+        writer.write_all(&le(frame_len))?; // Suggested buffer size
+        writer.write_all(&le(config.width))?;
+        writer.write_all(&le(config.height))?;
+        writer.write_all(&[0x00; 16])?; // Reserved
+
+        // Video stream
+        writer.write_all(b"LIST")?;
+        writer.write_all(&le(116))?;
+        writer.write_all(b"strl")?;
+        writer.write_all(b"strh")?;
+        writer.write_all(&le(56))?;
+        writer.write_all(b"vids")?;
+        writer.write_all(b"DIB ")?;
+        writer.write_all(&le(0))?; // Flags
+        writer.write_all(&le16(0))?; // Priority
+        writer.write_all(&le16(0))?; // Language
+        writer.write_all(&le(0))?; // Initial frames
+        writer.write_all(&le(scale))?;
+        writer.write_all(&le(rate))?;
+        writer.write_all(&le(0))?; // Start
+        writer.write_all(&le(frames))?;
+        writer.write_all(&le(frame_len))?; // Suggested buffer size
+        writer.write_all(&le(u32::MAX))?; // Quality
+        writer.write_all(&le(0))?; // Sample size
+        writer.write_all(&le16(0))?; // Frame rect
+        writer.write_all(&le16(0))?;
+        writer.write_all(&le16(config.width as u16))?;
+        writer.write_all(&le16(config.height as u16))?;
+        writer.write_all(b"strf")?;
+        writer.write_all(&le(40))?;
+        writer.write_all(&le(40))?; // BITMAPINFOHEADER size
+        writer.write_all(&le(config.width))?;
+        writer.write_all(&le(config.height))?; // Positive height is bottom-up
+        writer.write_all(&le16(1))?; // Planes
+        writer.write_all(&le16(24))?; // Bits per pixel
+        writer.write_all(&le(0))?; // BI_RGB
+        writer.write_all(&le(frame_len))?;
+        writer.write_all(&[0x00; 16])?; // Resolution and palette
+
+        // Audio stream
+        writer.write_all(b"LIST")?;
+        writer.write_all(&le(94))?;
+        writer.write_all(b"strl")?;
+        writer.write_all(b"strh")?;
+        writer.write_all(&le(56))?;
+        writer.write_all(b"auds")?;
+        writer.write_all(&le(0))?; // Handler
+        writer.write_all(&le(0))?; // Flags
+        writer.write_all(&le16(0))?; // Priority
+        writer.write_all(&le16(0))?; // Language
+        writer.write_all(&le(0))?; // Initial frames
+        writer.write_all(&le(1))?; // Scale
+        writer.write_all(&le(config.sample_rate))?;
+        writer.write_all(&le(0))?; // Start
+        writer.write_all(&le(samples))?;
+        writer.write_all(&le(config.sample_rate))?; // Suggested buffer size
+        writer.write_all(&le(u32::MAX))?; // Quality
+        writer.write_all(&le(2))?; // Sample size
+        writer.write_all(&[0x00; 8])?; // Frame rect
+        writer.write_all(b"strf")?;
+        writer.write_all(&le(18))?;
+        write_wave_format(writer, config.sample_rate)?;
+        writer.write_all(&le16(0))?; // WAVEFORMATEX extra size
+
+        writer.write_all(b"LIST")?;
+        writer.write_all(&le(movi_len))?;
+        writer.write_all(b"movi")?;
+        Ok(())
+    }
+
+    fn write_chunk(&mut self, id: [u8; 4], flags: u32, data: &[u8]) -> NesResult<()> {
+        let len = u32_len(data.len() as u64)?;
+        let offset = u32_len(self.position - self.movi)?;
+        self.writer.write_all(&id)?;
+        self.writer.write_all(&len.to_le_bytes())?;
+        self.writer.write_all(data)?;
+        let mut written = 8 + u64::from(len);
+        if len % 2 == 1 {
+            self.writer.write_all(&[0x00])?;
+            written += 1;
+        }
+        self.index.push((id, flags, offset, len));
+        self.position += written;
+        u32_len(self.position - self.start)?;
+        Ok(())
+    }
+}
+
+impl<W: Write + Seek> Recorder for AviRecorder<W> {
+    fn record_frame(&mut self, frame: &[u8], samples: &[f32]) -> NesResult<()> {
+        self.config.check_frame(frame)?;
+        let width = self.config.width as usize;
+        let stride = Self::stride(self.config.width);
+        let mut data = std::mem::take(&mut self.frame);
+        // BGR, bottom-up
+        for (row, line) in frame.chunks_exact(4 * width).rev().enumerate() {
+            let out = &mut data[row * stride..][..3 * width];
+            for (bgr, rgba) in out.chunks_exact_mut(3).zip(line.chunks_exact(4)) {
+                bgr[0] = rgba[2];
+                bgr[1] = rgba[1];
+                bgr[2] = rgba[0];
+            }
+        }
+        let result = self.write_chunk(Self::VIDEO_CHUNK, Self::AVIIF_KEYFRAME, &data);
+        self.frame = data;
+        result?;
+        self.frames += 1;
+
+        if !samples.is_empty() {
+            let audio = samples
+                .iter()
+                .flat_map(|sample| pcm_sample(*sample).to_le_bytes())
+                .collect::<Vec<_>>();
+            self.write_chunk(Self::AUDIO_CHUNK, Self::AVIIF_KEYFRAME, &audio)?;
+            self.samples += samples.len() as u32;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> NesResult<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+
+        let mut idx = Vec::with_capacity(16 * self.index.len());
+        for (id, flags, offset, len) in &self.index {
+            idx.extend_from_slice(id);
+            idx.extend_from_slice(&flags.to_le_bytes());
+            idx.extend_from_slice(&offset.to_le_bytes());
+            idx.extend_from_slice(&len.to_le_bytes());
+        }
+        self.writer.write_all(b"idx1")?;
+        self.writer
+            .write_all(&u32_len(idx.len() as u64)?.to_le_bytes())?;
+        self.writer.write_all(&idx)?;
+        let end = self.writer.stream_position()?;
+
+        // List and chunk lengths exclude their own id and length fields
+        let riff_len = u32_len(end - self.start - 8)?;
+        let movi_len = u32_len(self.position - self.movi)?;
+        self.writer.seek(SeekFrom::Start(self.start))?;
+        Self::write_headers(
+            &mut self.writer,
+            &self.config,
+            riff_len,
+            movi_len,
+            self.frames,
+            self.samples,
+        )
+        .context("failed to update avi header")?;
+        self.writer.seek(SeekFrom::Start(end))?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const CONFIG: RecordConfig = RecordConfig {
+        width: 2,
+        height: 2,
+        region: NesRegion::Ntsc,
+        sample_rate: 48_000,
+    };
+
+    fn u32_at(data: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes([
+            data[offset],
+            data[offset + 1],
+            data[offset + 2],
+            data[offset + 3],
+        ])
+    }
+
+    #[test]
+    fn frame_rates() {
+        let (num, den) = frame_rate(NesRegion::Ntsc);
+        assert!((f64::from(num) / f64::from(den) - 60.0988).abs() < 0.0001);
+        let (num, den) = frame_rate(NesRegion::Pal);
+        assert!((f64::from(num) / f64::from(den) - 50.007).abs() < 0.0001);
+    }
+
+    #[test]
+    fn y4m_wav() {
+        let mut video = Vec::new();
+        let mut audio = Cursor::new(Vec::new());
+        let mut recorder =
+            Y4mWavRecorder::new(CONFIG, &mut video, &mut audio).expect("valid recorder");
+        let frame = [0xFF; 16];
+        recorder
+            .record_frame(&frame, &[0.0, 0.5, -0.5])
+            .expect("recorded frame");
+        assert!(recorder.record_frame(&[0xFF; 4], &[]).is_err());
+        recorder.finish().expect("finished recording");
+        drop(recorder);
+
+        let header = b"YUV4MPEG2 W2 H2 F39375000:655171 Ip A1:1 C444 XCOLORRANGE=FULL\nFRAME\n";
+        assert_eq!(&video[..header.len()], header);
+        assert_eq!(video.len(), header.len() + 12);
+        assert_eq!(&video[header.len()..header.len() + 4], &[0xFF; 4]);
+
+        let audio = audio.into_inner();
+        assert_eq!(audio.len(), 44 + 6);
+        assert_eq!(u32_at(&audio, 4), 36 + 6);
+        assert_eq!(u32_at(&audio, 24), 48_000);
+        assert_eq!(u32_at(&audio, 40), 6);
+    }
+
+    #[test]
+    fn avi() {
+        let mut avi = Cursor::new(Vec::new());
+        let mut recorder = AviRecorder::new(CONFIG, &mut avi).expect("valid recorder");
+        let mut frame = [0x00; 16];
+        // Top-left pixel is red
+        frame[0] = 0xFF;
+        frame[3] = 0xFF;
+        recorder
+            .record_frame(&frame, &[0.25; 4])
+            .expect("recorded frame");
+        recorder.finish().expect("finished recording");
+        drop(recorder);
+
+        let avi = avi.into_inner();
+        assert_eq!(&avi[0..4], b"RIFF");
+        assert_eq!(u32_at(&avi, 4) as usize, avi.len() - 8);
+        assert_eq!(&avi[8..12], b"AVI ");
+        // avih total frames
+        assert_eq!(u32_at(&avi, 48), 1);
+        // Video stream rate and scale
+        let strh = 12 + 8 + 4 + 64 + 12;
+        assert_eq!(&avi[strh..strh + 4], b"strh");
+        assert_eq!(u32_at(&avi, strh + 28), 655_171);
+        assert_eq!(u32_at(&avi, strh + 32), 39_375_000);
+
+        let movi = 12 + 8 + 4 + 64 + 124 + 102;
+        assert_eq!(&avi[movi..movi + 4], b"LIST");
+        assert_eq!(&avi[movi + 8..movi + 12], b"movi");
+        let chunk = movi + 12;
+        assert_eq!(&avi[chunk..chunk + 4], b"00db");
+        assert_eq!(u32_at(&avi, chunk + 4), 16);
+        // Bottom-up BGR with 4 byte aligned rows, so the top-left pixel starts the second row
+        assert_eq!(&avi[chunk + 8 + 8..chunk + 8 + 11], &[0x00, 0x00, 0xFF]);
+        assert!(avi.windows(4).any(|w| w == b"idx1"));
+    }
+}
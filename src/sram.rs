@@ -0,0 +1,197 @@
+//! Battery-backed save persistence.
+//!
+//! Saves are stored as raw `.sav` files named after the SHA-1 of the ROM, so they follow the
+//! game across renames. A save holds battery-backed PRG-RAM followed by any battery-backed memory
+//! held by the mapper, such as a serial EEPROM. Files are written to a temporary file first and
+//! renamed over the previous save so a crash mid-write can't corrupt it.
+
+use crate::{cart::hash::RomHashes, NesResult};
+use anyhow::Context;
+use std::{
+    fs::{self, File},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// Loads and periodically flushes battery-backed saves in a directory. Only a single owner should
+/// flush a given save, so this is intentionally not `Clone`.
+#[derive(Debug, PartialEq, Eq)]
+#[must_use]
+pub struct SramManager {
+    dir: PathBuf,
+    path: Option<PathBuf>,
+    saved: Vec<u8>,
+    flush_interval: Duration,
+    elapsed: Duration,
+}
+
+impl SramManager {
+    pub const EXTENSION: &'static str = "sav";
+    pub const DEFAULT_FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+    /// Create a manager storing saves in `dir`, which is created on first write.
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        Self {
+            dir: dir.into(),
+            path: None,
+            saved: vec![],
+            flush_interval: Self::DEFAULT_FLUSH_INTERVAL,
+            elapsed: Duration::ZERO,
+        }
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Save file of the loaded ROM, if any.
+    #[inline]
+    #[must_use]
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Save file for a ROM with `hashes`.
+    #[must_use]
+    pub fn save_path(&self, hashes: &RomHashes) -> PathBuf {
+        self.dir
+            .join(hashes.sha1_hex())
+            .with_extension(Self::EXTENSION)
+    }
+
+    #[inline]
+    #[must_use]
+    pub const fn flush_interval(&self) -> Duration {
+        self.flush_interval
+    }
+
+    /// Set how much emulated time passes between flushes.
+    #[inline]
+    pub fn set_flush_interval(&mut self, interval: Duration) {
+        self.flush_interval = interval;
+    }
+
+    /// Read the save file for a ROM with `hashes` without switching to it.
+    ///
+    /// # Errors
+    ///
+    /// If the save file exists but can't be read, then an error is returned.
+    pub fn read(&self, hashes: &RomHashes) -> NesResult<Option<Vec<u8>>> {
+        let path = self.save_path(hashes);
+        match fs::read(&path) {
+            Ok(data) => {
+                log::info!("loaded save {path:?}");
+                Ok(Some(data))
+            }
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err).with_context(|| format!("failed to read save {path:?}")),
+        }
+    }
+
+    /// Switch to the save file for a ROM with `hashes`, whose contents are already `saved`.
+    pub fn open(&mut self, hashes: &RomHashes, saved: &[u8]) {
+        self.path = Some(self.save_path(hashes));
+        self.mark_saved(saved);
+        self.elapsed = Duration::ZERO;
+    }
+
+    /// Switch to the save file for a ROM with `hashes`, returning its contents if it exists.
+    ///
+    /// # Errors
+    ///
+    /// If the save file exists but can't be read, then an error is returned.
+    pub fn load(&mut self, hashes: &RomHashes) -> NesResult<Option<Vec<u8>>> {
+        let data = self.read(hashes)?;
+        self.open(hashes, data.as_deref().unwrap_or_default());
+        Ok(data)
+    }
+
+    /// Record `data` as already persisted, so it's only written once it changes.
+    pub fn mark_saved(&mut self, data: &[u8]) {
+        data.clone_into(&mut self.saved);
+    }
+
+    /// Stop tracking the current save file without writing it.
+    pub fn close(&mut self) {
+        self.path = None;
+        self.saved.clear();
+    }
+
+    /// Advance the flush timer by `elapsed`, returning whether a flush is due.
+    pub fn tick(&mut self, elapsed: Duration) -> bool {
+        self.elapsed += elapsed;
+        if self.elapsed >= self.flush_interval {
+            self.elapsed = Duration::ZERO;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Write `data` to the current save file if it changed since it was last loaded or written,
+    /// returning whether it was written.
+    ///
+    /// # Errors
+    ///
+    /// If the save directory can't be created or the file can't be written, then an error is
+    /// returned.
+    pub fn flush(&mut self, data: &[u8]) -> NesResult<bool> {
+        let Some(path) = &self.path else {
+            return Ok(false);
+        };
+        if data == self.saved {
+            return Ok(false);
+        }
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("failed to create save directory {:?}", self.dir))?;
+        Self::write_atomic(path, data).with_context(|| format!("failed to write save {path:?}"))?;
+        log::debug!("flushed save {path:?}");
+        self.mark_saved(data);
+        Ok(true)
+    }
+
+    fn write_atomic(path: &Path, data: &[u8]) -> NesResult<()> {
+        let tmp_path = path.with_extension("sav.tmp");
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_and_flush() {
+        let dir = std::env::temp_dir().join(format!("tetanes_sram_{}", std::process::id()));
+        let hashes = RomHashes::new(b"rom");
+        let mut sram = SramManager::new(&dir);
+        assert!(!sram.flush(&[0x01]).expect("no save selected"));
+
+        assert_eq!(sram.load(&hashes).expect("no save"), None);
+        assert_eq!(sram.path(), Some(sram.save_path(&hashes).as_path()));
+        sram.mark_saved(&[0x00; 4]);
+        assert!(!sram.flush(&[0x00; 4]).expect("unchanged"));
+        assert!(sram.flush(&[0x00, 0x01, 0x02, 0x03]).expect("written"));
+        assert!(!sram.flush(&[0x00, 0x01, 0x02, 0x03]).expect("unchanged"));
+        assert!(!sram.save_path(&hashes).with_extension("sav.tmp").exists());
+
+        let mut sram = SramManager::new(&dir);
+        assert_eq!(
+            sram.load(&hashes).expect("saved").as_deref(),
+            Some([0x00, 0x01, 0x02, 0x03].as_slice())
+        );
+        sram.set_flush_interval(Duration::from_secs(1));
+        assert!(!sram.tick(Duration::from_millis(600)));
+        assert!(sram.tick(Duration::from_millis(600)));
+        assert!(!sram.tick(Duration::from_millis(600)));
+
+        fs::remove_dir_all(&dir).expect("removed test dir");
+    }
+}
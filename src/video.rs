@@ -1,7 +1,20 @@
-use crate::ppu::Ppu;
-use once_cell::sync::Lazy;
+use crate::{
+    common::{NesRegion, Regional},
+    ppu::{variant::PpuVariant, Ppu},
+};
+use crt::{Crt, CrtConfig};
+use ntsc::{Ntsc, NtscConfig};
+use overscan::{Crop, Overscan};
+use pal::Pal;
 use serde::{Deserialize, Serialize};
-use std::f64::consts::PI;
+
+pub mod crt;
+pub mod gif;
+pub mod ntsc;
+pub mod overscan;
+pub mod pal;
+pub mod scale;
+pub mod screenshot;
 
 #[derive(Default, Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
 #[must_use]
@@ -9,11 +22,38 @@ pub enum VideoFilter {
     Pixellate,
     #[default]
     Ntsc,
+    Scale2x,
+    Scale3x,
+    Xbrz2x,
+    Xbrz3x,
+    Xbrz4x,
+    Pal,
 }
 
 impl VideoFilter {
     pub const fn as_slice() -> &'static [Self] {
-        &[Self::Pixellate, Self::Ntsc]
+        &[
+            Self::Pixellate,
+            Self::Ntsc,
+            Self::Scale2x,
+            Self::Scale3x,
+            Self::Xbrz2x,
+            Self::Xbrz3x,
+            Self::Xbrz4x,
+            Self::Pal,
+        ]
+    }
+
+    /// Width and height in pixels of frames produced by this filter.
+    #[must_use]
+    pub const fn dimensions(self) -> (u32, u32) {
+        match self {
+            Self::Pixellate | Self::Pal => (Ppu::WIDTH, Ppu::HEIGHT),
+            Self::Ntsc => (Ntsc::WIDTH, Ppu::HEIGHT),
+            Self::Scale2x | Self::Xbrz2x => (2 * Ppu::WIDTH, 2 * Ppu::HEIGHT),
+            Self::Scale3x | Self::Xbrz3x => (3 * Ppu::WIDTH, 3 * Ppu::HEIGHT),
+            Self::Xbrz4x => (4 * Ppu::WIDTH, 4 * Ppu::HEIGHT),
+        }
     }
 }
 
@@ -22,17 +62,23 @@ impl AsRef<str> for VideoFilter {
         match self {
             Self::Pixellate => "Pixellate",
             Self::Ntsc => "NTSC",
+            Self::Scale2x => "Scale2x",
+            Self::Scale3x => "Scale3x",
+            Self::Xbrz2x => "xBRZ 2x",
+            Self::Xbrz3x => "xBRZ 3x",
+            Self::Xbrz4x => "xBRZ 4x",
+            Self::Pal => "PAL",
         }
     }
 }
 
+// Indices follow `as_slice`, which only appends new filters so existing indices stay stable.
 impl From<usize> for VideoFilter {
     fn from(value: usize) -> Self {
-        if value == 1 {
-            Self::Ntsc
-        } else {
-            Self::Pixellate
-        }
+        Self::as_slice()
+            .get(value)
+            .copied()
+            .unwrap_or(Self::Pixellate)
     }
 }
 
@@ -40,7 +86,19 @@ impl From<usize> for VideoFilter {
 #[must_use]
 pub struct Video {
     filter: VideoFilter,
+    ntsc: Ntsc,
+    pal: Pal,
+    pixels: Vec<u32>,
+    width: u32,
+    height: u32,
     output: Vec<u8>,
+    region: NesRegion,
+    ppu_variant: PpuVariant,
+    overscan: Option<Overscan>,
+    pixel_aspect: bool,
+    cropped: Vec<u8>,
+    crt: Option<Crt>,
+    crt_output: Vec<u8>,
 }
 
 impl Default for Video {
@@ -51,15 +109,24 @@ impl Default for Video {
 
 impl Video {
     pub fn new() -> Self {
-        let mut output = vec![0x00; 4 * Ppu::SIZE];
-        // Force alpha to 255.
-        for p in output.iter_mut().skip(3).step_by(4) {
-            *p = 255;
-        }
-        Self {
+        let mut video = Self {
             filter: VideoFilter::default(),
-            output,
-        }
+            ntsc: Ntsc::default(),
+            pal: Pal::default(),
+            pixels: vec![0x00; Ppu::SIZE],
+            width: 0,
+            height: 0,
+            output: vec![],
+            region: NesRegion::default(),
+            ppu_variant: PpuVariant::default(),
+            overscan: None,
+            pixel_aspect: false,
+            cropped: vec![],
+            crt: None,
+            crt_output: vec![],
+        };
+        video.resize_output(Ppu::WIDTH, Ppu::HEIGHT);
+        video
     }
 
     #[inline]
@@ -70,27 +137,178 @@ impl Video {
     #[inline]
     pub fn set_filter(&mut self, filter: VideoFilter) {
         self.filter = filter;
+        let (width, height) = if self.decodes_rgb() {
+            VideoFilter::Pixellate.dimensions()
+        } else {
+            filter.dimensions()
+        };
+        self.resize_output(width, height);
+    }
+
+    #[inline]
+    pub const fn ppu_variant(&self) -> PpuVariant {
+        self.ppu_variant
+    }
+
+    /// Set the PPU variant used to decode colors. RGB PPUs have no composite signal to emulate,
+    /// so the NTSC and PAL filters decode their palette directly instead.
+    #[inline]
+    pub fn set_ppu_variant(&mut self, variant: PpuVariant) {
+        self.ppu_variant = variant;
+        self.set_filter(self.filter);
+    }
+
+    // Whether the current filter emulates a composite signal the PPU doesn't output.
+    const fn decodes_rgb(&self) -> bool {
+        self.ppu_variant.is_rgb() && matches!(self.filter, VideoFilter::Ntsc | VideoFilter::Pal)
+    }
+
+    #[inline]
+    pub const fn ntsc_config(&self) -> NtscConfig {
+        self.ntsc.config()
+    }
+
+    /// Set the NTSC filter parameters, regenerating its lookup tables if they changed.
+    #[inline]
+    pub fn set_ntsc_config(&mut self, config: NtscConfig) {
+        self.ntsc.set_config(config);
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn crt_config(&self) -> Option<CrtConfig> {
+        self.crt.as_ref().map(Crt::config)
+    }
+
+    /// Enable the CRT post-process, applied after the current filter, or disable it with `None`.
+    pub fn set_crt_config(&mut self, config: Option<CrtConfig>) {
+        match (config, &mut self.crt) {
+            (Some(config), Some(crt)) => crt.set_config(config),
+            (Some(config), None) => self.crt = Some(Crt::new(config)),
+            (None, _) => {
+                self.crt = None;
+                self.crt_output = vec![];
+            }
+        }
+    }
+
+    /// The overscan currently cropped from each edge, in NES pixels.
+    #[inline]
+    pub const fn overscan(&self) -> Overscan {
+        match self.overscan {
+            Some(overscan) => overscan,
+            None => Overscan::for_region(self.region),
+        }
+    }
+
+    /// Set the overscan to crop from each edge, or use the default for the current region with
+    /// `None`.
+    #[inline]
+    pub fn set_overscan(&mut self, overscan: Option<Overscan>) {
+        self.overscan = overscan;
+    }
+
+    #[inline]
+    #[must_use]
+    pub const fn pixel_aspect(&self) -> bool {
+        self.pixel_aspect
+    }
+
+    /// Stretch the output width to the pixel aspect ratio of the current region.
+    #[inline]
+    pub fn set_pixel_aspect(&mut self, enabled: bool) {
+        self.pixel_aspect = enabled;
+    }
+
+    /// Width of the rendered output, in pixels.
+    #[inline]
+    #[must_use]
+    pub fn width(&self) -> u32 {
+        self.dimensions().0
     }
 
-    // Returns a fully rendered frame of RENDER_SIZE RGB colors
+    /// Height of the rendered output, in pixels.
+    #[inline]
+    #[must_use]
+    pub fn height(&self) -> u32 {
+        self.dimensions().1
+    }
+
+    fn crop(&self) -> Crop {
+        let aspect_ratio = self
+            .pixel_aspect
+            .then(|| overscan::pixel_aspect_ratio(self.region));
+        Crop::new(self.overscan(), aspect_ratio, self.width, self.height)
+    }
+
+    fn dimensions(&self) -> (u32, u32) {
+        let (width, height) = self.crop().dimensions();
+        match &self.crt {
+            Some(crt) => crt.dimensions(width, height),
+            None => (width, height),
+        }
+    }
+
+    // Returns a fully rendered frame of RGBA colors with dimensions based on the current filter
     pub fn apply_filter(&mut self, buffer: &[u16], frame_number: u32) {
         match self.filter {
             VideoFilter::Pixellate => self.decode_buffer(buffer),
+            VideoFilter::Ntsc | VideoFilter::Pal if self.decodes_rgb() => {
+                self.decode_buffer(buffer);
+            }
             VideoFilter::Ntsc => self.apply_ntsc_filter(buffer, frame_number),
+            VideoFilter::Pal => self.apply_pal_filter(buffer, frame_number),
+            VideoFilter::Scale2x
+            | VideoFilter::Scale3x
+            | VideoFilter::Xbrz2x
+            | VideoFilter::Xbrz3x
+            | VideoFilter::Xbrz4x => self.apply_scaler(buffer),
+        }
+
+        let crop = self.crop();
+        let (frame, (width, height)) = if crop.is_identity(self.width, self.height) {
+            self.cropped = vec![];
+            (&self.output, (self.width, self.height))
+        } else {
+            crop.apply(&self.output, self.width, &mut self.cropped);
+            (&self.cropped, crop.dimensions())
+        };
+        if let Some(crt) = &mut self.crt {
+            crt.apply(frame, width, height, &mut self.crt_output);
         }
     }
 
     #[inline]
     #[must_use]
     pub fn output(&self) -> &[u8] {
-        &self.output
+        if self.crt.is_some() {
+            &self.crt_output
+        } else if !self.cropped.is_empty() {
+            &self.cropped
+        } else {
+            &self.output
+        }
+    }
+
+    fn resize_output(&mut self, width: u32, height: u32) {
+        if self.width != width || self.height != height {
+            self.width = width;
+            self.height = height;
+            self.output = vec![0x00; 4 * (width * height) as usize];
+            // Force alpha to 255.
+            for p in self.output.iter_mut().skip(3).step_by(4) {
+                *p = 255;
+            }
+        }
     }
 
     pub fn decode_buffer(&mut self, buffer: &[u16]) {
+        let (width, height) = VideoFilter::Pixellate.dimensions();
+        self.resize_output(width, height);
         assert!(buffer.len() * 4 == self.output.len());
         for (pixel, colors) in buffer.iter().zip(self.output.chunks_exact_mut(4)) {
             assert!(colors.len() > 2);
-            let (red, green, blue) = Ppu::system_palette(*pixel);
+            let (red, green, blue) = self.ppu_variant.rgb(*pixel);
             colors[0] = red;
             colors[1] = green;
             colors[2] = blue;
@@ -98,35 +316,57 @@ impl Video {
         }
     }
 
-    // Amazing implementation Bisqwit! Much faster than my original, but boy what a pain
-    // to translate it to Rust
-    // Source: https://bisqwit.iki.fi/jutut/kuvat/programming_examples/nesemu1/nesemu1.cc
-    // http://wiki.nesdev.com/w/index.php/NTSC_video
+    /// Applies the NTSC filter, producing a frame `Ntsc::WIDTH` pixels wide.
     pub fn apply_ntsc_filter(&mut self, buffer: &[u16], frame_number: u32) {
-        assert!(buffer.len() * 4 == self.output.len());
-        let mut prev_pixel = 0;
-        for (idx, (pixel, colors)) in buffer
-            .iter()
-            .zip(self.output.chunks_exact_mut(4))
-            .enumerate()
-        {
-            let x = idx % 256;
-            let color = if x == 0 {
-                // Remove pixel 0 artifact from not having a valid previous pixel
-                0
-            } else {
-                let y = idx / 256;
-                let even_phase = if frame_number & 0x01 == 0x01 { 0 } else { 1 };
-                let phase = (2 + y * 341 + x + even_phase) % 3;
-                NTSC_PALETTE
-                    [phase + ((prev_pixel & 0x3F) as usize) * 3 + (*pixel as usize) * 3 * 64]
-            };
-            prev_pixel = u32::from(*pixel);
-            assert!(colors.len() > 2);
-            colors[0] = (color >> 16 & 0xFF) as u8;
-            colors[1] = (color >> 8 & 0xFF) as u8;
-            colors[2] = (color & 0xFF) as u8;
-            // Alpha should always be 255
+        let (width, height) = VideoFilter::Ntsc.dimensions();
+        self.resize_output(width, height);
+        self.ntsc.apply(buffer, frame_number, &mut self.output);
+    }
+
+    /// Applies the PAL filter.
+    pub fn apply_pal_filter(&mut self, buffer: &[u16], frame_number: u32) {
+        let (width, height) = VideoFilter::Pal.dimensions();
+        self.resize_output(width, height);
+        self.pal.apply(buffer, frame_number, &mut self.output);
+    }
+
+    /// Applies the pixel-art scaler for the current filter.
+    pub fn apply_scaler(&mut self, buffer: &[u16]) {
+        let (width, height) = self.filter.dimensions();
+        self.resize_output(width, height);
+        for (pixel, color) in buffer.iter().zip(self.pixels.iter_mut()) {
+            let (red, green, blue) = self.ppu_variant.rgb(*pixel);
+            *color = u32::from_be_bytes([0x00, red, green, blue]);
+        }
+        let (src, w, h) = (&self.pixels, Ppu::WIDTH as usize, Ppu::HEIGHT as usize);
+        let dst = &mut self.output;
+        match self.filter {
+            VideoFilter::Scale2x => scale::scale2x(src, w, h, dst),
+            VideoFilter::Scale3x => scale::scale3x(src, w, h, dst),
+            VideoFilter::Xbrz2x => scale::xbrz(2, src, w, h, dst),
+            VideoFilter::Xbrz3x => scale::xbrz(3, src, w, h, dst),
+            VideoFilter::Xbrz4x => scale::xbrz(4, src, w, h, dst),
+            VideoFilter::Pixellate | VideoFilter::Ntsc | VideoFilter::Pal => (),
+        }
+    }
+}
+
+impl Regional for Video {
+    #[inline]
+    fn region(&self) -> NesRegion {
+        self.region
+    }
+
+    /// Set the region, switching between the NTSC and PAL filters to match.
+    #[inline]
+    fn set_region(&mut self, region: NesRegion) {
+        self.region = region;
+        match (region, self.filter) {
+            (NesRegion::Ntsc, VideoFilter::Pal) => self.set_filter(VideoFilter::Ntsc),
+            (NesRegion::Pal | NesRegion::Dendy, VideoFilter::Ntsc) => {
+                self.set_filter(VideoFilter::Pal);
+            }
+            _ => (),
         }
     }
 }
@@ -135,104 +375,15 @@ impl std::fmt::Debug for Video {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Video")
             .field("filter", &self.filter)
+            .field("ntsc", &self.ntsc)
+            .field("width", &self.width)
+            .field("height", &self.height)
             .field("output_len", &self.output.len())
+            .field("region", &self.region)
+            .field("ppu_variant", &self.ppu_variant)
+            .field("overscan", &self.overscan)
+            .field("pixel_aspect", &self.pixel_aspect)
+            .field("crt", &self.crt)
             .finish()
     }
 }
-
-pub static NTSC_PALETTE: Lazy<Vec<u32>> = Lazy::new(|| {
-    // NOTE: There's lot's to clean up here -- too many magic numbers and duplication but
-    // I'm afraid to touch it now that it works
-    // Source: https://bisqwit.iki.fi/jutut/kuvat/programming_examples/nesemu1/nesemu1.cc
-    // http://wiki.nesdev.com/w/index.php/NTSC_video
-
-    // Calculate the luma and chroma by emulating the relevant circuits:
-    const VOLTAGES: [i32; 16] = [
-        -6, -69, 26, -59, 29, -55, 73, -40, 68, -17, 125, 11, 68, 33, 125, 78,
-    ];
-
-    let mut ntsc_palette = vec![0; 512 * 64 * 3];
-
-    // Helper functions for converting YIQ to RGB
-    let gamma = 2.0; // Assumed display gamma
-    let gammafix = |color: f64| {
-        if color <= 0.0 {
-            0.0
-        } else {
-            color.powf(2.2 / gamma)
-        }
-    };
-    let yiq_divider = f64::from(9 * 10u32.pow(6));
-    for palette_offset in 0..3 {
-        for channel in 0..3 {
-            for color0_offset in 0..512 {
-                let emphasis = color0_offset / 64;
-
-                for color1_offset in 0..64 {
-                    let mut y = 0;
-                    let mut i = 0;
-                    let mut q = 0;
-                    // 12 samples of NTSC signal constitute a color.
-                    for sample in 0..12 {
-                        let noise = (sample + palette_offset * 4) % 12;
-                        // Sample either the previous or the current pixel.
-                        // Use pixel=color0 to disable artifacts.
-                        let pixel = if noise < 6 - channel * 2 {
-                            color0_offset
-                        } else {
-                            color1_offset
-                        };
-
-                        // Decode the color index.
-                        let chroma = pixel & 0x0F;
-                        // Forces luma to 0, 4, 8, or 12 for easy lookup
-                        let luma = if chroma < 0x0E { (pixel / 4) & 12 } else { 4 };
-                        // NES NTSC modulator (square wave between up to four voltage levels):
-                        let limit = if (chroma + 8 + sample) % 12 < 6 {
-                            12
-                        } else {
-                            0
-                        };
-                        let high = if chroma > limit { 1 } else { 0 };
-                        let emp_effect = if (152_278 >> (sample / 2 * 3)) & emphasis > 0 {
-                            0
-                        } else {
-                            2
-                        };
-                        let level = 40 + VOLTAGES[high + emp_effect + luma];
-                        // Ideal TV NTSC demodulator:
-                        let (sin, cos) = (PI * sample as f64 / 6.0).sin_cos();
-                        y += level;
-                        i += level * (cos * 5909.0) as i32;
-                        q += level * (sin * 5909.0) as i32;
-                    }
-                    // Store color at subpixel precision
-                    let y = f64::from(y) / 1980.0;
-                    let i = f64::from(i) / yiq_divider;
-                    let q = f64::from(q) / yiq_divider;
-                    let idx = palette_offset + color0_offset * 3 * 64 + color1_offset * 3;
-                    match channel {
-                        2 => {
-                            let rgb =
-                                255.95 * gammafix(q.mul_add(0.623_557, i.mul_add(0.946_882, y)));
-                            ntsc_palette[idx] += 0x10000 * rgb.clamp(0.0, 255.0) as u32;
-                        }
-                        1 => {
-                            let rgb =
-                                255.95 * gammafix(q.mul_add(-0.635_691, i.mul_add(-0.274_788, y)));
-                            ntsc_palette[idx] += 0x00100 * rgb.clamp(0.0, 255.0) as u32;
-                        }
-                        0 => {
-                            let rgb =
-                                255.95 * gammafix(q.mul_add(1.709_007, i.mul_add(-1.108_545, y)));
-                            ntsc_palette[idx] += rgb.clamp(0.0, 255.0) as u32;
-                        }
-                        _ => (), // invalid channel
-                    }
-                }
-            }
-        }
-    }
-
-    ntsc_palette
-});
@@ -0,0 +1,326 @@
+//! CPU CRT post-process.
+//!
+//! Runs after the other video filters on their RGBA output, upscaling it and simulating
+//! scanlines, a phosphor mask, bloom, screen curvature and vignetting. Everything runs on the
+//! CPU so it can be used headless.
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+const GAMMA: f32 = 2.2;
+const ENCODE_STEPS: usize = 4096;
+
+// sRGB channel to linear light.
+static LINEAR: Lazy<Vec<f32>> = Lazy::new(|| {
+    (0..=255)
+        .map(|value| (f32::from(value as u8) / 255.0).powf(GAMMA))
+        .collect()
+});
+// Linear light, quantized to `ENCODE_STEPS`, to sRGB channel.
+static SRGB: Lazy<Vec<u8>> = Lazy::new(|| {
+    (0..=ENCODE_STEPS)
+        .map(|step| ((step as f32 / ENCODE_STEPS as f32).powf(1.0 / GAMMA) * 255.0).round() as u8)
+        .collect()
+});
+
+/// Phosphor mask layouts.
+#[derive(Default, Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[must_use]
+pub enum CrtMask {
+    None,
+    /// Vertical RGB stripes, as on Trinitron displays.
+    #[default]
+    ApertureGrille,
+    /// RGB triads offset on each line.
+    ShadowMask,
+}
+
+impl CrtMask {
+    pub const fn as_slice() -> &'static [Self] {
+        &[Self::None, Self::ApertureGrille, Self::ShadowMask]
+    }
+}
+
+impl AsRef<str> for CrtMask {
+    fn as_ref(&self) -> &str {
+        match self {
+            Self::None => "None",
+            Self::ApertureGrille => "Aperture Grille",
+            Self::ShadowMask => "Shadow Mask",
+        }
+    }
+}
+
+impl From<usize> for CrtMask {
+    fn from(value: usize) -> Self {
+        match value {
+            1 => Self::ApertureGrille,
+            2 => Self::ShadowMask,
+            _ => Self::None,
+        }
+    }
+}
+
+/// CRT post-process parameters.
+///
+/// Effect strengths range from `0.0`, which disables the effect, to `1.0`.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[must_use]
+pub struct CrtConfig {
+    /// Integer upscale factor applied to the input frame.
+    pub scale: u32,
+    /// Darkening between scanlines.
+    pub scanlines: f32,
+    /// Phosphor mask layout.
+    pub mask: CrtMask,
+    /// Darkening of the phosphors not matching each mask color.
+    pub mask_strength: f32,
+    /// Glow added around bright areas.
+    pub bloom: f32,
+    /// Barrel distortion of the screen.
+    pub curvature: f32,
+    /// Darkening towards the screen corners.
+    pub vignette: f32,
+}
+
+impl Default for CrtConfig {
+    fn default() -> Self {
+        Self {
+            scale: 3,
+            scanlines: 0.5,
+            mask: CrtMask::default(),
+            mask_strength: 0.3,
+            bloom: 0.15,
+            curvature: 0.1,
+            vignette: 0.25,
+        }
+    }
+}
+
+/// CRT post-process state.
+#[derive(Default, Clone)]
+#[must_use]
+pub struct Crt {
+    config: CrtConfig,
+    // Linear RGB of the blurred input frame, used for bloom.
+    glow: Vec<[f32; 3]>,
+    scratch: Vec<[f32; 3]>,
+}
+
+impl Crt {
+    pub fn new(config: CrtConfig) -> Self {
+        Self {
+            config,
+            ..Self::default()
+        }
+    }
+
+    #[inline]
+    pub const fn config(&self) -> CrtConfig {
+        self.config
+    }
+
+    #[inline]
+    pub fn set_config(&mut self, config: CrtConfig) {
+        self.config = config;
+    }
+
+    /// Width and height of the output for a given input size.
+    #[inline]
+    #[must_use]
+    pub const fn dimensions(&self, width: u32, height: u32) -> (u32, u32) {
+        let scale = if self.config.scale > 0 {
+            self.config.scale
+        } else {
+            1
+        };
+        (width * scale, height * scale)
+    }
+
+    /// Applies the post-process to an RGBA `src` frame, resizing `dst` to fit the output.
+    pub fn apply(&mut self, src: &[u8], width: u32, height: u32, dst: &mut Vec<u8>) {
+        let (out_width, out_height) = self.dimensions(width, height);
+        let (width, height) = (width as usize, height as usize);
+        let (out_width, out_height) = (out_width as usize, out_height as usize);
+        dst.resize(4 * out_width * out_height, 0xFF);
+        if width == 0 || height == 0 {
+            return;
+        }
+
+        let config = self.config;
+        if config.bloom > 0.0 {
+            self.blur(src, width, height);
+        }
+
+        for oy in 0..out_height {
+            for ox in 0..out_width {
+                // Normalized screen position from -1.0 to 1.0
+                let mut u = 2.0 * (ox as f32 + 0.5) / out_width as f32 - 1.0;
+                let mut v = 2.0 * (oy as f32 + 0.5) / out_height as f32 - 1.0;
+                if config.curvature > 0.0 {
+                    let k = 0.25 * config.curvature;
+                    (u, v) = (u * (1.0 + k * v * v), v * (1.0 + k * u * u));
+                }
+
+                let idx = 4 * (oy * out_width + ox);
+                if u.abs() > 1.0 || v.abs() > 1.0 {
+                    dst[idx..idx + 3].fill(0x00);
+                    continue;
+                }
+
+                let sx = (u + 1.0) * 0.5 * width as f32;
+                let sy = (v + 1.0) * 0.5 * height as f32;
+                let mut color = Self::sample(src, width, height, sx, sy);
+
+                if config.bloom > 0.0 {
+                    let x = (sx as usize).min(width - 1);
+                    let y = (sy as usize).min(height - 1);
+                    let glow = self.glow[y * width + x];
+                    for (c, g) in color.iter_mut().zip(glow) {
+                        *c += config.bloom * g;
+                    }
+                }
+
+                let mut factor = 1.0;
+                if config.scanlines > 0.0 {
+                    // Brightest at the center of each source line
+                    let dist = 2.0 * (sy.fract() - 0.5);
+                    factor *= 1.0 - config.scanlines * dist * dist;
+                }
+                if config.vignette > 0.0 {
+                    factor *= (1.0 - config.vignette * 0.5 * (u * u + v * v)).max(0.0);
+                }
+                for c in &mut color {
+                    *c *= factor;
+                }
+
+                if config.mask_strength > 0.0 {
+                    let phosphor = match config.mask {
+                        CrtMask::None => None,
+                        CrtMask::ApertureGrille => Some(ox % 3),
+                        CrtMask::ShadowMask => Some((ox + oy) % 3),
+                    };
+                    if let Some(phosphor) = phosphor {
+                        for (i, c) in color.iter_mut().enumerate() {
+                            if i != phosphor {
+                                *c *= 1.0 - config.mask_strength;
+                            }
+                        }
+                    }
+                }
+
+                for (out, c) in dst[idx..idx + 3].iter_mut().zip(color) {
+                    *out = Self::encode(c);
+                }
+                dst[idx + 3] = 0xFF;
+            }
+        }
+    }
+
+    /// Samples the source frame, interpolating horizontally within a source line.
+    fn sample(src: &[u8], width: usize, height: usize, sx: f32, sy: f32) -> [f32; 3] {
+        let y = (sy as usize).min(height - 1);
+        let x = (sx - 0.5).max(0.0);
+        let x0 = (x as usize).min(width - 1);
+        let x1 = (x0 + 1).min(width - 1);
+        let t = x.fract();
+        let mut color = [0.0; 3];
+        for (i, c) in color.iter_mut().enumerate() {
+            let a = Self::decode(src[4 * (y * width + x0) + i]);
+            let b = Self::decode(src[4 * (y * width + x1) + i]);
+            *c = a + (b - a) * t;
+        }
+        color
+    }
+
+    /// Box blurs the source frame into `glow`.
+    fn blur(&mut self, src: &[u8], width: usize, height: usize) {
+        const RADIUS: usize = 3;
+
+        self.glow.resize(width * height, [0.0; 3]);
+        self.scratch.resize(width * height, [0.0; 3]);
+        for y in 0..height {
+            for x in 0..width {
+                let (start, end) = (x.saturating_sub(RADIUS), (x + RADIUS).min(width - 1));
+                let mut sum = [0.0; 3];
+                for sx in start..=end {
+                    for (i, s) in sum.iter_mut().enumerate() {
+                        *s += Self::decode(src[4 * (y * width + sx) + i]);
+                    }
+                }
+                let count = (end - start + 1) as f32;
+                self.scratch[y * width + x] = sum.map(|s| s / count);
+            }
+        }
+        for y in 0..height {
+            let (start, end) = (y.saturating_sub(RADIUS), (y + RADIUS).min(height - 1));
+            for x in 0..width {
+                let mut sum = [0.0; 3];
+                for sy in start..=end {
+                    for (s, c) in sum.iter_mut().zip(self.scratch[sy * width + x]) {
+                        *s += c;
+                    }
+                }
+                let count = (end - start + 1) as f32;
+                self.glow[y * width + x] = sum.map(|s| s / count);
+            }
+        }
+    }
+
+    /// Converts an sRGB channel to linear light.
+    #[inline]
+    fn decode(value: u8) -> f32 {
+        LINEAR[value as usize]
+    }
+
+    /// Converts a linear light channel to sRGB.
+    #[inline]
+    fn encode(value: f32) -> u8 {
+        SRGB[(value.clamp(0.0, 1.0) * ENCODE_STEPS as f32) as usize]
+    }
+}
+
+impl std::fmt::Debug for Crt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Crt").field("config", &self.config).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scanlines() {
+        let mut crt = Crt::new(CrtConfig {
+            scale: 4,
+            scanlines: 1.0,
+            mask: CrtMask::None,
+            mask_strength: 0.0,
+            bloom: 0.0,
+            curvature: 0.0,
+            vignette: 0.0,
+        });
+        let src = vec![0xFF; 4 * 2 * 2];
+        let mut dst = vec![];
+        crt.apply(&src, 2, 2, &mut dst);
+        assert_eq!(crt.dimensions(2, 2), (8, 8));
+        assert_eq!(dst.len(), 4 * 8 * 8);
+        // Middle of a source line is brighter than its edge
+        let row = |y: usize| dst[4 * 8 * y];
+        assert!(row(1) > row(0));
+        assert!(row(2) > row(3));
+    }
+
+    #[test]
+    fn curvature_masks_corners() {
+        let mut crt = Crt::new(CrtConfig {
+            curvature: 1.0,
+            ..CrtConfig::default()
+        });
+        let src = vec![0xFF; 4 * 16 * 16];
+        let mut dst = vec![];
+        crt.apply(&src, 16, 16, &mut dst);
+        assert_eq!(&dst[0..4], &[0x00, 0x00, 0x00, 0xFF]);
+    }
+}
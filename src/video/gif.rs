@@ -0,0 +1,184 @@
+//! Animated GIF capture.
+//!
+//! Frames use the `Pixellate` decode path, so every pixel maps directly onto the 64-color NES
+//! system palette without any quantization.
+
+use crate::{common::NesRegion, ppu::Ppu, recorder, NesResult};
+use anyhow::Context;
+use std::{io::Write, time::Duration};
+
+/// GIF capture parameters.
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
+#[must_use]
+pub struct GifConfig {
+    /// Number of frames to skip after each captured frame.
+    pub frame_skip: u32,
+    /// Stop capturing once the animation reaches this length.
+    pub max_duration: Option<Duration>,
+}
+
+/// Encodes PPU frames into an animated GIF.
+#[must_use]
+pub struct GifRecorder<W: Write> {
+    encoder: ::gif::Encoder<W>,
+    config: GifConfig,
+    // Duration of a single emulated frame, in seconds.
+    frame_duration: f64,
+    // Frames seen since the last captured frame.
+    skipped: u32,
+    elapsed: f64,
+    // Elapsed time already written as frame delays, in centiseconds.
+    written_delay: u64,
+    pending: Option<Vec<u8>>,
+}
+
+impl<W: Write> GifRecorder<W> {
+    /// Start a GIF, writing the header and global palette.
+    ///
+    /// # Errors
+    ///
+    /// If writing the header fails, then an error is returned.
+    pub fn new(writer: W, region: NesRegion, config: GifConfig) -> NesResult<Self> {
+        let mut encoder = ::gif::Encoder::new(
+            writer,
+            Ppu::WIDTH as u16,
+            Ppu::HEIGHT as u16,
+            &Self::palette(),
+        )
+        .context("failed to write gif header")?;
+        encoder
+            .set_repeat(::gif::Repeat::Infinite)
+            .context("failed to write gif header")?;
+        let (num, den) = recorder::frame_rate(region);
+        Ok(Self {
+            encoder,
+            config,
+            frame_duration: f64::from(den) / f64::from(num),
+            skipped: 0,
+            elapsed: 0.0,
+            written_delay: 0,
+            pending: None,
+        })
+    }
+
+    /// The NES system palette as packed RGB triplets.
+    #[must_use]
+    pub fn palette() -> Vec<u8> {
+        (0..64)
+            .flat_map(|pixel| {
+                let (red, green, blue) = Ppu::system_palette(pixel);
+                [red, green, blue]
+            })
+            .collect()
+    }
+
+    /// Add a frame of PPU pixels, honoring frame skip. Returns `false` once the maximum
+    /// duration has been reached and no more frames will be accepted.
+    ///
+    /// # Errors
+    ///
+    /// If writing the previous frame fails, then an error is returned.
+    pub fn add_frame(&mut self, buffer: &[u16]) -> NesResult<bool> {
+        if self.is_full() {
+            return Ok(false);
+        }
+        if self.pending.is_some() && self.skipped < self.config.frame_skip {
+            self.skipped += 1;
+            self.elapsed += self.frame_duration;
+            return Ok(!self.is_full());
+        }
+        self.skipped = 0;
+        self.flush_pending()?;
+        // Emphasis bits are ignored, matching `Video::decode_buffer`
+        let indices = buffer.iter().map(|pixel| (pixel & 0x3F) as u8).collect();
+        self.pending = Some(indices);
+        self.elapsed += self.frame_duration;
+        Ok(!self.is_full())
+    }
+
+    /// Write any remaining frame and the GIF trailer.
+    ///
+    /// # Errors
+    ///
+    /// If writing fails, then an error is returned.
+    pub fn finish(mut self) -> NesResult<W> {
+        self.flush_pending()?;
+        self.encoder
+            .into_inner()
+            .context("failed to write gif trailer")
+    }
+
+    // Whether another frame would exceed the maximum duration.
+    fn is_full(&self) -> bool {
+        self.config.max_duration.is_some_and(|max| {
+            self.elapsed + self.frame_duration > max.as_secs_f64() + f64::EPSILON
+        })
+    }
+
+    // Frames are written once their delay is known, which is when the next frame is captured or
+    // the capture finishes. Delays are in centiseconds, so they are rounded against the total
+    // elapsed time to avoid drift.
+    fn flush_pending(&mut self) -> NesResult<()> {
+        if let Some(indices) = self.pending.take() {
+            let total_delay = (self.elapsed * 100.0).round() as u64;
+            let delay = total_delay.saturating_sub(self.written_delay).max(1);
+            self.written_delay += delay;
+            let mut frame = ::gif::Frame {
+                width: Ppu::WIDTH as u16,
+                height: Ppu::HEIGHT as u16,
+                buffer: indices.into(),
+                ..::gif::Frame::default()
+            };
+            frame.delay = u16::try_from(delay).unwrap_or(u16::MAX);
+            self.encoder
+                .write_frame(&frame)
+                .context("failed to write gif frame")?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> std::fmt::Debug for GifRecorder<W> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GifRecorder")
+            .field("config", &self.config)
+            .field("elapsed", &self.elapsed)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_skip_and_duration() {
+        let config = GifConfig {
+            frame_skip: 1,
+            max_duration: Some(Duration::from_millis(100)),
+        };
+        let mut gif = GifRecorder::new(vec![], NesRegion::Ntsc, config).expect("valid gif");
+        let buffer = vec![0x16; Ppu::SIZE];
+        let mut added = 0;
+        while gif.add_frame(&buffer).expect("added frame") {
+            added += 1;
+        }
+        // ~6 emulated frames in 100ms
+        assert_eq!(added, 5);
+        let data = gif.finish().expect("finished gif");
+
+        let mut options = ::gif::DecodeOptions::new();
+        options.set_color_output(::gif::ColorOutput::Indexed);
+        let mut decoder = options.read_info(data.as_slice()).expect("gif header");
+        assert_eq!(decoder.global_palette().map(<[u8]>::len), Some(3 * 64));
+        let mut frames = 0;
+        let mut delay = 0;
+        while let Some(frame) = decoder.read_next_frame().expect("gif frame") {
+            assert!(frame.buffer.iter().all(|&index| index == 0x16));
+            delay += frame.delay;
+            frames += 1;
+        }
+        assert_eq!(frames, 3);
+        assert_eq!(delay, 10);
+    }
+}
@@ -0,0 +1,399 @@
+//! Configurable NTSC composite video filter.
+//!
+//! Emulates the NES NTSC signal and demodulates it back into RGB, in the spirit of Blargg's
+//! `nes_ntsc`. A lookup table is generated from a set of [`NtscConfig`] parameters and is
+//! regenerated whenever they change.
+//!
+//! <https://bisqwit.iki.fi/jutut/kuvat/programming_examples/nesemu1/nesemu1.cc>
+//! <http://wiki.nesdev.com/w/index.php/NTSC_video>
+//! <http://slack.net/~ant/libs/ntsc.html>
+
+use crate::ppu::Ppu;
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+
+/// NTSC filter presets matching the common video connections.
+#[derive(Default, Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[must_use]
+pub enum NtscPreset {
+    #[default]
+    Composite,
+    SVideo,
+    Rgb,
+    Monochrome,
+}
+
+impl NtscPreset {
+    pub const fn as_slice() -> &'static [Self] {
+        &[Self::Composite, Self::SVideo, Self::Rgb, Self::Monochrome]
+    }
+}
+
+impl AsRef<str> for NtscPreset {
+    fn as_ref(&self) -> &str {
+        match self {
+            Self::Composite => "Composite",
+            Self::SVideo => "S-Video",
+            Self::Rgb => "RGB",
+            Self::Monochrome => "Monochrome",
+        }
+    }
+}
+
+impl From<usize> for NtscPreset {
+    fn from(value: usize) -> Self {
+        match value {
+            1 => Self::SVideo,
+            2 => Self::Rgb,
+            3 => Self::Monochrome,
+            _ => Self::Composite,
+        }
+    }
+}
+
+/// NTSC filter parameters.
+///
+/// Each parameter ranges from `-1.0` to `1.0`, where `0.0` is the neutral setting.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[must_use]
+pub struct NtscConfig {
+    /// Hue rotation, from -180 to +180 degrees.
+    pub hue: f32,
+    /// Color saturation, where `-1.0` is grayscale.
+    pub saturation: f32,
+    /// Luma contrast.
+    pub contrast: f32,
+    /// Luma brightness.
+    pub brightness: f32,
+    /// Horizontal sharpness of the luma signal.
+    pub sharpness: f32,
+    /// Display gamma adjustment.
+    pub gamma: f32,
+    /// Color artifacts caused by luma bleeding into chroma, where `-1.0` disables them.
+    pub artifacts: f32,
+    /// Color fringing caused by chroma bleeding into luma, where `-1.0` disables it.
+    pub fringing: f32,
+}
+
+impl Default for NtscConfig {
+    fn default() -> Self {
+        Self::composite()
+    }
+}
+
+impl NtscConfig {
+    /// Composite video, with full artifacts and fringing.
+    pub const fn composite() -> Self {
+        Self {
+            hue: 0.0,
+            saturation: 0.0,
+            contrast: 0.0,
+            brightness: 0.0,
+            sharpness: 0.0,
+            gamma: 0.0,
+            artifacts: 0.0,
+            fringing: 0.0,
+        }
+    }
+
+    /// S-Video, with separate luma and chroma signals.
+    pub const fn svideo() -> Self {
+        Self {
+            sharpness: 0.2,
+            artifacts: -1.0,
+            fringing: -1.0,
+            ..Self::composite()
+        }
+    }
+
+    /// RGB, which has no crosstalk between signals at all.
+    pub const fn rgb() -> Self {
+        Self {
+            sharpness: 0.7,
+            artifacts: -1.0,
+            fringing: -1.0,
+            ..Self::composite()
+        }
+    }
+
+    /// Monochrome composite video.
+    pub const fn monochrome() -> Self {
+        Self {
+            saturation: -1.0,
+            sharpness: 0.2,
+            artifacts: -0.2,
+            fringing: -0.2,
+            ..Self::composite()
+        }
+    }
+}
+
+impl From<NtscPreset> for NtscConfig {
+    fn from(preset: NtscPreset) -> Self {
+        match preset {
+            NtscPreset::Composite => Self::composite(),
+            NtscPreset::SVideo => Self::svideo(),
+            NtscPreset::Rgb => Self::rgb(),
+            NtscPreset::Monochrome => Self::monochrome(),
+        }
+    }
+}
+
+/// NTSC filter state, holding the current parameters and the lookup table generated from them.
+#[derive(Clone)]
+#[must_use]
+pub struct Ntsc {
+    config: NtscConfig,
+    // Indexed by phase, previous pixel, current pixel and sub-pixel. Empty until first use, or
+    // after the config changes.
+    lut: Vec<u32>,
+}
+
+impl Default for Ntsc {
+    fn default() -> Self {
+        Self::new(NtscConfig::default())
+    }
+}
+
+impl Ntsc {
+    /// Output width, matching `nes_ntsc`.
+    pub const WIDTH: u32 = 602;
+
+    const PHASES: usize = 3;
+    const PREV_COLORS: usize = 64;
+    const COLORS: usize = 512;
+    // Each pixel is 8 samples wide and is decoded at 4 sub-pixel positions.
+    const PIXEL_SAMPLES: usize = 8;
+    const SUB_PIXELS: usize = 4;
+    // One full color subcarrier cycle.
+    const CYCLE_SAMPLES: usize = 12;
+    const LINE_SAMPLES: usize = Ppu::WIDTH as usize * Self::PIXEL_SAMPLES;
+
+    // Signal voltage levels for low/high at each luma level, with and without emphasis.
+    const VOLTAGES: [i32; 16] = [
+        -6, -69, 26, -59, 29, -55, 73, -40, 68, -17, 125, 11, 68, 33, 125, 78,
+    ];
+
+    pub const fn new(config: NtscConfig) -> Self {
+        Self {
+            config,
+            lut: vec![],
+        }
+    }
+
+    #[inline]
+    pub const fn config(&self) -> NtscConfig {
+        self.config
+    }
+
+    /// Set new filter parameters. The lookup table is regenerated on the next frame if they
+    /// changed.
+    pub fn set_config(&mut self, config: NtscConfig) {
+        if self.config != config {
+            self.config = config;
+            self.lut.clear();
+        }
+    }
+
+    /// Decode a frame of NES palette indexes into `Ntsc::WIDTH` wide RGBA pixels.
+    pub fn apply(&mut self, buffer: &[u16], frame_number: u32, output: &mut [u8]) {
+        if self.lut.is_empty() {
+            self.generate_lut();
+        }
+
+        let width = Ppu::WIDTH as usize;
+        let out_width = Self::WIDTH as usize;
+        assert!(buffer.len() / width * out_width * 4 == output.len());
+
+        let even_phase = if frame_number & 0x01 == 0x01 { 0 } else { 1 };
+        for (y, (line, out_line)) in buffer
+            .chunks_exact(width)
+            .zip(output.chunks_exact_mut(out_width * 4))
+            .enumerate()
+        {
+            for (out_x, colors) in out_line.chunks_exact_mut(4).enumerate() {
+                let sample = (out_x * Self::LINE_SAMPLES + out_width / 2) / out_width;
+                let x = sample / Self::PIXEL_SAMPLES;
+                let sub = (sample % Self::PIXEL_SAMPLES) * Self::SUB_PIXELS / Self::PIXEL_SAMPLES;
+                let pixel = line[x] as usize & (Self::COLORS - 1);
+                // Black border to the left of the first pixel
+                let prev_pixel = if x == 0 { 0x0F } else { line[x - 1] as usize };
+                let phase = (2 + y * 341 + x + even_phase) % Self::PHASES;
+                let color = self.lut[Self::lut_index(phase, prev_pixel, pixel, sub)];
+                colors[0] = (color >> 16 & 0xFF) as u8;
+                colors[1] = (color >> 8 & 0xFF) as u8;
+                colors[2] = (color & 0xFF) as u8;
+                // Alpha should always be 255
+            }
+        }
+    }
+
+    #[inline]
+    const fn lut_index(phase: usize, prev_pixel: usize, pixel: usize, sub: usize) -> usize {
+        let prev_pixel = prev_pixel & (Self::PREV_COLORS - 1);
+        ((pixel * Self::PREV_COLORS + prev_pixel) * Self::PHASES + phase) * Self::SUB_PIXELS + sub
+    }
+
+    // Signal level of a pixel at a given subcarrier phase, emulating the NES NTSC modulator which
+    // outputs a square wave between up to four voltage levels.
+    fn signal_level(pixel: usize, phase: usize) -> f32 {
+        let chroma = pixel & 0x0F;
+        // Forces luma to 0, 4, 8, or 12 for easy lookup
+        let luma = if chroma < 0x0E {
+            (pixel >> 2) & 0x0C
+        } else {
+            4
+        };
+        let limit = if (chroma + 8 + phase) % 12 < 6 { 12 } else { 0 };
+        let high = usize::from(chroma > limit);
+        let emphasis = pixel >> 6;
+        let attenuate = if (152_278 >> (phase / 2 * 3)) & emphasis > 0 {
+            0
+        } else {
+            2
+        };
+        (40 + Self::VOLTAGES[high + attenuate + luma]) as f32
+    }
+
+    fn generate_lut(&mut self) {
+        let NtscConfig {
+            hue,
+            saturation,
+            contrast,
+            brightness,
+            sharpness,
+            gamma,
+            artifacts,
+            fringing,
+        } = self.config;
+
+        let mut levels = vec![0.0; Self::COLORS * Self::CYCLE_SAMPLES];
+        for (pixel, levels) in levels.chunks_exact_mut(Self::CYCLE_SAMPLES).enumerate() {
+            for (phase, level) in levels.iter_mut().enumerate() {
+                *level = Self::signal_level(pixel, phase);
+            }
+        }
+        let (sin, cos): (Vec<f32>, Vec<f32>) = (0..Self::CYCLE_SAMPLES)
+            .map(|phase| (PI * phase as f32 / 6.0).sin_cos())
+            .unzip();
+
+        // How much of the previous pixel bleeds into the chroma and luma decoders.
+        let artifacts = (1.0 + artifacts).clamp(0.0, 2.0);
+        let fringing = (1.0 + fringing).clamp(0.0, 2.0);
+        // Luma is averaged over a window of 8 to 16 samples depending on sharpness.
+        let luma_window = (12.0 - 4.0 * sharpness.clamp(-1.0, 1.0)).round() as i32;
+        let (hue_sin, hue_cos) = (hue.clamp(-1.0, 1.0) * PI).sin_cos();
+        let saturation = (1.0 + saturation).clamp(0.0, 2.0);
+        let contrast = 1.0 + contrast.clamp(-1.0, 1.0) * 0.5;
+        let brightness = brightness.clamp(-1.0, 1.0) * 0.5;
+        // Assumed display gamma of 2.0
+        let gamma = 2.2 / (2.0 - gamma.clamp(-1.0, 1.0) * 0.5);
+        let to_rgb = |color: f32| {
+            let color = if color <= 0.0 { 0.0 } else { color.powf(gamma) };
+            (255.95 * color).clamp(0.0, 255.0) as u32
+        };
+
+        let size = Self::PHASES * Self::PREV_COLORS * Self::COLORS * Self::SUB_PIXELS;
+        self.lut.resize(size, 0);
+        let pixel_samples = Self::PIXEL_SAMPLES as i32;
+        for pixel in 0..Self::COLORS {
+            let cur = &levels[pixel * Self::CYCLE_SAMPLES..(pixel + 1) * Self::CYCLE_SAMPLES];
+            for prev_pixel in 0..Self::PREV_COLORS {
+                let prev = &levels
+                    [prev_pixel * Self::CYCLE_SAMPLES..(prev_pixel + 1) * Self::CYCLE_SAMPLES];
+                for phase in 0..Self::PHASES {
+                    // Subcarrier phase of the first sample of the current pixel
+                    let start = (phase * 4) as i32;
+                    for sub in 0..Self::SUB_PIXELS {
+                        let center = (2 * sub + 1) as i32;
+                        let sample_at = |offset: i32, bleed: f32| {
+                            let phase = (start + offset).rem_euclid(12) as usize;
+                            if offset < 0 {
+                                cur[phase] + bleed * (prev[phase] - cur[phase])
+                            } else {
+                                cur[phase]
+                            }
+                        };
+
+                        let mut i = 0.0;
+                        let mut q = 0.0;
+                        for offset in center - 6..center + 6 {
+                            let phase = (start + offset).rem_euclid(12) as usize;
+                            let level = sample_at(offset, artifacts);
+                            i += level * cos[phase];
+                            q += level * sin[phase];
+                        }
+                        let mut y = 0.0;
+                        let lo = (center - luma_window / 2).max(-pixel_samples);
+                        let hi = center + luma_window / 2;
+                        for offset in lo..hi {
+                            y += sample_at(offset, fringing);
+                        }
+                        let y = y / (hi - lo) as f32 / 165.0;
+                        let i = i / 1523.1;
+                        let q = q / 1523.1;
+
+                        let (i, q) = (
+                            saturation * (i * hue_cos - q * hue_sin),
+                            saturation * (i * hue_sin + q * hue_cos),
+                        );
+                        let y = y.mul_add(contrast, brightness);
+
+                        let red = to_rgb(q.mul_add(0.623_557, i.mul_add(0.946_882, y)));
+                        let green = to_rgb(q.mul_add(-0.635_691, i.mul_add(-0.274_788, y)));
+                        let blue = to_rgb(q.mul_add(1.709_007, i.mul_add(-1.108_545, y)));
+                        self.lut[Self::lut_index(phase, prev_pixel, pixel, sub)] =
+                            (red << 16) | (green << 8) | blue;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for Ntsc {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Ntsc")
+            .field("config", &self.config)
+            .field("lut_len", &self.lut.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(ntsc: &mut Ntsc, buffer: &[u16]) -> Vec<u8> {
+        let mut output = vec![0x00; 4 * Ntsc::WIDTH as usize * Ppu::HEIGHT as usize];
+        ntsc.apply(buffer, 0, &mut output);
+        output
+    }
+
+    #[test]
+    fn decode_color() {
+        let mut ntsc = Ntsc::default();
+        let buffer = vec![0x16; Ppu::SIZE];
+        let output = apply(&mut ntsc, &buffer);
+        let (red, green, blue) = (output[40], output[41], output[42]);
+        assert!(red > green && red > blue, "red: ({red}, {green}, {blue})");
+    }
+
+    #[test]
+    fn lut_regenerates() {
+        let mut ntsc = Ntsc::default();
+        let buffer = vec![0x2A; Ppu::SIZE];
+        let composite = apply(&mut ntsc, &buffer);
+        ntsc.set_config(NtscPreset::Monochrome.into());
+        assert!(ntsc.lut.is_empty(), "lut cleared");
+
+        let monochrome = apply(&mut ntsc, &buffer);
+        assert_ne!(composite, monochrome);
+        for colors in monochrome.chunks_exact(4).skip(8).take(64) {
+            assert!(
+                colors[0].abs_diff(colors[1]) <= 1 && colors[1].abs_diff(colors[2]) <= 1,
+                "grayscale: {colors:?}"
+            );
+        }
+    }
+}
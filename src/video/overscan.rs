@@ -0,0 +1,171 @@
+//! Overscan cropping and pixel aspect ratio correction.
+//!
+//! <https://wiki.nesdev.com/w/index.php/Overscan>
+
+use crate::{common::NesRegion, ppu::Ppu};
+use serde::{Deserialize, Serialize};
+
+/// Number of NES pixels hidden from each edge of the frame.
+#[derive(Default, Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[must_use]
+pub struct Overscan {
+    pub top: u32,
+    pub bottom: u32,
+    pub left: u32,
+    pub right: u32,
+}
+
+impl Overscan {
+    pub const fn new(top: u32, bottom: u32, left: u32, right: u32) -> Self {
+        Self {
+            top,
+            bottom,
+            left,
+            right,
+        }
+    }
+
+    /// Default overscan for a region. NTSC TVs hide roughly 8 lines at the top and bottom, while
+    /// the PAL PPU blanks the top scanline and the two pixels at each side itself.
+    pub const fn for_region(region: NesRegion) -> Self {
+        match region {
+            NesRegion::Ntsc => Self::new(8, 8, 0, 0),
+            NesRegion::Pal | NesRegion::Dendy => Self::new(1, 0, 2, 2),
+        }
+    }
+
+    /// Visible width and height, in NES pixels.
+    #[must_use]
+    pub const fn visible(&self) -> (u32, u32) {
+        (
+            Ppu::WIDTH.saturating_sub(self.left + self.right),
+            Ppu::HEIGHT.saturating_sub(self.top + self.bottom),
+        )
+    }
+}
+
+/// Pixel aspect ratio of the NES output for a region. NTSC pixels are 8:7.
+#[must_use]
+pub const fn pixel_aspect_ratio(region: NesRegion) -> f32 {
+    match region {
+        NesRegion::Ntsc => 8.0 / 7.0,
+        NesRegion::Pal | NesRegion::Dendy => 2_950_000.0 / 2_128_137.0,
+    }
+}
+
+/// Crops and optionally stretches filtered frames.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[must_use]
+pub(crate) struct Crop {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    out_width: u32,
+}
+
+impl Crop {
+    /// Determine the crop of a `width` x `height` filtered frame, scaling the overscan from NES
+    /// pixels to filtered pixels. If `aspect_ratio` is provided, the output width is stretched to
+    /// match it.
+    pub(crate) fn new(
+        overscan: Overscan,
+        aspect_ratio: Option<f32>,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let scale_x = width as f32 / Ppu::WIDTH as f32;
+        let scale_y = height / Ppu::HEIGHT;
+        let (visible_width, visible_height) = overscan.visible();
+        let x = ((overscan.left as f32 * scale_x).round() as u32).min(width);
+        let crop_width = ((visible_width as f32 * scale_x).round() as u32).min(width - x);
+        let y = (overscan.top * scale_y).min(height);
+        let crop_height = (visible_height * scale_y).min(height - y);
+        let out_width = aspect_ratio.map_or(crop_width, |ratio| {
+            (visible_width as f32 * ratio * scale_y as f32).round() as u32
+        });
+        Self {
+            x,
+            y,
+            width: crop_width,
+            height: crop_height,
+            out_width,
+        }
+    }
+
+    /// Whether cropping would leave the frame unchanged.
+    #[must_use]
+    pub(crate) const fn is_identity(&self, width: u32, height: u32) -> bool {
+        self.x == 0
+            && self.y == 0
+            && self.width == width
+            && self.height == height
+            && self.out_width == width
+    }
+
+    #[must_use]
+    pub(crate) const fn dimensions(&self) -> (u32, u32) {
+        (self.out_width, self.height)
+    }
+
+    /// Crops an RGBA `src` frame of `width` pixels wide into `dst`, resampling each line
+    /// horizontally if the output width differs.
+    pub(crate) fn apply(&self, src: &[u8], width: u32, dst: &mut Vec<u8>) {
+        let (width, out_width) = (width as usize, self.out_width as usize);
+        let (x, y) = (self.x as usize, self.y as usize);
+        let (crop_width, crop_height) = (self.width as usize, self.height as usize);
+        dst.resize(4 * out_width * crop_height, 0xFF);
+        if crop_width == 0 || out_width == 0 {
+            return;
+        }
+        let step = crop_width as f32 / out_width as f32;
+        for row in 0..crop_height {
+            let line = &src[4 * ((y + row) * width + x)..][..4 * crop_width];
+            let out = &mut dst[4 * row * out_width..][..4 * out_width];
+            if crop_width == out_width {
+                out.copy_from_slice(line);
+                continue;
+            }
+            for (ox, pixel) in out.chunks_exact_mut(4).enumerate() {
+                let sx = ((ox as f32 + 0.5) * step - 0.5).max(0.0);
+                let x0 = (sx as usize).min(crop_width - 1);
+                let x1 = (x0 + 1).min(crop_width - 1);
+                let t = sx.fract();
+                for i in 0..3 {
+                    let a = f32::from(line[4 * x0 + i]);
+                    let b = f32::from(line[4 * x1 + i]);
+                    pixel[i] = (a + (b - a) * t).round() as u8;
+                }
+                pixel[3] = 0xFF;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ntsc_crop() {
+        let crop = Crop::new(Overscan::for_region(NesRegion::Ntsc), None, 256, 240);
+        assert_eq!(crop.dimensions(), (256, 224));
+        let crop = Crop::new(Overscan::for_region(NesRegion::Ntsc), None, 512, 480);
+        assert_eq!(crop.dimensions(), (512, 448));
+        let crop = Crop::new(Overscan::default(), None, 256, 240);
+        assert!(crop.is_identity(256, 240));
+    }
+
+    #[test]
+    fn aspect_ratio() {
+        let ratio = pixel_aspect_ratio(NesRegion::Ntsc);
+        let crop = Crop::new(Overscan::default(), Some(ratio), 256, 240);
+        assert_eq!(crop.dimensions(), (293, 240));
+
+        let src = vec![0x80; 4 * 256 * 240];
+        let mut dst = vec![];
+        crop.apply(&src, 256, &mut dst);
+        assert_eq!(dst.len(), 4 * 293 * 240);
+        assert!(dst.chunks_exact(4).all(|p| p == [0x80, 0x80, 0x80, 0xFF]));
+    }
+}
@@ -0,0 +1,292 @@
+//! PAL composite video filter.
+//!
+//! Emulates the 2C07 composite signal and decodes it the way a PAL receiver does. The 2C07 runs
+//! from a 26.6 MHz master clock, six times the 4.43 MHz color subcarrier, and outputs a pixel
+//! every five master clocks. Like the NTSC PPU, it generates chroma as a square wave sampled on
+//! both clock edges, giving 12 subcarrier phases, so each pixel spans 10 phases instead of 8.
+//!
+//! On alternating lines the 2C07 reverses its chroma generator, mirroring every hue around the U
+//! axis and so inverting V. The receiver switches V back and averages each line's chroma with
+//! the previous line through a delay line, so phase errors on one line cancel out against the
+//! next at the cost of vertical color resolution. The switch alternates between frames as well.
+//!
+//! Emphasis bits are already normalized by the PPU, since the 2C07 swaps the red and green
+//! emphasis bits in `PPUMASK`.
+//!
+//! <https://www.nesdev.org/wiki/PAL_video>
+//! <https://www.nesdev.org/wiki/NTSC_video>
+
+use crate::ppu::Ppu;
+use std::f32::consts::PI;
+
+/// PAL filter state, holding the signal levels of every palette entry.
+#[derive(Default, Clone)]
+#[must_use]
+pub struct Pal {
+    // Indexed by pixel and generator phase. Empty until first use.
+    levels: Vec<f32>,
+    // Composite signal of the current line.
+    signal: Vec<f32>,
+    // Decoded chroma of the current and previous line, for the delay line.
+    chroma: Vec<[f32; 2]>,
+    prev_chroma: Vec<[f32; 2]>,
+}
+
+impl Pal {
+    const COLORS: usize = 512;
+    // One full color subcarrier cycle.
+    const CYCLE_SAMPLES: usize = 12;
+    // Five master clocks per pixel, sampled on both edges.
+    const PIXEL_SAMPLES: usize = 10;
+    // A line is 341 pixels, so the subcarrier phase advances by 2 each line.
+    const LINE_PHASE: usize = 341 * Self::PIXEL_SAMPLES % Self::CYCLE_SAMPLES;
+    // Black border pixels on each side of a line, covering the decode windows.
+    const BORDER: usize = 3;
+    // The U axis sits at phase 4. Mirroring the generator phase around it inverts V.
+    const V_SWITCH_PHASE: usize = 8;
+
+    // Low and high signal levels in volts above sync for each luma level.
+    const LOW: [f32; 4] = [0.228, 0.312, 0.552, 0.880];
+    const HIGH: [f32; 4] = [0.616, 0.840, 1.100, 1.100];
+    const BLACK: f32 = 0.312;
+    const WHITE: f32 = 1.100;
+    // Emphasis attenuates the signal during the phases of the emphasized colors.
+    const EMPHASIS_ATTENUATION: f32 = 0.746;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decode a frame of NES palette indexes into RGBA pixels.
+    pub fn apply(&mut self, buffer: &[u16], frame_number: u32, output: &mut [u8]) {
+        if self.levels.is_empty() {
+            self.generate_levels();
+        }
+
+        let width = Ppu::WIDTH as usize;
+        assert!(buffer.len() * 4 == output.len());
+        self.chroma.resize(width, [0.0; 2]);
+        self.prev_chroma.clear();
+
+        let (u_axis, v_axis): (Vec<f32>, Vec<f32>) = (0..Self::CYCLE_SAMPLES)
+            .map(|phase| {
+                let angle = PI * phase as f32 / 6.0;
+                ((angle - 2.0 * PI / 3.0).cos(), (angle - PI / 6.0).cos())
+            })
+            .unzip();
+        for (y, (line, out_line)) in buffer
+            .chunks_exact(width)
+            .zip(output.chunks_exact_mut(width * 4))
+            .enumerate()
+        {
+            let v_switch = (y + frame_number as usize) & 0x01 == 0x01;
+            let line_phase = y * Self::LINE_PHASE % Self::CYCLE_SAMPLES;
+            self.generate_line(line, line_phase, v_switch);
+
+            for (x, colors) in out_line.chunks_exact_mut(4).enumerate() {
+                let center = (x + Self::BORDER) * Self::PIXEL_SAMPLES + Self::PIXEL_SAMPLES / 2;
+
+                // A full subcarrier cycle cancels out chroma
+                let luma_window =
+                    center - Self::CYCLE_SAMPLES / 2..center + Self::CYCLE_SAMPLES / 2;
+                let luma =
+                    self.signal[luma_window].iter().sum::<f32>() / Self::CYCLE_SAMPLES as f32;
+                let luma = (luma - Self::BLACK) / (Self::WHITE - Self::BLACK);
+
+                // Chroma has a lower bandwidth, so it's demodulated over two cycles
+                let (mut u, mut v) = (0.0, 0.0);
+                for sample in center - Self::CYCLE_SAMPLES..center + Self::CYCLE_SAMPLES {
+                    let level = self.signal[sample];
+                    let phase = (line_phase + sample) % Self::CYCLE_SAMPLES;
+                    u += level * u_axis[phase];
+                    v += level * v_axis[phase];
+                }
+                let scale = Self::CYCLE_SAMPLES as f32 * (Self::WHITE - Self::BLACK);
+                let (u, v) = (u / scale, v / scale);
+                self.chroma[x] = [u, if v_switch { -v } else { v }];
+
+                // Delay line
+                let [mut u, mut v] = self.chroma[x];
+                if let Some(prev) = self.prev_chroma.get(x) {
+                    u = 0.5 * (u + prev[0]);
+                    v = 0.5 * (v + prev[1]);
+                }
+                let (red, green, blue) = Self::yuv_to_rgb(luma, u, v);
+                colors[0] = red;
+                colors[1] = green;
+                colors[2] = blue;
+                // Alpha should always be 255
+            }
+
+            self.prev_chroma.clone_from(&self.chroma);
+        }
+    }
+
+    #[inline]
+    const fn palette_index(pixel: u16) -> usize {
+        pixel as usize & (Self::COLORS - 1)
+    }
+
+    // Composite signal of a line with a black border, starting `BORDER` pixels before the first
+    // pixel at `line_phase`.
+    fn generate_line(&mut self, line: &[u16], line_phase: usize, v_switch: bool) {
+        let border = [0x0F; Self::BORDER];
+        let pixels = border.iter().chain(line).chain(&border);
+        self.signal.clear();
+        for pixel in pixels {
+            let pixel = Self::palette_index(*pixel);
+            for _ in 0..Self::PIXEL_SAMPLES {
+                let phase = (line_phase + self.signal.len()) % Self::CYCLE_SAMPLES;
+                let phase = if v_switch {
+                    (Self::V_SWITCH_PHASE + Self::CYCLE_SAMPLES - phase) % Self::CYCLE_SAMPLES
+                } else {
+                    phase
+                };
+                self.signal
+                    .push(self.levels[pixel * Self::CYCLE_SAMPLES + phase]);
+            }
+        }
+    }
+
+    // Signal level of a pixel at a given chroma generator phase. The 2C07 outputs a square wave
+    // between the low and high level of each luma level, in phase with the hue.
+    fn signal_level(pixel: usize, phase: usize) -> f32 {
+        let hue = pixel & 0x0F;
+        // Hues $E and $F output black
+        let luma = if hue < 0x0E { (pixel >> 4) & 0x03 } else { 1 };
+        let high = match hue {
+            0x00 => true,
+            0x01..=0x0C => (hue + 8 + phase) % Self::CYCLE_SAMPLES < 6,
+            _ => false,
+        };
+        let level = if high {
+            Self::HIGH[luma]
+        } else {
+            Self::LOW[luma]
+        };
+        // Red, green and blue emphasis each attenuate the half of the phases centered on the
+        // opposite hue
+        let emphasis = pixel >> 6;
+        let emphasized = [0x0C, 0x04, 0x08].iter().enumerate().any(|(bit, hue)| {
+            emphasis & (1 << bit) != 0 && (hue + 8 + phase) % Self::CYCLE_SAMPLES < 6
+        });
+        if emphasized {
+            level * Self::EMPHASIS_ATTENUATION
+        } else {
+            level
+        }
+    }
+
+    fn yuv_to_rgb(y: f32, u: f32, v: f32) -> (u8, u8, u8) {
+        // Assumed display gamma of 2.0, matching the NTSC filter
+        let to_rgb = |color: f32| {
+            let color = if color <= 0.0 { 0.0 } else { color.powf(1.1) };
+            (255.95 * color).clamp(0.0, 255.0) as u8
+        };
+        (
+            to_rgb(v.mul_add(1.139_883, y)),
+            to_rgb(v.mul_add(-0.580_622, u.mul_add(-0.394_642, y))),
+            to_rgb(u.mul_add(2.032_062, y)),
+        )
+    }
+
+    fn generate_levels(&mut self) {
+        self.levels.resize(Self::COLORS * Self::CYCLE_SAMPLES, 0.0);
+        for (pixel, levels) in self
+            .levels
+            .chunks_exact_mut(Self::CYCLE_SAMPLES)
+            .enumerate()
+        {
+            for (phase, level) in levels.iter_mut().enumerate() {
+                *level = Self::signal_level(pixel, phase);
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for Pal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Pal")
+            .field("levels_len", &self.levels.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(pal: &mut Pal, buffer: &[u16], frame_number: u32) -> Vec<u8> {
+        let mut output = vec![0x00; 4 * Ppu::SIZE];
+        pal.apply(buffer, frame_number, &mut output);
+        output
+    }
+
+    fn pixel(output: &[u8], x: usize, y: usize) -> [u8; 3] {
+        let index = 4 * (y * Ppu::WIDTH as usize + x);
+        [output[index], output[index + 1], output[index + 2]]
+    }
+
+    fn assert_close(a: [u8; 3], b: [u8; 3]) {
+        assert!(
+            a.iter().zip(&b).all(|(a, b)| a.abs_diff(*b) <= 1),
+            "{a:?} != {b:?}"
+        );
+    }
+
+    #[test]
+    fn decode_color() {
+        let mut pal = Pal::new();
+        let output = apply(&mut pal, &vec![0x16; Ppu::SIZE], 0);
+        let [red, green, blue] = pixel(&output, 10, 0);
+        assert!(red > green && red > blue, "red: ({red}, {green}, {blue})");
+    }
+
+    #[test]
+    fn v_switch_inverts_chroma() {
+        let mut pal = Pal::new();
+        pal.generate_levels();
+        let line = vec![0x16; Ppu::WIDTH as usize];
+        pal.generate_line(&line, 0, false);
+        let signal = pal.signal.clone();
+        pal.generate_line(&line, 0, true);
+        assert_ne!(signal, pal.signal);
+        // Luma is unchanged
+        let luma = |signal: &[f32]| signal[60..72].iter().sum::<f32>();
+        assert!((luma(&signal) - luma(&pal.signal)).abs() < 1e-4);
+
+        // Switched lines decode to the same color, on alternate frames as well
+        let buffer = vec![0x16; Ppu::SIZE];
+        let even = apply(&mut pal, &buffer, 0);
+        let odd = apply(&mut pal, &buffer, 1);
+        assert_close(pixel(&even, 100, 20), pixel(&even, 100, 21));
+        assert_close(pixel(&even, 100, 20), pixel(&odd, 100, 20));
+    }
+
+    #[test]
+    fn delay_line_blends_chroma() {
+        let mut pal = Pal::new();
+        let width = Ppu::WIDTH as usize;
+        let mut buffer = vec![0x16; Ppu::SIZE];
+        // Blue line following red lines
+        buffer[width * 10..width * 11].fill(0x12);
+        let output = apply(&mut pal, &buffer, 0);
+        let red = pixel(&output, 100, 9);
+        let blend = pixel(&output, 100, 10);
+        // Both the blue line and the one after it average their chroma with the line before
+        assert_ne!(red, blend);
+        assert!(blend[2] > red[2] && blend[0] < red[0], "blend: {blend:?}");
+        assert_close(blend, pixel(&output, 100, 11));
+        assert_close(red, pixel(&output, 100, 12));
+    }
+
+    #[test]
+    fn filter_index() {
+        use crate::video::VideoFilter;
+
+        assert_eq!(VideoFilter::from(1), VideoFilter::Ntsc);
+        assert_eq!(VideoFilter::from(2), VideoFilter::Scale2x);
+        assert_eq!(VideoFilter::from(6), VideoFilter::Xbrz4x);
+        assert_eq!(VideoFilter::from(7), VideoFilter::Pal);
+    }
+}
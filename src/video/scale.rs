@@ -0,0 +1,454 @@
+//! CPU pixel-art scalers.
+//!
+//! Each scaler takes a frame of `0x00RRGGBB` pixels and writes an enlarged RGBA frame. Alpha is
+//! left untouched in the output buffer.
+//!
+//! `Scale2x` and `Scale3x` follow the reference algorithm and `xBRZ` follows Zenju's reference
+//! implementation with its default thresholds.
+//!
+//! <https://www.scale2x.it/algorithm>
+//! <https://sourceforge.net/projects/xbrz/>
+
+/// Applies the `Scale2x` (EPX) algorithm.
+pub fn scale2x(src: &[u32], width: usize, height: usize, dst: &mut [u8]) {
+    let out_width = 2 * width;
+    for y in 0..height {
+        for x in 0..width {
+            let p = |dx: isize, dy: isize| pixel(src, width, height, x, y, dx, dy);
+            let (b, d, e, f, h) = (p(0, -1), p(-1, 0), p(0, 0), p(1, 0), p(0, 1));
+            let mut out = [e; 4];
+            if b != h && d != f {
+                if d == b {
+                    out[0] = d;
+                }
+                if b == f {
+                    out[1] = f;
+                }
+                if d == h {
+                    out[2] = d;
+                }
+                if h == f {
+                    out[3] = f;
+                }
+            }
+            for (i, color) in out.into_iter().enumerate() {
+                let idx = (2 * y + i / 2) * out_width + 2 * x + i % 2;
+                put(dst, idx, color);
+            }
+        }
+    }
+}
+
+/// Applies the `Scale3x` algorithm.
+pub fn scale3x(src: &[u32], width: usize, height: usize, dst: &mut [u8]) {
+    let out_width = 3 * width;
+    for y in 0..height {
+        for x in 0..width {
+            let p = |dx: isize, dy: isize| pixel(src, width, height, x, y, dx, dy);
+            let (a, b, c) = (p(-1, -1), p(0, -1), p(1, -1));
+            let (d, e, f) = (p(-1, 0), p(0, 0), p(1, 0));
+            let (g, h, i) = (p(-1, 1), p(0, 1), p(1, 1));
+            let mut out = [e; 9];
+            if b != h && d != f {
+                if d == b {
+                    out[0] = d;
+                }
+                if (d == b && e != c) || (b == f && e != a) {
+                    out[1] = b;
+                }
+                if b == f {
+                    out[2] = f;
+                }
+                if (d == b && e != g) || (d == h && e != a) {
+                    out[3] = d;
+                }
+                if (b == f && e != i) || (h == f && e != c) {
+                    out[5] = f;
+                }
+                if d == h {
+                    out[6] = d;
+                }
+                if (d == h && e != i) || (h == f && e != g) {
+                    out[7] = h;
+                }
+                if h == f {
+                    out[8] = f;
+                }
+            }
+            for (n, color) in out.into_iter().enumerate() {
+                let idx = (3 * y + n / 3) * out_width + 3 * x + n % 3;
+                put(dst, idx, color);
+            }
+        }
+    }
+}
+
+/// Applies the `xBRZ` scaler with a scale factor of 2 to 4.
+///
+/// Every corner shared by four source pixels is first classified by comparing the color gradients
+/// along its two diagonals. Each source pixel is then filled with its own color and blended at
+/// the corners found to lie on an edge, using a line or corner pattern depending on the slope of
+/// the edge.
+pub fn xbrz(scale: usize, src: &[u32], width: usize, height: usize, dst: &mut [u8]) {
+    assert!((2..=4).contains(&scale), "invalid xbrz scale: {scale}");
+    let out_width = scale * width;
+    let kernel = |x: usize, y: usize| {
+        let mut kernel = [0; 16];
+        for (n, color) in kernel.iter_mut().enumerate() {
+            let (dx, dy) = ((n % 4) as isize - 1, (n / 4) as isize - 1);
+            *color = pixel(src, width, height, x, y, dx, dy);
+        }
+        kernel
+    };
+
+    // Blend types of the corners of each pixel in the current row, completed as the corners
+    // below and to the right are classified. Corners above the first row are never blended, as
+    // the clamped row above matches it.
+    let mut corners = vec![BLEND_NONE; width];
+    let mut cell = [0; 16];
+    for y in 0..height {
+        let mut next_row = 0;
+        for x in 0..width {
+            let kernel = kernel(x, y);
+            let [blend_f, blend_g, blend_j, blend_k] = classify_corner(&kernel);
+            let blend = corners[x] | blend_f << BOTTOM_RIGHT;
+            corners[x] = next_row | blend_j << TOP_RIGHT;
+            next_row = blend_k << TOP_LEFT;
+            if x + 1 < width {
+                corners[x + 1] |= blend_g << BOTTOM_LEFT;
+            }
+
+            let cell = &mut cell[..scale * scale];
+            cell.fill(kernel[5]);
+            if blend != BLEND_NONE {
+                let [a, b, c, _, e, f, g, _, i, j, k, ..] = kernel;
+                let mut neighbors = [a, b, c, e, f, g, i, j, k];
+                let mut blend = blend;
+                for rotation in 0..4 {
+                    blend_corner(scale, &neighbors, blend, rotation, cell);
+                    neighbors = ROTATE_90.map(|n| neighbors[n]);
+                    blend = blend.rotate_left(2);
+                }
+            }
+            for (n, &color) in cell.iter().enumerate() {
+                let idx = (scale * y + n / scale) * out_width + scale * x + n % scale;
+                put(dst, idx, color);
+            }
+        }
+    }
+}
+
+// Blend types, stored in two bits per pixel corner.
+const BLEND_NONE: u8 = 0;
+const BLEND_NORMAL: u8 = 1;
+const BLEND_DOMINANT: u8 = 2;
+const TOP_LEFT: u8 = 0;
+const TOP_RIGHT: u8 = 2;
+const BOTTOM_RIGHT: u8 = 4;
+const BOTTOM_LEFT: u8 = 6;
+
+// `xBRZ` default thresholds.
+const EQUAL_COLOR_TOLERANCE: f32 = 30.0;
+const CENTER_DIRECTION_BIAS: f32 = 4.0;
+const DOMINANT_DIRECTION_THRESHOLD: f32 = 3.6;
+const STEEP_DIRECTION_THRESHOLD: f32 = 2.2;
+
+// Neighbor indices of a 3x3 kernel rotated by 90 degrees.
+const ROTATE_90: [usize; 9] = [6, 3, 0, 7, 4, 1, 8, 5, 2];
+
+// Blend patterns as `(row, column, weight, total)` within the bottom-right corner of a cell, where
+// a full weight replaces the color.
+type Pattern = &'static [(usize, usize, u32, u32)];
+
+struct Patterns {
+    shallow: Pattern,
+    steep: Pattern,
+    steep_and_shallow: Pattern,
+    diagonal: Pattern,
+    corner: Pattern,
+}
+
+const PATTERNS: [Patterns; 3] = [
+    Patterns {
+        shallow: &[(1, 0, 1, 4), (1, 1, 3, 4)],
+        steep: &[(0, 1, 1, 4), (1, 1, 3, 4)],
+        steep_and_shallow: &[(1, 0, 1, 4), (0, 1, 1, 4), (1, 1, 5, 6)],
+        diagonal: &[(1, 1, 1, 2)],
+        corner: &[(1, 1, 21, 100)],
+    },
+    Patterns {
+        shallow: &[(2, 0, 1, 4), (1, 2, 1, 4), (2, 1, 3, 4), (2, 2, 1, 1)],
+        steep: &[(0, 2, 1, 4), (2, 1, 1, 4), (1, 2, 3, 4), (2, 2, 1, 1)],
+        steep_and_shallow: &[
+            (2, 0, 1, 4),
+            (0, 2, 1, 4),
+            (2, 1, 3, 4),
+            (1, 2, 3, 4),
+            (2, 2, 1, 1),
+        ],
+        diagonal: &[(1, 2, 1, 8), (2, 1, 1, 8), (2, 2, 7, 8)],
+        corner: &[(2, 2, 45, 100)],
+    },
+    Patterns {
+        shallow: &[
+            (3, 0, 1, 4),
+            (2, 2, 1, 4),
+            (3, 1, 3, 4),
+            (2, 3, 3, 4),
+            (3, 2, 1, 1),
+            (3, 3, 1, 1),
+        ],
+        steep: &[
+            (0, 3, 1, 4),
+            (2, 2, 1, 4),
+            (1, 3, 3, 4),
+            (3, 2, 3, 4),
+            (2, 3, 1, 1),
+            (3, 3, 1, 1),
+        ],
+        steep_and_shallow: &[
+            (3, 1, 3, 4),
+            (1, 3, 3, 4),
+            (3, 0, 1, 4),
+            (0, 3, 1, 4),
+            (2, 2, 1, 3),
+            (3, 3, 1, 1),
+            (3, 2, 1, 1),
+            (2, 3, 1, 1),
+        ],
+        diagonal: &[(3, 2, 1, 2), (2, 3, 1, 2), (3, 3, 1, 1)],
+        corner: &[(3, 3, 68, 100), (3, 2, 9, 100), (2, 3, 9, 100)],
+    },
+];
+
+/// Classifies the corner shared by `F`, `G`, `J` and `K` in a 4x4 kernel from `A` to `P`,
+/// returning the blend type of each of the four pixels at that corner.
+fn classify_corner(kernel: &[u32; 16]) -> [u8; 4] {
+    let [_, b, c, _, e, f, g, h, i, j, k, l, _, n, o, _] = *kernel;
+    let mut blend = [BLEND_NONE; 4];
+    if (f == g && j == k) || (f == j && g == k) {
+        return blend;
+    }
+    let jg = distance(i, f)
+        + distance(f, c)
+        + distance(n, k)
+        + distance(k, h)
+        + CENTER_DIRECTION_BIAS * distance(j, g);
+    let fk = distance(e, j)
+        + distance(j, o)
+        + distance(b, g)
+        + distance(g, l)
+        + CENTER_DIRECTION_BIAS * distance(f, k);
+    if jg < fk {
+        let blend_type = if DOMINANT_DIRECTION_THRESHOLD * jg < fk {
+            BLEND_DOMINANT
+        } else {
+            BLEND_NORMAL
+        };
+        if f != g && f != j {
+            blend[0] = blend_type;
+        }
+        if k != j && k != g {
+            blend[3] = blend_type;
+        }
+    } else if fk < jg {
+        let blend_type = if DOMINANT_DIRECTION_THRESHOLD * fk < jg {
+            BLEND_DOMINANT
+        } else {
+            BLEND_NORMAL
+        };
+        if j != f && j != k {
+            blend[2] = blend_type;
+        }
+        if g != f && g != k {
+            blend[1] = blend_type;
+        }
+    }
+    blend
+}
+
+/// Blends the bottom-right corner of the center pixel `E` of a 3x3 kernel from `A` to `I`, with
+/// the kernel, corner blend types and cell rotated by `rotation` quarter turns.
+fn blend_corner(scale: usize, kernel: &[u32; 9], corners: u8, rotation: usize, cell: &mut [u32]) {
+    let corner = |shift: u8| (corners >> shift) & 0x03;
+    if corner(BOTTOM_RIGHT) == BLEND_NONE {
+        return;
+    }
+    let [_, b, c, d, e, f, g, h, i] = *kernel;
+    let eq = |a: u32, b: u32| distance(a, b) < EQUAL_COLOR_TOLERANCE;
+
+    let line_blend = corner(BOTTOM_RIGHT) >= BLEND_DOMINANT
+        || !(
+            // Avoid blending twice in adjacent rotations, except for 90 degree corners
+            (corner(TOP_RIGHT) != BLEND_NONE && !eq(e, g))
+                || (corner(BOTTOM_LEFT) != BLEND_NONE && !eq(e, c))
+                // Only round the corner of L-shapes
+                || (!eq(e, i) && eq(g, h) && eq(h, i) && eq(i, f) && eq(f, c))
+        );
+    let color = if distance(e, f) <= distance(e, h) {
+        f
+    } else {
+        h
+    };
+
+    let patterns = &PATTERNS[scale - 2];
+    let pattern = if line_blend {
+        let (fg, hc) = (distance(f, g), distance(h, c));
+        let shallow = STEEP_DIRECTION_THRESHOLD * fg <= hc && e != g && d != g;
+        let steep = STEEP_DIRECTION_THRESHOLD * hc <= fg && e != c && b != c;
+        match (shallow, steep) {
+            (true, true) => patterns.steep_and_shallow,
+            (true, false) => patterns.shallow,
+            (false, true) => patterns.steep,
+            (false, false) => patterns.diagonal,
+        }
+    } else {
+        patterns.corner
+    };
+    for &(row, col, weight, total) in pattern {
+        // Undo the rotation to find the position in the cell
+        let (mut row, mut col) = (row, col);
+        for _ in 0..rotation {
+            (row, col) = (scale - 1 - col, row);
+        }
+        let back = &mut cell[row * scale + col];
+        *back = if weight == total {
+            color
+        } else {
+            blend(&[(color, weight), (*back, total - weight)])
+        };
+    }
+}
+
+/// Returns the pixel at an offset from `(x, y)`, clamped to the frame edges.
+#[inline]
+fn pixel(
+    src: &[u32],
+    width: usize,
+    height: usize,
+    x: usize,
+    y: usize,
+    dx: isize,
+    dy: isize,
+) -> u32 {
+    let x = x.saturating_add_signed(dx).min(width - 1);
+    let y = y.saturating_add_signed(dy).min(height - 1);
+    src[y * width + x]
+}
+
+#[inline]
+fn put(dst: &mut [u8], idx: usize, color: u32) {
+    let [_, red, green, blue] = color.to_be_bytes();
+    let idx = 4 * idx;
+    dst[idx] = red;
+    dst[idx + 1] = green;
+    dst[idx + 2] = blue;
+}
+
+/// Weighted average of colors.
+fn blend(colors: &[(u32, u32)]) -> u32 {
+    let (mut red, mut green, mut blue, mut total) = (0, 0, 0, 0);
+    for &(color, weight) in colors {
+        red += ((color >> 16) & 0xFF) * weight;
+        green += ((color >> 8) & 0xFF) * weight;
+        blue += (color & 0xFF) * weight;
+        total += weight;
+    }
+    ((red / total) << 16) | ((green / total) << 8) | (blue / total)
+}
+
+/// Distance between two colors in YCbCr space, using the ITU-R BT.2020 luma coefficients.
+fn distance(a: u32, b: u32) -> f32 {
+    const K_B: f32 = 0.0593;
+    const K_R: f32 = 0.2627;
+    const K_G: f32 = 1.0 - K_B - K_R;
+    let diff = |shift: u32| ((a >> shift) & 0xFF) as f32 - ((b >> shift) & 0xFF) as f32;
+    let (red, green, blue) = (diff(16), diff(8), diff(0));
+    let y = K_R.mul_add(red, K_G.mul_add(green, K_B * blue));
+    let cb = 0.5 / (1.0 - K_B) * (blue - y);
+    let cr = 0.5 / (1.0 - K_R) * (red - y);
+    y.mul_add(y, cb.mul_add(cb, cr * cr)).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const W: u32 = 0x00FF_FFFF;
+    const K: u32 = 0x0000_0000;
+
+    fn output(width: usize, height: usize, dst: &[u8]) -> Vec<u32> {
+        (0..width * height)
+            .map(|i| u32::from_be_bytes([0, dst[4 * i], dst[4 * i + 1], dst[4 * i + 2]]))
+            .collect()
+    }
+
+    #[test]
+    fn scale2x_diagonal() {
+        #[rustfmt::skip]
+        let src = [
+            W, K,
+            K, K,
+        ];
+        let mut dst = vec![0; 4 * 16];
+        scale2x(&src, 2, 2, &mut dst);
+        let out = output(4, 4, &dst);
+        // The inner corner of the white pixel is filled in by its black neighbors
+        assert_eq!(&out[0..2], &[W, W]);
+        assert_eq!(&out[4..6], &[W, K]);
+    }
+
+    #[test]
+    fn xbrz_rounds_single_pixel() {
+        #[rustfmt::skip]
+        let src = [
+            K, K, K,
+            K, W, K,
+            K, K, K,
+        ];
+        let mut dst = vec![0; 4 * 9 * 16];
+        xbrz(4, &src, 3, 3, &mut dst);
+        let out = output(12, 12, &dst);
+        let cell = |row: usize, col: usize| out[(4 + row) * 12 + 4 + col];
+        // Each corner is rounded off, leaving the center untouched
+        for (row, col) in [(0, 0), (0, 3), (3, 0), (3, 3)] {
+            assert_eq!(cell(row, col), 0x0051_5151, "corner ({row}, {col})");
+        }
+        for (row, col) in [(0, 1), (1, 0), (2, 3), (3, 2)] {
+            assert_eq!(cell(row, col), 0x00E8_E8E8, "edge ({row}, {col})");
+        }
+        assert_eq!(cell(1, 1), W);
+        assert_eq!(cell(2, 2), W);
+    }
+
+    #[test]
+    fn xbrz_smooths_diagonal() {
+        // Staircase edge between a white bottom-left and black top-right
+        let src = (0..36)
+            .map(|n| if n % 6 <= n / 6 { W } else { K })
+            .collect::<Vec<_>>();
+        let mut dst = vec![0; 4 * 36 * 4];
+        xbrz(2, &src, 6, 6, &mut dst);
+        let out = output(12, 12, &dst);
+        // The outer corner of each step is blended halfway towards black
+        for step in 1..5 {
+            let (x, y) = (2 * step + 1, 2 * step);
+            assert_eq!(out[y * 12 + x], 0x007F_7F7F, "step {step}");
+        }
+    }
+
+    #[test]
+    fn flat_color_unchanged() {
+        let src = [0x0012_3456; 9];
+        for scale in 2..=4 {
+            let mut dst = vec![0; 4 * 9 * scale * scale];
+            xbrz(scale, &src, 3, 3, &mut dst);
+            assert!(output(3 * scale, 3 * scale, &dst)
+                .iter()
+                .all(|&c| c == 0x0012_3456));
+        }
+        let mut dst = vec![0; 4 * 9 * 9];
+        scale3x(&src, 3, 3, &mut dst);
+        assert!(output(9, 9, &dst).iter().all(|&c| c == 0x0012_3456));
+    }
+}
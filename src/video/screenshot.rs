@@ -0,0 +1,77 @@
+//! PNG encoding of rendered frames.
+
+use crate::NesResult;
+use anyhow::Context;
+use std::io::Write;
+
+/// Text metadata keywords embedded in screenshots.
+pub const KEYWORD_ROM: &str = "ROM";
+pub const KEYWORD_FRAME: &str = "Frame";
+pub const KEYWORD_REGION: &str = "Region";
+
+/// Encodes an RGBA frame as a PNG image, with optional `(keyword, text)` pairs stored as `tEXt`
+/// chunks.
+///
+/// # Errors
+///
+/// If the frame dimensions don't match the size of `rgba` or writing fails, then an error is
+/// returned.
+pub fn encode_png<W: Write>(
+    writer: W,
+    width: u32,
+    height: u32,
+    rgba: &[u8],
+    metadata: &[(&str, String)],
+) -> NesResult<()> {
+    let mut encoder = png::Encoder::new(writer, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    for (keyword, text) in metadata {
+        encoder
+            .add_text_chunk((*keyword).to_string(), text.clone())
+            .with_context(|| format!("invalid png text chunk: {keyword}"))?;
+    }
+    let mut writer = encoder
+        .write_header()
+        .context("failed to write png header")?;
+    writer
+        .write_image_data(rgba)
+        .context("failed to write png data")?;
+    writer.finish().context("failed to finish png")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_with_metadata() {
+        let rgba = [0x10, 0x20, 0x30, 0xFF].repeat(4);
+        let mut png = Vec::new();
+        encode_png(
+            &mut png,
+            2,
+            2,
+            &rgba,
+            &[(KEYWORD_ROM, "test.nes".to_string())],
+        )
+        .expect("valid png");
+
+        let decoder = png::Decoder::new(png.as_slice());
+        let mut reader = decoder.read_info().expect("png header");
+        let info = reader.info();
+        assert_eq!((info.width, info.height), (2, 2));
+        assert_eq!(info.uncompressed_latin1_text[0].keyword, KEYWORD_ROM);
+        assert_eq!(info.uncompressed_latin1_text[0].text, "test.nes");
+        let mut buf = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut buf).expect("png frame");
+        assert_eq!(buf, rgba);
+    }
+
+    #[test]
+    fn invalid_size() {
+        let mut png = Vec::new();
+        assert!(encode_png(&mut png, 4, 4, &[0x00; 4], &[]).is_err());
+    }
+}
@@ -1,10 +1,8 @@
 use anyhow::Context;
 use std::{
-    collections::hash_map::DefaultHasher,
     env,
     ffi::OsStr,
     fs::File,
-    hash::{Hash, Hasher},
     io::{BufWriter, Write},
     path::{Path, PathBuf},
 };
@@ -18,7 +16,7 @@ fn main() -> NesResult<()> {
     let path = opt
         .path
         .unwrap_or_else(|| env::current_dir().unwrap_or_default());
-    let header = "# Fields: Hash, Region, Board, PCB, Chip, Mapper, PrgRomSize, ChrRomSize, ChrRamSize, PrgRamSize, Battery, Mirroring, SubMapper, Title";
+    let header = "# Fields: Key, Region, Board, PCB, Chip, Mapper, PrgRomSize, ChrRomSize, ChrRamSize, PrgRamSize, Battery, Mirroring, SubMapper, Title";
     if path.is_dir() {
         let mut db_file =
             BufWriter::new(File::create(GAME_DB).context("failed to open game_database.txt")?);
@@ -29,16 +27,16 @@ fn main() -> NesResult<()> {
             .filter(|f| f.path().extension() == Some(OsStr::new("nes")))
             .map(|f| f.path())
             .collect();
-        let mut boards: Vec<(u64, String)> =
+        let mut boards: Vec<(u32, String)> =
             paths.iter().map(get_info).filter_map(Result::ok).collect();
         boards.sort_by_key(|board| board.0);
         writeln!(db_file, "{header}")?;
-        let mut last_hash = 0;
+        let mut last_crc32 = 0;
         for board in &boards {
-            if board.0 != last_hash {
+            if board.0 != last_crc32 {
                 writeln!(db_file, "{}", board.1)?;
             }
-            last_hash = board.0;
+            last_crc32 = board.0;
         }
     } else if path.is_file() {
         todo!("adding individual files is not yet supported");
@@ -46,13 +44,11 @@ fn main() -> NesResult<()> {
     Ok(())
 }
 
-fn get_info<P: AsRef<Path>>(path: P) -> NesResult<(u64, String)> {
+fn get_info<P: AsRef<Path>>(path: P) -> NesResult<(u32, String)> {
     let path = path.as_ref();
     let cart = Cart::from_path(path, RamState::default())?;
-    let mut hasher = DefaultHasher::new();
-    cart.prg_rom().hash(&mut hasher);
     let filename = path.file_name().unwrap_or_default();
-    let hash = hasher.finish();
+    let hashes = cart.rom_hashes();
     let region = match filename.to_str() {
         Some(filename) => {
             if filename.contains("Europe") || filename.contains("PAL") {
@@ -69,15 +65,15 @@ fn get_info<P: AsRef<Path>>(path: P) -> NesResult<(u64, String)> {
 
     let chr_rom_banks = cart.chr_rom().len() / (8 * 1024);
     let chr_ram_banks = cart.chr_ram().len() / (8 * 1024);
-    let prg_rom_banks = cart.prg_ram().len() / (16 * 1024);
+    let prg_rom_banks = cart.prg_rom().len() / (16 * 1024);
     let prg_ram_banks = cart.prg_ram().len() / (16 * 1024);
     let mirroring = cart.mirroring();
 
     Ok((
-        hash,
+        hashes.crc32,
         format!(
-            "{},{},{},{},{},{},{},{},{},{},{},{:?},{},{:?}",
-            hash,
+            "crc32:{},{},{},{},{},{},{},{},{},{},{},{:?},{},{:?}",
+            hashes.crc32_hex(),
             region,
             board,
             pcb,
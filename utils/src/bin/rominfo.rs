@@ -0,0 +1,536 @@
+use anyhow::{bail, Context};
+use serde::Serialize;
+use std::{
+    collections::HashSet,
+    env, fmt, fs, io,
+    path::{Path, PathBuf},
+};
+use structopt::StructOpt;
+use tetanes::{
+    cart::{
+        archive::{Archive, ROM_EXTENSIONS},
+        fds::DiskImage,
+        game_db::GameDb,
+        hash::RomHashes,
+        rom_db::RomDb,
+        unif::Unif,
+        Cart, NesHeader,
+    },
+    common::{NesRegion, Regional},
+    mem::RamState,
+    ppu::Mirroring,
+    NesResult,
+};
+
+const ARCHIVE_EXTENSIONS: [&str; 3] = ["gz", "zip", "7z"];
+const PRG_BANK_SIZE: usize = 16 * 1024;
+const CHR_BANK_SIZE: usize = 8 * 1024;
+const TRAINER_SIZE: usize = 512;
+
+fn main() -> NesResult<()> {
+    let opt = Opt::from_args();
+    let rom_dbs = opt
+        .dat
+        .iter()
+        .map(RomDb::from_path)
+        .collect::<NesResult<Vec<_>>>()?;
+    let mut paths = vec![];
+    if opt.paths.is_empty() {
+        collect_roms(&env::current_dir().unwrap_or_default(), &mut paths)?;
+    }
+    for path in &opt.paths {
+        collect_roms(path, &mut paths)?;
+    }
+    paths.sort();
+
+    let reports = paths
+        .iter()
+        .map(|path| Report::new(path, &rom_dbs))
+        .collect::<Vec<_>>();
+    if opt.json {
+        serde_json::to_writer_pretty(io::stdout().lock(), &reports)
+            .context("failed to write json")?;
+        println!();
+    } else {
+        for report in &reports {
+            println!("{report}");
+        }
+    }
+    Ok(())
+}
+
+// Files are always inspected, directories are searched recursively for ROMs and archives.
+fn collect_roms(path: &Path, paths: &mut Vec<PathBuf>) -> NesResult<()> {
+    if !path.is_dir() {
+        paths.push(path.to_path_buf());
+        return Ok(());
+    }
+    let entries = path
+        .read_dir()
+        .with_context(|| format!("unable to read directory {path:?}"))?;
+    for entry in entries.filter_map(Result::ok) {
+        let path = entry.path();
+        if path.is_dir() {
+            collect_roms(&path, paths)?;
+        } else if path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| {
+                ROM_EXTENSIONS
+                    .iter()
+                    .chain(ARCHIVE_EXTENSIONS.iter())
+                    .any(|rom_ext| ext.eq_ignore_ascii_case(rom_ext))
+            })
+        {
+            paths.push(path);
+        }
+    }
+    Ok(())
+}
+
+#[derive(Default, Debug, Serialize)]
+#[must_use]
+struct Report {
+    path: PathBuf,
+    archive_entry: Option<String>,
+    format: Option<&'static str>,
+    header: Option<HeaderReport>,
+    prg_hashes: Option<Hashes>,
+    rom_hashes: Option<Hashes>,
+    game_db: Option<GameDbMatch>,
+    rom_db: Vec<RomDbMatch>,
+    mapper: Option<MapperReport>,
+    region: Option<NesRegion>,
+    prg_rom: Option<BankStats>,
+    chr_rom: Option<BankStats>,
+    error: Option<String>,
+}
+
+impl Report {
+    fn new(path: &Path, rom_dbs: &[RomDb]) -> Self {
+        let mut report = Self {
+            path: path.to_path_buf(),
+            ..Self::default()
+        };
+        if let Err(err) = report.inspect(rom_dbs) {
+            report.error = Some(format!("{err:#}"));
+        }
+        report
+    }
+
+    fn inspect(&mut self, rom_dbs: &[RomDb]) -> NesResult<()> {
+        let mut data =
+            fs::read(&self.path).with_context(|| format!("failed to read {:?}", self.path))?;
+        if Archive::is_archive(&data) {
+            let (entry, rom) = Archive::new(data)?.read_rom()?;
+            self.archive_entry = Some(entry);
+            data = rom;
+        }
+
+        let (prg_rom, chr_rom) = if Unif::is_unif(&data) {
+            self.format = Some("UNIF");
+            let unif = Unif::load(&mut data.as_slice())?;
+            self.mapper = Some(MapperReport {
+                number: unif.mapper_num().map(|(mapper_num, _)| mapper_num),
+                submapper: unif.mapper_num().map(|(_, submapper_num)| submapper_num),
+                board: unif.board().to_string(),
+                supported: false,
+                error: None,
+            });
+            (unif.prg_rom().to_vec(), unif.chr_rom().to_vec())
+        } else if DiskImage::is_disk_image(&data) {
+            self.format = Some("FDS");
+            let image = DiskImage::load(&data)?;
+            self.mapper = Some(MapperReport {
+                number: Some(20),
+                submapper: None,
+                board: format!("FDS, {} disk side(s)", image.sides().len()),
+                // Disk images load with a user-supplied BIOS
+                supported: true,
+                error: None,
+            });
+            self.region = Some(NesRegion::Ntsc);
+            (image.sides().concat(), vec![])
+        } else {
+            let header = NesHeader::load(&mut data.as_slice())?;
+            self.format = Some(if header.version == 2 {
+                "NES 2.0"
+            } else {
+                "iNES"
+            });
+            let mut offset = NesHeader::SIZE;
+            if header.has_trainer() {
+                offset += TRAINER_SIZE;
+            }
+            let prg_rom_size = header.prg_rom_size();
+            let Some(prg_rom) = offset
+                .checked_add(prg_rom_size)
+                .and_then(|end| data.get(offset..end))
+            else {
+                bail!("invalid or truncated prg-rom: expected {prg_rom_size} bytes");
+            };
+            offset += prg_rom.len();
+            let chr_rom_size = header.chr_rom_size();
+            let Some(chr_rom) = offset
+                .checked_add(chr_rom_size)
+                .and_then(|end| data.get(offset..end))
+            else {
+                bail!("invalid or truncated chr-rom: expected {chr_rom_size} bytes");
+            };
+            let mut header_bytes = [0x00; NesHeader::SIZE];
+            header_bytes.copy_from_slice(&data[..NesHeader::SIZE]);
+            let repairs = NesHeader::repair(&mut header_bytes);
+
+            let game = GameDb::builtin().lookup(&RomHashes::from_chunks(&[prg_rom, chr_rom]));
+            if let Some(game) = game {
+                let overrides = if header.version == 1 {
+                    let mut corrected = header;
+                    corrected
+                        .apply_game_info(game)
+                        .iter()
+                        .map(|db_override| format!("{db_override:?}"))
+                        .collect()
+                } else {
+                    vec![]
+                };
+                self.game_db = Some(GameDbMatch {
+                    title: game.title.clone(),
+                    region: game.region,
+                    mapper: game.mapper_num,
+                    submapper: game.submapper_num,
+                    overrides,
+                });
+            }
+            self.region = header
+                .region()
+                .or_else(|| game.and_then(|game| game.region));
+            self.header = Some(HeaderReport::new(&header, repairs));
+            self.mapper = Some(MapperReport {
+                number: Some(header.mapper_num),
+                submapper: Some(header.submapper_num),
+                board: header.mapper_board().to_string(),
+                supported: false,
+                error: None,
+            });
+            (prg_rom.to_vec(), chr_rom.to_vec())
+        };
+
+        // Mapper support is checked by loading the cart, which includes game database overrides
+        if self.format != Some("FDS") {
+            let name = self.path.to_string_lossy();
+            match Cart::from_rom(name, &mut data.as_slice(), RamState::default()) {
+                Ok(cart) => {
+                    self.region = Some(cart.region());
+                    if let Some(mapper) = &mut self.mapper {
+                        mapper.number = Some(cart.mapper_num());
+                        mapper.submapper = Some(cart.submapper_num());
+                        mapper.board = cart.mapper_board().to_string();
+                        mapper.supported = true;
+                    }
+                }
+                Err(err) => {
+                    if let Some(mapper) = &mut self.mapper {
+                        mapper.error = Some(format!("{err:#}"));
+                    }
+                }
+            }
+        }
+
+        let rom_hashes = RomHashes::from_chunks(&[&prg_rom, &chr_rom]);
+        self.rom_db = rom_dbs
+            .iter()
+            .filter_map(|db| db.find(&rom_hashes))
+            .map(|rom| RomDbMatch {
+                title: rom.title.clone(),
+                board: rom.board.clone(),
+                mapper: rom.mapper_num,
+                mapper_mismatch: rom.mapper_num.is_some_and(|mapper_num| {
+                    self.mapper
+                        .as_ref()
+                        .and_then(|mapper| mapper.number)
+                        .is_some_and(|number| number != mapper_num)
+                }),
+                region: rom.region,
+            })
+            .collect();
+        self.prg_hashes = Some(Hashes::from(RomHashes::new(&prg_rom)));
+        self.rom_hashes = Some(Hashes::from(rom_hashes));
+        self.prg_rom = Some(BankStats::new(&prg_rom, PRG_BANK_SIZE));
+        if !chr_rom.is_empty() {
+            self.chr_rom = Some(BankStats::new(&chr_rom, CHR_BANK_SIZE));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.path.display())?;
+        if let Some(entry) = &self.archive_entry {
+            writeln!(f, "  Archive entry: {entry}")?;
+        }
+        if let Some(format) = self.format {
+            writeln!(f, "  Format:        {format}")?;
+        }
+        if let Some(header) = &self.header {
+            write!(f, "{header}")?;
+        }
+        if let Some(mapper) = &self.mapper {
+            write!(f, "  Mapper:        {}", mapper.board)?;
+            if let Some(submapper) = mapper.submapper.filter(|submapper| *submapper > 0) {
+                write!(f, ", submapper {submapper}")?;
+            }
+            match &mapper.error {
+                Some(err) => writeln!(f, " (unsupported: {err})")?,
+                None if mapper.supported => writeln!(f, " (supported)")?,
+                None => writeln!(f, " (unsupported)")?,
+            }
+        }
+        if let Some(region) = self.region {
+            writeln!(f, "  Region:        {region:?}")?;
+        }
+        if let Some(hashes) = &self.prg_hashes {
+            writeln!(f, "  PRG hashes:    {hashes}")?;
+        }
+        if let Some(hashes) = &self.rom_hashes {
+            writeln!(f, "  ROM hashes:    {hashes}")?;
+        }
+        if let Some(stats) = &self.prg_rom {
+            writeln!(f, "  PRG-ROM:       {stats}")?;
+        }
+        if let Some(stats) = &self.chr_rom {
+            writeln!(f, "  CHR-ROM:       {stats}")?;
+        }
+        match &self.game_db {
+            Some(game) => {
+                writeln!(f, "  Game DB:       {}", game.title)?;
+                for db_override in &game.overrides {
+                    writeln!(f, "    Header disagrees: {db_override}")?;
+                }
+            }
+            None if self.header.is_some() => writeln!(f, "  Game DB:       no match")?,
+            None => (),
+        }
+        for rom in &self.rom_db {
+            write!(f, "  ROM DB:        {}", rom.title)?;
+            if let Some(board) = &rom.board {
+                write!(f, ", {board}")?;
+            }
+            if let Some(mapper) = rom.mapper.filter(|_| rom.mapper_mismatch) {
+                write!(f, " (mapper {mapper} disagrees)")?;
+            }
+            writeln!(f)?;
+        }
+        if let Some(err) = &self.error {
+            writeln!(f, "  Error:         {err}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[must_use]
+struct HeaderReport {
+    version: u8,
+    mapper: u16,
+    submapper: Option<u8>,
+    prg_rom_size: usize,
+    chr_rom_size: usize,
+    prg_ram_size: Option<usize>,
+    prg_nvram_size: Option<usize>,
+    chr_ram_size: Option<usize>,
+    chr_nvram_size: Option<usize>,
+    mirroring: Mirroring,
+    battery: bool,
+    trainer: bool,
+    console_type: String,
+    timing: Option<NesRegion>,
+    vs_hardware: Option<String>,
+    misc_roms: Option<u8>,
+    expansion_device: Option<u8>,
+    repairs: Vec<String>,
+}
+
+impl HeaderReport {
+    fn new(header: &NesHeader, repairs: Vec<String>) -> Self {
+        // Fields only stored by NES 2.0 headers
+        let nes2 = header.version == 2;
+        Self {
+            version: header.version,
+            mapper: header.mapper_num,
+            submapper: nes2.then_some(header.submapper_num),
+            prg_rom_size: header.prg_rom_size(),
+            chr_rom_size: header.chr_rom_size(),
+            prg_ram_size: nes2.then_some(header.prg_ram_size()),
+            prg_nvram_size: nes2.then_some(header.prg_nvram_size()),
+            chr_ram_size: nes2.then_some(header.chr_ram_size()),
+            chr_nvram_size: nes2.then_some(header.chr_nvram_size()),
+            mirroring: header.mirroring(),
+            battery: header.flags & 0x02 == 0x02,
+            trainer: header.has_trainer(),
+            console_type: format!("{:?}", header.console_type()),
+            timing: header.region(),
+            vs_hardware: header.vs_hardware().map(|hardware| format!("{hardware:?}")),
+            misc_roms: nes2.then_some(header.misc_roms),
+            expansion_device: nes2.then_some(header.expansion_device),
+            repairs,
+        }
+    }
+}
+
+impl fmt::Display for HeaderReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "  Header:        mapper {}, PRG-ROM {}K, CHR-ROM {}K, {:?} mirroring, battery: {}, trainer: {}, console: {}",
+            self.mapper,
+            self.prg_rom_size / 1024,
+            self.chr_rom_size / 1024,
+            self.mirroring,
+            self.battery,
+            self.trainer,
+            self.console_type,
+        )?;
+        if self.version == 2 {
+            writeln!(
+                f,
+                "  NES 2.0:       submapper {}, PRG-RAM {}B, PRG-NVRAM {}B, CHR-RAM {}B, CHR-NVRAM {}B, timing: {}, misc ROMs: {}, expansion: {:#04X}",
+                self.submapper.unwrap_or_default(),
+                self.prg_ram_size.unwrap_or_default(),
+                self.prg_nvram_size.unwrap_or_default(),
+                self.chr_ram_size.unwrap_or_default(),
+                self.chr_nvram_size.unwrap_or_default(),
+                self.timing.map_or_else(|| "multi-region".to_string(), |timing| format!("{timing:?}")),
+                self.misc_roms.unwrap_or_default(),
+                self.expansion_device.unwrap_or_default(),
+            )?;
+        }
+        if let Some(hardware) = &self.vs_hardware {
+            writeln!(f, "  Vs. hardware:  {hardware}")?;
+        }
+        for repair in &self.repairs {
+            writeln!(f, "  Repaired:      {repair}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[must_use]
+struct Hashes {
+    crc32: String,
+    md5: String,
+    sha1: String,
+}
+
+impl From<RomHashes> for Hashes {
+    fn from(hashes: RomHashes) -> Self {
+        Self {
+            crc32: hashes.crc32_hex(),
+            md5: hashes.md5_hex(),
+            sha1: hashes.sha1_hex(),
+        }
+    }
+}
+
+impl fmt::Display for Hashes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "CRC32 {}, MD5 {}, SHA-1 {}",
+            self.crc32, self.md5, self.sha1
+        )
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[must_use]
+struct GameDbMatch {
+    title: String,
+    region: Option<NesRegion>,
+    mapper: u16,
+    submapper: u8,
+    overrides: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[must_use]
+struct RomDbMatch {
+    title: String,
+    board: Option<String>,
+    mapper: Option<u16>,
+    mapper_mismatch: bool,
+    region: Option<NesRegion>,
+}
+
+#[derive(Debug, Serialize)]
+#[must_use]
+struct MapperReport {
+    number: Option<u16>,
+    submapper: Option<u8>,
+    board: String,
+    supported: bool,
+    error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[must_use]
+struct BankStats {
+    size: usize,
+    bank_size: usize,
+    banks: usize,
+    unique_banks: usize,
+    // Banks filled with a single value, usually padding from overdumps
+    blank_banks: usize,
+}
+
+impl BankStats {
+    fn new(data: &[u8], bank_size: usize) -> Self {
+        let banks = data.chunks(bank_size).collect::<Vec<_>>();
+        Self {
+            size: data.len(),
+            bank_size,
+            banks: banks.len(),
+            unique_banks: banks.iter().collect::<HashSet<_>>().len(),
+            blank_banks: banks
+                .iter()
+                .filter(|bank| bank.iter().all(|byte| *byte == bank[0]))
+                .count(),
+        }
+    }
+}
+
+impl fmt::Display for BankStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}K, {} x {}K banks, {} unique, {} blank",
+            self.size / 1024,
+            self.banks,
+            self.bank_size / 1024,
+            self.unique_banks,
+            self.blank_banks
+        )
+    }
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(
+    name = "tetanes-rominfo",
+    about = "Print a report of the header, hashes, database matches and mapper support of NES ROMs."
+)]
+#[must_use]
+struct Opt {
+    #[structopt(
+        help = "NES ROMs, archives, or directories to search recursively. [default: current directory]"
+    )]
+    paths: Vec<PathBuf>,
+    #[structopt(
+        short,
+        long,
+        help = "No-Intro DAT or NesCartDB XML files to identify ROMs with."
+    )]
+    dat: Vec<PathBuf>,
+    #[structopt(short, long, help = "Print reports as JSON.")]
+    json: bool,
+}